@@ -9,15 +9,75 @@ use anyhow::{Context, Result};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use near_api::{Contract, NearGas, NearToken, NetworkConfig, RPCEndpoint, Signer};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use std::sync::Arc;
-use tracing::{debug, info, warn};
+use tracing::{debug, info};
 
 /// Payment input for submitting to the contract
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentInput {
     pub recipient: String,
     pub amount: String,
+    /// Passed through as the memo of the token transfer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    /// Free-form reference for the payment, e.g. an invoice ID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// NFT sent to the recipient (NFT lists only, with amount "1")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nft_token_id: Option<String>,
+    /// External chain address a PoA token payment is withdrawn to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<Destination>,
+    /// Token of this payment if it differs from the list's, making it a mixed-token list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<String>,
+}
+
+/// One recipient of a weighted split
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedShare {
+    pub recipient: String,
+    /// Share of the total in basis points (1/100 of a percent)
+    pub weight_bps: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memo: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+/// Share that receives the rounding remainder of a split
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum SplitRemainder {
+    First,
+    #[default]
+    Last,
+}
+
+/// Total split across recipients by weight, passed to `submit_weighted_list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightedSplit {
+    pub total: String,
+    /// Weights must add up to 10,000 basis points
+    pub shares: Vec<WeightedShare>,
+    #[serde(default)]
+    pub remainder: SplitRemainder,
+}
+
+/// Terms a weighted list was computed from, the weights in the order of its records
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SplitTerms {
+    pub total: String,
+    pub weights_bps: Vec<u32>,
+    pub remainder: SplitRemainder,
+}
+
+/// Address on an external chain, e.g. `{ "chain": "Bitcoin", "address": "bc1q..." }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Destination {
+    pub chain: String,
+    pub address: String,
 }
 
 /// Payment record returned from the contract
@@ -25,6 +85,25 @@ pub struct PaymentInput {
 pub struct PaymentRecord {
     pub recipient: String,
     pub amount: String,
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub nft_token_id: Option<String>,
+    #[serde(default)]
+    pub destination: Option<Destination>,
+    #[serde(default)]
+    pub token_id: Option<String>,
+    /// Positions in the submitted list of the payments merged into this record
+    #[serde(default)]
+    pub merged_lines: Option<Vec<u32>>,
+    /// Amount sent to the recipient when tax is withheld, `amount` being the gross
+    #[serde(default)]
+    pub net_amount: Option<String>,
+    /// Tax withheld from `amount` for the list's withholding account
+    #[serde(default)]
+    pub withheld_amount: Option<String>,
     pub status: PaymentStatus,
 }
 
@@ -38,6 +117,169 @@ pub enum PaymentStatus {
     Paid {
         block_height: u64,
     },
+    /// Unclaimed payment returned to the submitter after the claim deadline
+    Reclaimed {
+        block_height: u64,
+    },
+    /// Recipient was screened out at payout; the payment is refundable to the submitter
+    Blocked {
+        block_height: u64,
+    },
+}
+
+/// How recipients receive their payments
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum PayoutMode {
+    /// Payments are pushed to recipients via `payout_batch`
+    #[default]
+    Push,
+    /// Recipients pull their own payments via `claim` until the claim deadline
+    Claim,
+}
+
+/// How NEAR Intents ("nep141:") tokens reach recipients
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum IntentsDelivery {
+    /// `ft_withdraw` off the intents ledger
+    #[default]
+    Withdraw,
+    /// `mt_transfer` to the recipient's intents account
+    Transfer,
+}
+
+/// Optional list settings passed through to `submit_list`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payout_mode: Option<PayoutMode>,
+    /// Block timestamp (ns) after which unclaimed payments return to the submitter
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_deadline: Option<u64>,
+    /// Descriptive information shown to approvers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ListMetadata>,
+    /// Register unregistered FT recipients with the token contract before transferring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub register_recipients: Option<bool>,
+    /// Delivery of NEAR Intents tokens, `Transfer` keeps payments on the intents ledger
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub intents_delivery: Option<IntentsDelivery>,
+    /// Whether payments to the same recipient are kept, rejected or merged
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicates: Option<DuplicatePolicy>,
+    /// NEAR (yocto) paid to the caller of `payout_batch` per payment it processes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keeper_fee: Option<String>,
+    /// Accounts allowed to call `payout_batch`; anyone if unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operators: Option<Vec<String>>,
+    /// Gas to attach to each payment call per token, instead of the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attached_gas: Option<Vec<TokenGas>>,
+    /// Withhold a share of each payment for a tax escrow account (push mode only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub withholding: Option<WithholdingRule>,
+    /// Unit of the payment amounts; `UsdCents` lists are converted with `lock_price`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denomination: Option<Denomination>,
+}
+
+/// Unit in which the amounts of a list are given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Denomination {
+    /// Smallest units of the list's token
+    #[default]
+    Token,
+    /// US cents, converted to the token at the price oracle's rate
+    UsdCents,
+}
+
+/// USD value of one smallest token unit: multiplier / 10^decimals
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Price {
+    pub multiplier: String,
+    pub decimals: u8,
+}
+
+/// Price a USD-denominated list was converted at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockedPrice {
+    pub price: Price,
+    /// Oracle timestamp (ns) of the price
+    pub price_timestamp: String,
+    /// Block timestamp (ns) of the lock
+    pub locked_at: u64,
+    /// Token amount required to fund the list
+    pub total: String,
+}
+
+/// USD amounts of a list and the price they were converted at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FiatDenomination {
+    /// Oracle asset ID of the list's token
+    pub asset_id: String,
+    /// US cents of each record, in the order of `payments`
+    pub usd_cents: Vec<String>,
+    /// Set once the price is locked; until then record amounts are US cents
+    #[serde(default)]
+    pub locked: Option<LockedPrice>,
+}
+
+/// Share of each payment withheld for a tax escrow account
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithholdingRule {
+    /// Basis points (1/100 of a percent) of each payment, between 1 and 9999
+    pub rate_bps: u32,
+    pub account: String,
+}
+
+/// Tax withheld from the payments of a list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withholding {
+    pub rate_bps: u32,
+    pub account: String,
+    /// Withheld from the payments paid so far
+    pub withheld: String,
+    /// Block height at which the withheld total was sent to `account`
+    #[serde(default)]
+    pub remitted_at: Option<u64>,
+}
+
+/// Gas (as a string) to attach to each payment call of a token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenGas {
+    pub token_id: String,
+    pub gas: String,
+}
+
+/// What the contract does with payments to the same recipient
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub enum DuplicatePolicy {
+    /// Keep every payment as its own record
+    #[default]
+    Allow,
+    /// Reject the list, listing the duplicates
+    Reject,
+    /// Merge the duplicates of each recipient into one record
+    Merge,
+}
+
+/// Descriptive list information (title, description, document reference, category)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ListMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// URL or hash of an off-chain document backing the list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+    /// Free-form category, e.g. "payroll" or "grants"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub category: Option<String>,
 }
 
 /// List status
@@ -45,6 +287,7 @@ pub enum PaymentStatus {
 #[serde(rename_all = "PascalCase")]
 pub enum ListStatus {
     Pending,
+    PartiallyFunded,
     Approved,
     Rejected,
 }
@@ -57,13 +300,112 @@ pub struct PaymentList {
     pub status: ListStatus,
     pub payments: Vec<PaymentRecord>,
     pub created_at: u64,
+    /// Payroll template this list was materialised from, if any
+    #[serde(default)]
+    pub template_id: Option<String>,
+    #[serde(default)]
+    pub payout_mode: PayoutMode,
+    #[serde(default)]
+    pub claim_deadline: Option<u64>,
+    /// Set for Merkle lists, which store only the tree root instead of `payments`
+    #[serde(default)]
+    pub merkle: Option<MerkleDistribution>,
+    #[serde(default)]
+    pub metadata: Option<ListMetadata>,
+    #[serde(default)]
+    pub registration: Option<RecipientRegistration>,
+    /// Indices of the payments whose NFT has been received (NFT lists only)
+    #[serde(default)]
+    pub nft_received: Option<Vec<u32>>,
+    #[serde(default)]
+    pub intents_delivery: IntentsDelivery,
+    /// Amount deposited so far (single-token lists)
+    #[serde(default)]
+    pub funded_amount: String,
+    /// Token totals funded so far (mixed-token lists only)
+    #[serde(default)]
+    pub funded: Option<Vec<TokenAmount>>,
+    /// Set when the callers of `payout_batch` are rewarded
+    #[serde(default)]
+    pub keeper_rewards: Option<KeeperRewards>,
+    /// Accounts allowed to call `payout_batch`; anyone if unset
+    #[serde(default)]
+    pub operators: Option<Vec<String>>,
+    /// Gas attached to each payment call per token, where it differs from the default
+    #[serde(default)]
+    pub attached_gas: Option<Vec<TokenGas>>,
+    /// Largest gas a payment used besides its attached gas in the latest payout call
+    #[serde(default)]
+    pub measured_gas_overhead: Option<String>,
+    /// Total, weights and rounding rule the amounts were computed from (weighted lists only)
+    #[serde(default)]
+    pub split: Option<SplitTerms>,
+    /// Tax withheld from each payment for a withholding account
+    #[serde(default)]
+    pub withholding: Option<Withholding>,
+    /// US cent amounts and the price they are converted at (USD-denominated lists only)
+    #[serde(default)]
+    pub fiat: Option<FiatDenomination>,
+}
+
+/// Amount of one token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenAmount {
+    pub token_id: String,
+    pub amount: String,
+}
+
+/// NEAR budget of a list for rewarding the callers of `payout_batch`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeeperRewards {
+    pub fee_per_payment: String,
+    pub budget: String,
+    pub paid: String,
+}
+
+/// NEAR budget of a list for registering recipients with the token contract
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecipientRegistration {
+    pub budget: String,
+    pub spent: String,
+    /// Payments whose registration or transfer has not resolved yet
+    #[serde(default)]
+    pub in_flight: Vec<RegistrationInFlight>,
+}
+
+/// Payment of a list with registration between `payout_batch` and its transfer's result
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegistrationInFlight {
+    pub index: u32,
+    /// Block height at which the registration check was scheduled
+    pub scheduled_at: u64,
+}
+
+/// Summary of a Merkle list as stored by the contract
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleDistribution {
+    pub total: String,
+    pub count: u32,
+    pub claimed_count: u32,
+    pub claimed_amount: String,
+    /// Amount returned to the submitter after the deadline
+    pub reclaimed_amount: String,
+    pub reclaimed_at: Option<u64>,
 }
 
 /// Represents a completed payment transaction with block height for transaction lookup
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentTransaction {
     pub recipient: String,
+    /// Gross amount of the payment
     pub amount: String,
+    /// Amount the recipient received, if tax was withheld
+    #[serde(default)]
+    pub net_amount: Option<String>,
+    #[serde(default)]
+    pub withheld_amount: Option<String>,
+    #[serde(default)]
+    pub reference: Option<String>,
     pub block_height: u64,
 }
 
@@ -289,6 +631,7 @@ impl BulkPaymentClient {
         submitter_id: &str,
         token_id: &str,
         payments: Vec<PaymentInput>,
+        options: Option<ListOptions>,
     ) -> Result<String> {
         info!(
             "Submitting payment list {} for {} with {} payments",
@@ -297,17 +640,56 @@ impl BulkPaymentClient {
             payments.len()
         );
 
-        // Sign as the contract account, but pass submitter_id to the contract
+        self.send_submission(
+            "submit_list",
+            list_id,
+            json!({
+                "list_id": list_id,
+                "token_id": token_id,
+                "payments": payments,
+                "submitter_id": submitter_id,
+                "options": options
+            }),
+        )
+        .await
+    }
+
+    /// Submit a list splitting a total across recipients by weight, signed as the
+    /// contract account like `submit_list`
+    pub async fn submit_weighted_list(
+        &self,
+        list_id: &str,
+        submitter_id: &str,
+        token_id: &str,
+        split: WeightedSplit,
+        options: Option<ListOptions>,
+    ) -> Result<String> {
+        info!(
+            "Submitting weighted list {} for {} splitting {} across {} recipients",
+            list_id,
+            submitter_id,
+            split.total,
+            split.shares.len()
+        );
+
+        self.send_submission(
+            "submit_weighted_list",
+            list_id,
+            json!({
+                "list_id": list_id,
+                "token_id": token_id,
+                "split": split,
+                "submitter_id": submitter_id,
+                "options": options
+            }),
+        )
+        .await
+    }
+
+    /// Call a list submission method as the contract account and check the list was stored
+    async fn send_submission(&self, method: &str, list_id: &str, args: Value) -> Result<String> {
         let result = Contract(self.contract_id.parse()?)
-            .call_function(
-                "submit_list",
-                json!({
-                    "list_id": list_id,
-                    "token_id": token_id,
-                    "payments": payments,
-                    "submitter_id": submitter_id
-                }),
-            )?
+            .call_function(method, args)?
             .transaction()
             .with_signer(self.contract_id.parse()?, self.signer.clone())
             .send_to(&self.network_config)
@@ -402,15 +784,22 @@ impl BulkPaymentClient {
     }
 
     /// Execute payout batch for a payment list
-    /// Contract auto-determines optimal batch size based on token type
-    pub async fn payout_batch(&self, caller_id: &str, list_id: &str) -> Result<u64> {
+    /// Contract auto-determines optimal batch size based on token type, capped at
+    /// `max_payments` if given
+    pub async fn payout_batch(
+        &self,
+        caller_id: &str,
+        list_id: &str,
+        max_payments: Option<u32>,
+    ) -> Result<u64> {
         debug!("Executing payout batch for list: {}", list_id);
 
         let result = Contract(self.contract_id.parse()?)
             .call_function(
                 "payout_batch",
                 json!({
-                    "list_id": list_id
+                    "list_id": list_id,
+                    "max_payments": max_payments
                 }),
             )?
             .transaction()
@@ -471,13 +860,47 @@ impl BulkPaymentClient {
             .any(|p| matches!(p.status, PaymentStatus::Pending)))
     }
 
-    /// Get all approved lists that have pending payments
+    /// Pay pending records of the lists queued in the contract, up to `max_lists` lists
+    /// Returns the number of payments processed across all lists
+    pub async fn payout_next(&self, caller_id: &str, max_lists: Option<u32>) -> Result<u64> {
+        debug!("Executing payout_next for up to {:?} lists", max_lists);
+
+        let result = Contract(self.contract_id.parse()?)
+            .call_function("payout_next", json!({ "max_lists": max_lists }))?
+            .transaction()
+            .gas(NearGas::from_tgas(300))
+            .with_signer(caller_id.parse()?, self.signer.clone())
+            .send_to(&self.network_config)
+            .await
+            .context("Failed to execute payout_next")?;
+
+        if !result.is_success() {
+            anyhow::bail!("payout_next transaction failed: {:?}", result);
+        }
+
+        // One "Processed X payments for list Y, Z remaining" log per list visited
+        let processed = result
+            .logs()
+            .iter()
+            .filter(|log| log.starts_with("Processed ") && log.contains(" payments for list "))
+            .filter_map(|log| log.split_whitespace().nth(1)?.parse::<u64>().ok())
+            .sum();
+
+        debug!("Processed {} payments from the payout queue", processed);
+        Ok(processed)
+    }
+
+    /// Get the approved lists queued for payout in the contract, front first
+    /// (may include lists finished via `payout_batch` that the queue has not dropped yet)
     pub async fn get_approved_lists_with_pending(&self) -> Result<Vec<String>> {
-        // Note: In a production implementation, this would query the contract
-        // for a list of approved lists. For now, we'll track them in the worker.
-        warn!(
-            "get_approved_lists_with_pending not fully implemented - requires contract enumeration"
-        );
-        Ok(vec![])
+        let result: Vec<String> = Contract(self.contract_id.parse()?)
+            .call_function("view_payout_queue", json!({}))?
+            .read_only()
+            .fetch_from(&self.network_config)
+            .await
+            .context("Failed to view payout queue")?
+            .data;
+
+        Ok(result)
     }
 }
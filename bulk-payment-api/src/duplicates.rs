@@ -0,0 +1,174 @@
+//! Duplicate recipients in a payment list
+//!
+//! Mirrors the contract's `DuplicatePolicy`: payments to the same recipient in the same
+//! token (with the same destination and NFT, if any) are duplicates, "near", "NEAR" and
+//! "native" being the same token as for the contract. `Reject` refuses the list with the
+//! positions of every duplicate, `Merge` folds them into the first one by adding up the
+//! amounts, keeping a shared memo and joining the distinct references, which must stay
+//! within the contract's reference limit. The list hash is computed over the
+//! result, which is what the contract stores.
+
+use crate::contract::{DuplicatePolicy, PaymentInput};
+
+/// Longest memo or reference the contract stores, in bytes
+const MAX_PAYMENT_FIELD_LENGTH: usize = 128;
+
+/// Token of a payment as the contract parses it, NEAR under a single spelling
+fn normalized_token(token_id: &Option<String>) -> Option<&str> {
+    token_id.as_deref().map(|token| match token {
+        "near" | "NEAR" => "native",
+        token => token,
+    })
+}
+
+fn same_payee(a: &PaymentInput, b: &PaymentInput) -> bool {
+    a.recipient == b.recipient
+        && normalized_token(&a.token_id) == normalized_token(&b.token_id)
+        && a.nft_token_id == b.nft_token_id
+        && match (&a.destination, &b.destination) {
+            (Some(a), Some(b)) => a.chain == b.chain && a.address == b.address,
+            (None, None) => true,
+            _ => false,
+        }
+}
+
+/// Positions of the payments in each group of duplicates, in order of first appearance
+pub fn duplicate_groups(payments: &[PaymentInput]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (index, payment) in payments.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|group| same_payee(&payments[group[0]], payment))
+        {
+            Some(group) => group.push(index),
+            None => groups.push(vec![index]),
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Apply `policy` to `payments`, returning the payments the contract will store
+pub fn apply_duplicate_policy(
+    payments: &[PaymentInput],
+    policy: DuplicatePolicy,
+) -> Result<Vec<PaymentInput>, String> {
+    if policy == DuplicatePolicy::Allow {
+        return Ok(payments.to_vec());
+    }
+    let groups = duplicate_groups(payments);
+    if policy == DuplicatePolicy::Reject {
+        if groups.is_empty() {
+            return Ok(payments.to_vec());
+        }
+        let duplicates: Vec<String> = groups
+            .iter()
+            .map(|group| {
+                let positions: Vec<String> = group.iter().map(usize::to_string).collect();
+                format!(
+                    "{} at {}",
+                    payments[group[0]].recipient,
+                    positions.join(", ")
+                )
+            })
+            .collect();
+        return Err(format!("Duplicate payments: {}", duplicates.join("; ")));
+    }
+
+    let mut merged: Vec<Option<PaymentInput>> = payments.iter().cloned().map(Some).collect();
+    for group in groups {
+        let lines: Vec<PaymentInput> = group
+            .iter()
+            .map(|index| merged[*index].take().expect("Payment in one group"))
+            .collect();
+        let mut payment = lines[0].clone();
+
+        let mut total: u128 = 0;
+        for line in &lines {
+            let amount: u128 = line
+                .amount
+                .parse()
+                .map_err(|_| format!("Invalid amount '{}'", line.amount))?;
+            total = total
+                .checked_add(amount)
+                .ok_or("Total payment amount overflow")?;
+        }
+        payment.amount = total.to_string();
+
+        if lines.iter().any(|line| line.memo != payment.memo) {
+            payment.memo = None;
+        }
+        let mut references: Vec<&str> = Vec::new();
+        for reference in lines.iter().filter_map(|line| line.reference.as_deref()) {
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+        payment.reference = (!references.is_empty()).then(|| references.join(", "));
+        if payment
+            .reference
+            .as_ref()
+            .is_some_and(|reference| reference.len() > MAX_PAYMENT_FIELD_LENGTH)
+        {
+            return Err(format!(
+                "Merged reference of {} exceeds {} bytes",
+                payment.recipient, MAX_PAYMENT_FIELD_LENGTH
+            ));
+        }
+        merged[group[0]] = Some(payment);
+    }
+    Ok(merged.into_iter().flatten().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(recipient: &str, amount: &str, reference: Option<&str>) -> PaymentInput {
+        PaymentInput {
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            memo: None,
+            reference: reference.map(str::to_string),
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let payments = vec![
+            payment("a.near", "100", Some("INV-1")),
+            payment("b.near", "200", None),
+            payment("a.near", "300", Some("INV-2")),
+        ];
+
+        assert_eq!(
+            apply_duplicate_policy(&payments, DuplicatePolicy::Allow)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            apply_duplicate_policy(&payments, DuplicatePolicy::Reject).unwrap_err(),
+            "Duplicate payments: a.near at 0, 2"
+        );
+
+        let merged = apply_duplicate_policy(&payments, DuplicatePolicy::Merge).unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].amount, "400");
+        assert_eq!(merged[0].reference.as_deref(), Some("INV-1, INV-2"));
+        assert_eq!(merged[1].recipient, "b.near");
+
+        let long = "x".repeat(MAX_PAYMENT_FIELD_LENGTH / 2);
+        let payments = vec![
+            payment("a.near", "100", Some(&long)),
+            payment("a.near", "300", Some(&long.replace('x', "y"))),
+        ];
+        assert_eq!(
+            apply_duplicate_policy(&payments, DuplicatePolicy::Merge).unwrap_err(),
+            "Merged reference of a.near exceeds 128 bytes"
+        );
+    }
+}
@@ -5,6 +5,9 @@
 //! along with a background worker that processes approved lists.
 
 mod contract;
+mod duplicates;
+mod merkle;
+mod preflight;
 mod routes;
 mod worker;
 
@@ -17,6 +20,7 @@ use tracing::{info, Level};
 use tracing_subscriber::FmtSubscriber;
 
 use contract::BulkPaymentClient;
+use merkle::MerkleStore;
 use routes::{create_router, AppState};
 use worker::{PayoutWorker, WorkerConfig};
 
@@ -39,12 +43,22 @@ async fn main() -> Result<()> {
         .and_then(|p| p.parse().ok())
         .unwrap_or(8080u16);
     let worker_caller = std::env::var("WORKER_CALLER_ID").unwrap_or_else(|_| "test.near".into());
+    let worker_max_payments = std::env::var("WORKER_MAX_PAYMENTS")
+        .ok()
+        .and_then(|n| n.parse().ok())
+        .filter(|&n: &u32| n > 0);
+    let merkle_store_dir =
+        std::env::var("MERKLE_STORE_DIR").unwrap_or_else(|_| "merkle-trees".into());
 
     info!("Configuration:");
     info!("  RPC URL: {}", rpc_url);
     info!("  Contract ID: {}", contract_id);
     info!("  API Port: {}", api_port);
     info!("  Worker Caller: {}", worker_caller);
+    if let Some(max_payments) = worker_max_payments {
+        info!("  Worker Max Payments: {}", max_payments);
+    }
+    info!("  Merkle Store: {}", merkle_store_dir);
 
     // Create the bulk payment client
     let client = BulkPaymentClient::with_genesis_signer(&rpc_url, &contract_id)?;
@@ -57,6 +71,7 @@ async fn main() -> Result<()> {
         client: client.clone(),
         pending_lists: pending_lists.clone(),
         rpc_url: rpc_url.clone(),
+        merkle_store: MerkleStore::new(merkle_store_dir),
     };
 
     // Create the router with CORS and tracing
@@ -73,6 +88,7 @@ async fn main() -> Result<()> {
     let worker_config = WorkerConfig {
         poll_interval: 5,
         caller_id: worker_caller,
+        max_payments_per_batch: worker_max_payments,
     };
     let worker = PayoutWorker::new(client, worker_config, pending_lists);
 
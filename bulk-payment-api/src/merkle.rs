@@ -0,0 +1,328 @@
+//! Merkle trees for Merkle-root payment lists
+//!
+//! Builds the tree whose root is submitted as the `list_id` of a Merkle list and
+//! produces the proofs recipients pass to `claim_with_proof`. The contract only stores
+//! the root, so the leaves of every built tree are persisted by `MerkleStore` and the
+//! tree is rebuilt from them when proofs are requested. The hashing rules must match
+//! the contract (src/merkle.rs):
+//! - leaf   = SHA-256("{index}:{recipient}:{amount}"), amount as a decimal string
+//! - parent = SHA-256(min(left, right) || max(left, right))
+//! - a node without a sibling is carried up to the next level unchanged
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+use crate::contract::PaymentInput;
+
+type Hash = [u8; 32];
+
+/// A single claimable leaf with its proof
+#[derive(Debug, Clone, Serialize)]
+pub struct MerkleClaim {
+    pub index: u32,
+    pub amount: String,
+    pub proof: Vec<String>,
+}
+
+/// Leaf as persisted by `MerkleStore`
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredLeaf {
+    recipient: String,
+    amount: String,
+}
+
+/// Merkle tree over a payment list, with all levels kept for proof generation
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    payments: Vec<(String, u128)>,
+    /// levels[0] are the leaves, the last level holds only the root
+    levels: Vec<Vec<Hash>>,
+}
+
+fn leaf_hash(index: usize, recipient: &str, amount: u128) -> Hash {
+    Sha256::digest(format!("{}:{}:{}", index, recipient, amount).as_bytes()).into()
+}
+
+fn parent_hash(a: &Hash, b: &Hash) -> Hash {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = Sha256::new();
+    hasher.update(first);
+    hasher.update(second);
+    hasher.finalize().into()
+}
+
+impl MerkleTree {
+    /// Build a tree from payments; leaf indices follow the order of `payments`.
+    /// Fails on invalid account IDs and on zero or malformed amounts, which could never be
+    /// claimed.
+    pub fn build(payments: &[PaymentInput]) -> Result<Self> {
+        if payments.is_empty() {
+            bail!("Payment list cannot be empty");
+        }
+        let payments = payments
+            .iter()
+            .enumerate()
+            .map(|(index, p)| {
+                if p.recipient.parse::<near_api::AccountId>().is_err() {
+                    bail!("Invalid account ID {} at {}", p.recipient, index);
+                }
+                let amount = p
+                    .amount
+                    .parse::<u128>()
+                    .map_err(|_| anyhow!("Invalid amount {} for {}", p.amount, p.recipient))?;
+                if amount == 0 {
+                    bail!("Amount for {} at {} must be positive", p.recipient, index);
+                }
+                Ok((p.recipient.clone(), amount))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut levels = vec![payments
+            .iter()
+            .enumerate()
+            .map(|(i, (recipient, amount))| leaf_hash(i, recipient, *amount))
+            .collect::<Vec<_>>()];
+        while levels.last().map_or(0, Vec::len) > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => parent_hash(a, b),
+                    [a] => *a,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Ok(Self { payments, levels })
+    }
+
+    /// Hex-encoded root, used as the list_id of the Merkle list
+    pub fn root(&self) -> String {
+        hex::encode(self.levels.last().unwrap()[0])
+    }
+
+    /// Sum of all leaf amounts, the amount required to approve the list
+    pub fn total(&self) -> Result<u128> {
+        self.payments
+            .iter()
+            .try_fold(0u128, |acc, (_, amount)| acc.checked_add(*amount))
+            .ok_or_else(|| anyhow!("Total payment amount overflow"))
+    }
+
+    pub fn count(&self) -> usize {
+        self.payments.len()
+    }
+
+    /// Proof for the leaf at `index`: sibling hashes from the bottom up
+    pub fn proof(&self, index: usize) -> Vec<String> {
+        let mut proof = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                proof.push(hex::encode(sibling));
+            }
+            position /= 2;
+        }
+        proof
+    }
+
+    /// All leaves of a recipient with their proofs
+    pub fn claims_for(&self, recipient: &str) -> Vec<MerkleClaim> {
+        self.payments
+            .iter()
+            .enumerate()
+            .filter(|(_, (r, _))| r == recipient)
+            .map(|(index, (_, amount))| MerkleClaim {
+                index: index as u32,
+                amount: amount.to_string(),
+                proof: self.proof(index),
+            })
+            .collect()
+    }
+}
+
+/// Leaves of built trees, one JSON file per root in a directory
+#[derive(Debug, Clone)]
+pub struct MerkleStore {
+    dir: PathBuf,
+}
+
+impl MerkleStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// File of a root; None unless `root` is a hex SHA-256 hash, so it cannot escape `dir`
+    fn path(&self, root: &str) -> Option<PathBuf> {
+        (root.len() == 64 && root.chars().all(|c| c.is_ascii_hexdigit()))
+            .then(|| self.dir.join(format!("{}.json", root.to_ascii_lowercase())))
+    }
+
+    /// Persist the leaves of a tree, replacing an earlier copy of the same tree
+    pub async fn save(&self, tree: &MerkleTree) -> Result<()> {
+        let root = tree.root();
+        let path = self.path(&root).expect("Roots are hex hashes");
+        let leaves: Vec<StoredLeaf> = tree
+            .payments
+            .iter()
+            .map(|(recipient, amount)| StoredLeaf {
+                recipient: recipient.clone(),
+                amount: amount.to_string(),
+            })
+            .collect();
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        // Write to a temporary file first so a crash never leaves a truncated tree
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(&leaves)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// Rebuild the tree of a root from its persisted leaves, None if it was never built
+    pub async fn load(&self, root: &str) -> Result<Option<MerkleTree>> {
+        let Some(path) = self.path(root) else {
+            return Ok(None);
+        };
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let payments: Vec<PaymentInput> = serde_json::from_slice::<Vec<StoredLeaf>>(&data)?
+            .into_iter()
+            .map(|leaf| PaymentInput {
+                recipient: leaf.recipient,
+                amount: leaf.amount,
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
+            })
+            .collect();
+        let tree = MerkleTree::build(&payments)?;
+        if !tree.root().eq_ignore_ascii_case(root) {
+            bail!("Stored leaves of {} hash to {}", root, tree.root());
+        }
+        Ok(Some(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payments() -> Vec<PaymentInput> {
+        [
+            ("alice.near", "100"),
+            ("bob.near", "200"),
+            ("carol.near", "300"),
+        ]
+        .iter()
+        .map(|(recipient, amount)| PaymentInput {
+            recipient: recipient.to_string(),
+            amount: amount.to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        })
+        .collect()
+    }
+
+    fn verify(root: &str, index: usize, recipient: &str, amount: u128, proof: &[String]) -> bool {
+        let computed = proof
+            .iter()
+            .fold(leaf_hash(index, recipient, amount), |node, sibling| {
+                let sibling: Hash = hex::decode(sibling).unwrap().try_into().unwrap();
+                parent_hash(&node, &sibling)
+            });
+        hex::encode(computed) == root
+    }
+
+    #[test]
+    fn test_merkle_root_matches_contract() {
+        let tree = MerkleTree::build(&payments()).unwrap();
+        // Same tree as the contract's Merkle tests
+        assert_eq!(
+            tree.root(),
+            "303a9135f5a9d4812356022b7d582f44fe514800ffb565526ab8da837a0975f9"
+        );
+        assert_eq!(tree.total().unwrap(), 600);
+        assert_eq!(tree.count(), 3);
+    }
+
+    #[test]
+    fn test_merkle_proofs_verify() {
+        let tree = MerkleTree::build(&payments()).unwrap();
+        let root = tree.root();
+        for (index, payment) in payments().iter().enumerate() {
+            let claims = tree.claims_for(&payment.recipient);
+            assert_eq!(claims.len(), 1);
+            let amount = claims[0].amount.parse().unwrap();
+            assert!(verify(
+                &root,
+                index,
+                &payment.recipient,
+                amount,
+                &claims[0].proof
+            ));
+            assert!(!verify(
+                &root,
+                index,
+                &payment.recipient,
+                amount + 1,
+                &claims[0].proof
+            ));
+        }
+        // The odd leaf is carried up, so its proof is one hash shorter
+        assert_eq!(tree.proof(2).len(), 1);
+    }
+
+    #[test]
+    fn test_build_rejects_unclaimable_leaves() {
+        let mut invalid = payments();
+        invalid[1].recipient = "Bob!".to_string();
+        assert_eq!(
+            MerkleTree::build(&invalid).unwrap_err().to_string(),
+            "Invalid account ID Bob! at 1"
+        );
+
+        let mut zero = payments();
+        zero[2].amount = "0".to_string();
+        assert_eq!(
+            MerkleTree::build(&zero).unwrap_err().to_string(),
+            "Amount for carol.near at 2 must be positive"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_store_rebuilds_saved_trees() {
+        let dir = std::env::temp_dir().join(format!("merkle-store-{}", std::process::id()));
+        let store = MerkleStore::new(&dir);
+        let tree = MerkleTree::build(&payments()).unwrap();
+        store.save(&tree).await.unwrap();
+
+        // A new store over the same directory, as after a restart
+        let loaded = MerkleStore::new(&dir)
+            .load(&tree.root())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(loaded.root(), tree.root());
+        assert_eq!(loaded.proof(1), tree.proof(1));
+
+        assert!(store.load(&"00".repeat(32)).await.unwrap().is_none());
+        assert!(store.load("../secrets").await.unwrap().is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,217 @@
+//! Pre-flight validation of payment lists
+//!
+//! Checks recipients via RPC before a list is submitted: named accounts must exist
+//! (`view_account`) and, for NEP-141 tokens, every recipient must be registered with
+//! the token contract (`storage_balance_of`). NFT lists only need existing accounts.
+//! Implicit accounts are not required to exist since transfers create them. Lists of NEAR Intents tokens are only checked
+//! for valid account IDs; PoA tokens (`.omft.near`) pay external chain addresses and
+//! are not checked at all.
+
+use anyhow::{anyhow, Result};
+use base64::Engine;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::contract::PaymentInput;
+
+/// Problem found with a payment
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+pub enum PreflightIssueKind {
+    InvalidAccountId,
+    AccountNotFound,
+    NotRegistered,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreflightIssue {
+    pub index: usize,
+    pub recipient: String,
+    pub kind: PreflightIssueKind,
+}
+
+/// Check whether a recipient is a syntactically valid NEAR account ID
+fn is_valid_account_id(account_id: &str) -> bool {
+    account_id.parse::<near_api::AccountId>().is_ok()
+}
+
+/// Implicit accounts (NEAR or ETH style) exist as soon as they receive tokens
+fn is_implicit_account(account_id: &str) -> bool {
+    let is_hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+    (account_id.len() == 64 && is_hex(account_id))
+        || account_id
+            .strip_prefix("0x")
+            .is_some_and(|address| address.len() == 40 && is_hex(address))
+}
+
+/// Run a JSON-RPC `query`, returning `Ok(Err(error))` for query errors
+async fn rpc_query(
+    client: &reqwest::Client,
+    rpc_url: &str,
+    params: serde_json::Value,
+) -> Result<std::result::Result<serde_json::Value, serde_json::Value>> {
+    let response: serde_json::Value = client
+        .post(rpc_url)
+        .json(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": "preflight",
+            "method": "query",
+            "params": params
+        }))
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if let Some(error) = response.get("error") {
+        return Ok(Err(error.clone()));
+    }
+    let result = response
+        .get("result")
+        .ok_or_else(|| anyhow!("No result in query response"))?;
+    // Older nodes report some query errors inside the result
+    if let Some(error) = result.get("error") {
+        return Ok(Err(error.clone()));
+    }
+    Ok(Ok(result.clone()))
+}
+
+async fn account_exists(client: &reqwest::Client, rpc_url: &str, account_id: &str) -> Result<bool> {
+    let response = rpc_query(
+        client,
+        rpc_url,
+        serde_json::json!({
+            "request_type": "view_account",
+            "finality": "final",
+            "account_id": account_id
+        }),
+    )
+    .await?;
+
+    match response {
+        Ok(_) => Ok(true),
+        Err(error) => {
+            let unknown = error.pointer("/cause/name").and_then(|name| name.as_str())
+                == Some("UNKNOWN_ACCOUNT")
+                || error.to_string().contains("does not exist");
+            if unknown {
+                Ok(false)
+            } else {
+                Err(anyhow!(
+                    "RPC error viewing account {}: {}",
+                    account_id,
+                    error
+                ))
+            }
+        }
+    }
+}
+
+async fn is_registered(
+    client: &reqwest::Client,
+    rpc_url: &str,
+    token_id: &str,
+    account_id: &str,
+) -> Result<bool> {
+    let args = serde_json::json!({ "account_id": account_id }).to_string();
+    let result = rpc_query(
+        client,
+        rpc_url,
+        serde_json::json!({
+            "request_type": "call_function",
+            "finality": "final",
+            "account_id": token_id,
+            "method_name": "storage_balance_of",
+            "args_base64": base64::engine::general_purpose::STANDARD.encode(args)
+        }),
+    )
+    .await?
+    .map_err(|error| {
+        anyhow!(
+            "RPC error calling storage_balance_of on {}: {}",
+            token_id,
+            error
+        )
+    })?;
+
+    let bytes: Vec<u8> = serde_json::from_value(
+        result
+            .get("result")
+            .cloned()
+            .ok_or_else(|| anyhow!("No result in storage_balance_of response"))?,
+    )?;
+    let balance: serde_json::Value = serde_json::from_slice(&bytes)?;
+    Ok(!balance.is_null())
+}
+
+/// Check all recipients of a payment list, returning the problems found
+pub async fn preflight_payments(
+    rpc_url: &str,
+    token_id: &str,
+    payments: &[PaymentInput],
+) -> Result<Vec<PreflightIssue>> {
+    let intents_token = token_id.strip_prefix("nep141:");
+    if intents_token.is_some_and(|token| token.ends_with(".omft.near")) {
+        return Ok(Vec::new());
+    }
+    let is_native = matches!(token_id, "native" | "near" | "NEAR");
+    let is_nft = token_id.starts_with("nft:");
+    let check_registration = !is_native && !is_nft && intents_token.is_none();
+
+    let client = reqwest::Client::new();
+    // Each recipient is checked once, even if it appears in several payments
+    let mut checked: HashMap<&str, Option<PreflightIssueKind>> = HashMap::new();
+    let mut issues = Vec::new();
+
+    for (index, payment) in payments.iter().enumerate() {
+        let recipient = payment.recipient.as_str();
+        let kind = match checked.get(recipient) {
+            Some(kind) => *kind,
+            None => {
+                let kind = if !is_valid_account_id(recipient) {
+                    Some(PreflightIssueKind::InvalidAccountId)
+                } else if intents_token.is_some() {
+                    None
+                } else if !is_implicit_account(recipient)
+                    && !account_exists(&client, rpc_url, recipient).await?
+                {
+                    Some(PreflightIssueKind::AccountNotFound)
+                } else if check_registration
+                    && !is_registered(&client, rpc_url, token_id, recipient).await?
+                {
+                    Some(PreflightIssueKind::NotRegistered)
+                } else {
+                    None
+                };
+                checked.insert(recipient, kind);
+                kind
+            }
+        };
+
+        if let Some(kind) = kind {
+            issues.push(PreflightIssue {
+                index,
+                recipient: payment.recipient.clone(),
+                kind,
+            });
+        }
+    }
+
+    Ok(issues)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_id_classification() {
+        assert!(is_valid_account_id("alice.near"));
+        assert!(!is_valid_account_id("Alice.near"));
+
+        assert!(is_implicit_account(&"a".repeat(64)));
+        assert!(is_implicit_account(
+            "0x52908400098527886e0f7030069857d2e4169ee7"
+        ));
+        assert!(!is_implicit_account("alice.near"));
+    }
+}
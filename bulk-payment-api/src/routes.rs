@@ -17,22 +17,44 @@ use tokio::sync::RwLock;
 use tracing::{error, info};
 
 use crate::contract::{
-    BulkPaymentClient, ListStatus, PaymentInput, PaymentList, PaymentStatus, PaymentTransaction,
+    BulkPaymentClient, ListMetadata, ListOptions, ListStatus, PaymentInput, PaymentList,
+    PaymentStatus, PaymentTransaction, WeightedSplit,
 };
+use crate::duplicates::apply_duplicate_policy;
+use crate::merkle::{MerkleClaim, MerkleStore, MerkleTree};
+use crate::preflight::{preflight_payments, PreflightIssue};
 
 /// Compute SHA-256 hash of payment list for verification
 /// This ensures the provided list_id matches the actual payload content
-fn compute_list_hash(submitter_id: &str, token_id: &str, payments: &[PaymentInput]) -> String {
+///
+/// List options, and the memo and reference of each payment, are only part of the
+/// canonical JSON when provided, so lists without them keep the same hash as before
+/// these fields existed.
+///
+/// The duplicate policy of the options is applied first, so the hash covers the payments
+/// the contract stores. Fails if the policy rejects the list's duplicates.
+fn compute_list_hash(
+    submitter_id: &str,
+    token_id: &str,
+    payments: &[PaymentInput],
+    options: Option<&ListOptions>,
+) -> Result<String, String> {
+    let policy = options.and_then(|o| o.duplicates).unwrap_or_default();
+    let payments = apply_duplicate_policy(payments, policy)?;
+
     // Sort payments by recipient for deterministic ordering
     let mut sorted_payments: Vec<_> = payments.iter().collect();
     sorted_payments.sort_by(|a, b| a.recipient.cmp(&b.recipient));
 
     // Create canonical JSON representation
-    let canonical = serde_json::json!({
+    let mut canonical = serde_json::json!({
         "submitter": submitter_id,
         "token_id": token_id,
         "payments": sorted_payments
     });
+    if let Some(options) = options {
+        canonical["options"] = serde_json::json!(options);
+    }
 
     // Compute SHA-256 hash
     let mut hasher = Sha256::new();
@@ -40,7 +62,52 @@ fn compute_list_hash(submitter_id: &str, token_id: &str, payments: &[PaymentInpu
     let result = hasher.finalize();
 
     // Return hex-encoded hash
-    hex::encode(result)
+    Ok(hex::encode(result))
+}
+
+/// Compute SHA-256 hash of a weighted list for verification
+///
+/// The canonical JSON holds the split (total, shares and remainder rule) instead of the
+/// computed amounts. Shares keep their order, which decides where the remainder goes.
+fn compute_split_hash(
+    submitter_id: &str,
+    token_id: &str,
+    split: &WeightedSplit,
+    options: Option<&ListOptions>,
+) -> String {
+    let mut canonical = serde_json::json!({
+        "submitter": submitter_id,
+        "token_id": token_id,
+        "split": split
+    });
+    if let Some(options) = options {
+        canonical["options"] = serde_json::json!(options);
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(canonical.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Hash of the list a submit request describes, weighted or not
+fn request_hash(request: &SubmitListRequest) -> Result<String, String> {
+    match &request.split {
+        Some(_) if !request.payments.is_empty() => {
+            Err("Provide either payments or a split, not both".to_string())
+        }
+        Some(split) => Ok(compute_split_hash(
+            &request.submitter_id,
+            &request.token_id,
+            split,
+            request.options.as_ref(),
+        )),
+        None => compute_list_hash(
+            &request.submitter_id,
+            &request.token_id,
+            &request.payments,
+            request.options.as_ref(),
+        ),
+    }
 }
 
 /// Application state shared across handlers
@@ -51,6 +118,8 @@ pub struct AppState {
     pub pending_lists: Arc<RwLock<Vec<String>>>,
     /// RPC URL for making direct blockchain queries
     pub rpc_url: String,
+    /// Leaves of the Merkle trees built via `/merkle/build`, keyed by root (the Merkle list_id)
+    pub merkle_store: MerkleStore,
 }
 
 /// Request body for submitting a payment list
@@ -60,7 +129,14 @@ pub struct SubmitListRequest {
     pub submitter_id: String,
     pub dao_contract_id: String,
     pub token_id: String,
+    #[serde(default)]
     pub payments: Vec<PaymentInput>,
+    /// Total split across recipients by weight, submitted instead of `payments`
+    #[serde(default)]
+    pub split: Option<WeightedSplit>,
+    /// Optional list settings, e.g. claim mode with a claim deadline
+    #[serde(default)]
+    pub options: Option<ListOptions>,
 }
 
 /// Response for a submitted list
@@ -90,6 +166,56 @@ pub struct PaymentListView {
     pub pending_payments: usize,
     pub processed_payments: usize,
     pub created_at: u64,
+    /// Title, description, document reference and category given at submission
+    pub metadata: Option<ListMetadata>,
+}
+
+/// Request body for pre-flight validation of a payment list
+#[derive(Debug, Deserialize)]
+pub struct PreflightRequest {
+    pub token_id: String,
+    pub payments: Vec<PaymentInput>,
+}
+
+/// Response for pre-flight validation; `issues` is empty when all recipients passed
+#[derive(Debug, Serialize)]
+pub struct PreflightResponse {
+    pub success: bool,
+    pub issues: Option<Vec<PreflightIssue>>,
+    pub error: Option<String>,
+}
+
+/// Request body for building a Merkle tree
+#[derive(Debug, Deserialize)]
+pub struct BuildMerkleRequest {
+    pub payments: Vec<PaymentInput>,
+}
+
+/// Response for a built Merkle tree
+///
+/// `list_id`, `total` and `count` are the arguments for the contract's `submit_merkle_list`.
+#[derive(Debug, Serialize)]
+pub struct BuildMerkleResponse {
+    pub success: bool,
+    pub list_id: Option<String>,
+    pub total: Option<String>,
+    pub count: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Response for Merkle proofs of a recipient
+#[derive(Debug, Serialize)]
+pub struct MerkleProofResponse {
+    pub success: bool,
+    pub claims: Option<Vec<MerkleClaim>>,
+    pub error: Option<String>,
+}
+
+/// Path parameters for Merkle proof lookup
+#[derive(Debug, Deserialize)]
+pub struct MerkleProofParams {
+    pub id: String,
+    pub recipient: String,
 }
 
 /// Health check response
@@ -130,30 +256,41 @@ impl From<(String, PaymentList)> for PaymentListView {
     fn from((id, list): (String, PaymentList)) -> Self {
         let status = match list.status {
             ListStatus::Pending => "Pending",
+            ListStatus::PartiallyFunded => "PartiallyFunded",
             ListStatus::Approved => "Approved",
             ListStatus::Rejected => "Rejected",
         };
 
-        let pending = list
-            .payments
-            .iter()
-            .filter(|p| matches!(p.status, PaymentStatus::Pending))
-            .count();
-        let processed = list
-            .payments
-            .iter()
-            .filter(|p| matches!(p.status, PaymentStatus::Paid { .. }))
-            .count();
+        // Merkle lists have no payment records, only claim counters
+        let (total, pending, processed) = match &list.merkle {
+            Some(merkle) => (
+                merkle.count as usize,
+                (merkle.count - merkle.claimed_count) as usize,
+                merkle.claimed_count as usize,
+            ),
+            None => (
+                list.payments.len(),
+                list.payments
+                    .iter()
+                    .filter(|p| matches!(p.status, PaymentStatus::Pending))
+                    .count(),
+                list.payments
+                    .iter()
+                    .filter(|p| matches!(p.status, PaymentStatus::Paid { .. }))
+                    .count(),
+            ),
+        };
 
         Self {
             id,
             token_id: list.token_id,
             submitter: list.submitter,
             status: status.to_string(),
-            total_payments: list.payments.len(),
+            total_payments: total,
             pending_payments: pending,
             processed_payments: processed,
             created_at: list.created_at,
+            metadata: list.metadata,
         }
     }
 }
@@ -163,12 +300,15 @@ pub fn create_router(state: AppState) -> Router {
     Router::new()
         .route("/health", get(health_check))
         .route("/submit-list", post(submit_list))
+        .route("/preflight", post(preflight))
         .route("/list/:id", get(get_list))
         .route("/list/:id/transactions", get(get_transactions))
         .route(
             "/list/:id/transaction/:recipient",
             get(get_transaction_hash),
         )
+        .route("/merkle/build", post(build_merkle))
+        .route("/merkle/:id/proof/:recipient", get(get_merkle_proof))
         .with_state(state)
 }
 
@@ -193,13 +333,28 @@ async fn submit_list(
         "Received submit-list request from {} (DAO: {}) with {} payments, list_id: {}",
         request.submitter_id,
         request.dao_contract_id,
-        request.payments.len(),
+        request
+            .split
+            .as_ref()
+            .map_or(request.payments.len(), |split| split.shares.len()),
         request.list_id
     );
 
     // First, verify the list_id matches the SHA-256 hash of the payload
-    let computed_hash =
-        compute_list_hash(&request.submitter_id, &request.token_id, &request.payments);
+    let computed_hash = match request_hash(&request) {
+        Ok(hash) => hash,
+        Err(e) => {
+            error!("Invalid payment list {}: {}", request.list_id, e);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(SubmitListResponse {
+                    success: false,
+                    list_id: None,
+                    error: Some(e),
+                }),
+            );
+        }
+    };
     if computed_hash != request.list_id {
         error!(
             "Hash mismatch: provided list_id {} does not match computed hash {}",
@@ -268,16 +423,33 @@ async fn submit_list(
     }
 
     // DAO proposal verified - proceed with list submission
-    match state
-        .client
-        .submit_list(
-            &request.list_id,
-            &request.submitter_id,
-            &request.token_id,
-            request.payments,
-        )
-        .await
-    {
+    let submitted = match request.split {
+        Some(split) => {
+            state
+                .client
+                .submit_weighted_list(
+                    &request.list_id,
+                    &request.submitter_id,
+                    &request.token_id,
+                    split,
+                    request.options,
+                )
+                .await
+        }
+        None => {
+            state
+                .client
+                .submit_list(
+                    &request.list_id,
+                    &request.submitter_id,
+                    &request.token_id,
+                    request.payments,
+                    request.options,
+                )
+                .await
+        }
+    };
+    match submitted {
         Ok(list_id) => {
             // Track this list for the worker
             {
@@ -310,6 +482,165 @@ async fn submit_list(
     }
 }
 
+/// Validate the recipients of a payment list before it is submitted
+///
+/// Checks that named accounts exist and, for NEP-141 tokens, that every recipient is
+/// registered with the token contract. Problems are reported, not rejected, so callers
+/// decide whether to fix the list or submit it anyway.
+async fn preflight(
+    State(state): State<AppState>,
+    Json(request): Json<PreflightRequest>,
+) -> impl IntoResponse {
+    info!(
+        "Received preflight request for {} payments of {}",
+        request.payments.len(),
+        request.token_id
+    );
+
+    match preflight_payments(&state.rpc_url, &request.token_id, &request.payments).await {
+        Ok(issues) => {
+            info!("Preflight found {} issues", issues.len());
+            (
+                StatusCode::OK,
+                Json(PreflightResponse {
+                    success: true,
+                    issues: Some(issues),
+                    error: None,
+                }),
+            )
+        }
+        Err(e) => {
+            error!("Preflight checks failed: {}", e);
+            (
+                StatusCode::BAD_GATEWAY,
+                Json(PreflightResponse {
+                    success: false,
+                    issues: None,
+                    error: Some(e.to_string()),
+                }),
+            )
+        }
+    }
+}
+
+/// Build a Merkle tree over a payment list
+///
+/// Leaf indices follow the order of the submitted payments. The leaves are persisted so
+/// proofs can be served for recipients once the Merkle list has been submitted on-chain.
+async fn build_merkle(
+    State(state): State<AppState>,
+    Json(request): Json<BuildMerkleRequest>,
+) -> impl IntoResponse {
+    info!(
+        "Received merkle-build request with {} payments",
+        request.payments.len()
+    );
+
+    let built = MerkleTree::build(&request.payments)
+        .and_then(|tree| tree.total().map(|total| (tree, total)));
+    match built {
+        Ok((tree, total)) => {
+            if let Err(e) = state.merkle_store.save(&tree).await {
+                error!("Failed to store Merkle tree: {}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(BuildMerkleResponse {
+                        success: false,
+                        list_id: None,
+                        total: None,
+                        count: None,
+                        error: Some(e.to_string()),
+                    }),
+                );
+            }
+
+            (
+                StatusCode::OK,
+                Json(BuildMerkleResponse {
+                    success: true,
+                    list_id: Some(tree.root()),
+                    total: Some(total.to_string()),
+                    count: Some(tree.count()),
+                    error: None,
+                }),
+            )
+        }
+        Err(e) => {
+            error!("Failed to build Merkle tree: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(BuildMerkleResponse {
+                    success: false,
+                    list_id: None,
+                    total: None,
+                    count: None,
+                    error: Some(e.to_string()),
+                }),
+            )
+        }
+    }
+}
+
+/// Get the Merkle proofs for all leaves of a recipient
+async fn get_merkle_proof(
+    State(state): State<AppState>,
+    Path(params): Path<MerkleProofParams>,
+) -> impl IntoResponse {
+    info!(
+        "Received merkle-proof request for list {}, recipient {}",
+        params.id, params.recipient
+    );
+
+    let tree = match state.merkle_store.load(&params.id).await {
+        Ok(Some(tree)) => tree,
+        Ok(None) => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(MerkleProofResponse {
+                    success: false,
+                    claims: None,
+                    error: Some(format!("No Merkle tree found for list {}", params.id)),
+                }),
+            )
+        }
+        Err(e) => {
+            error!("Failed to load Merkle tree {}: {}", params.id, e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(MerkleProofResponse {
+                    success: false,
+                    claims: None,
+                    error: Some(e.to_string()),
+                }),
+            );
+        }
+    };
+
+    let claims = tree.claims_for(&params.recipient);
+    if claims.is_empty() {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(MerkleProofResponse {
+                success: false,
+                claims: None,
+                error: Some(format!(
+                    "No payments for {} in list {}",
+                    params.recipient, params.id
+                )),
+            }),
+        );
+    }
+
+    (
+        StatusCode::OK,
+        Json(MerkleProofResponse {
+            success: true,
+            claims: Some(claims),
+            error: None,
+        }),
+    )
+}
+
 /// Get payment list status
 async fn get_list(State(state): State<AppState>, Path(id): Path<String>) -> impl IntoResponse {
     info!("Received get-list request for list {}", id);
@@ -404,7 +735,10 @@ async fn get_transaction_hash(
     };
 
     // Find the transaction for this recipient
-    let payment = match transactions.iter().find(|t| t.recipient == params.recipient) {
+    let payment = match transactions
+        .iter()
+        .find(|t| t.recipient == params.recipient)
+    {
         Some(p) => p,
         None => {
             return (
@@ -426,7 +760,7 @@ async fn get_transaction_hash(
 
     // Look up the transaction hash by querying the block
     let contract_id = state.client.get_contract_id();
-    match lookup_transaction_hash(&state.rpc_url, payment.block_height, &contract_id).await {
+    match lookup_transaction_hash(&state.rpc_url, payment.block_height, contract_id).await {
         Ok(tx_hash) => (
             StatusCode::OK,
             Json(TransactionHashResponse {
@@ -553,8 +887,13 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: "a.near".to_string(),
             amount: "100".to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
-        let hash = compute_list_hash("test.near", "native", &payments);
+        let hash = compute_list_hash("test.near", "native", &payments, None).unwrap();
         println!(
             "Rust JSON: {}",
             serde_json::json!({
@@ -570,4 +909,154 @@ mod tests {
             "b667f7213a94d9e4f106080e7b3ec2f92d3ad19c71c4d6cb45b2f6f370c59ec4"
         );
     }
+
+    #[test]
+    fn test_compute_list_hash_merges_native_token_aliases() {
+        let payment = |amount: &str, token_id: &str| PaymentInput {
+            recipient: "a.near".to_string(),
+            amount: amount.to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: Some(token_id.to_string()),
+        };
+        let options = ListOptions {
+            duplicates: Some(crate::contract::DuplicatePolicy::Merge),
+            ..Default::default()
+        };
+
+        // The contract parses every spelling of NEAR to the same token and merges them
+        let aliases = vec![
+            payment("100", "near"),
+            payment("200", "NEAR"),
+            payment("300", "native"),
+        ];
+        assert_eq!(
+            compute_list_hash("test.near", "native", &aliases, Some(&options)).unwrap(),
+            compute_list_hash(
+                "test.near",
+                "native",
+                &[payment("600", "near")],
+                Some(&options)
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compute_list_hash_covers_options() {
+        let payments = vec![PaymentInput {
+            recipient: "a.near".to_string(),
+            amount: "100".to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let options = ListOptions {
+            payout_mode: Some(crate::contract::PayoutMode::Claim),
+            claim_deadline: Some(1_000),
+            ..Default::default()
+        };
+
+        let plain = compute_list_hash("test.near", "native", &payments, None).unwrap();
+        let with_options =
+            compute_list_hash("test.near", "native", &payments, Some(&options)).unwrap();
+        assert_ne!(plain, with_options);
+        // Empty options are still part of the hash, only absent options are skipped
+        assert_ne!(
+            plain,
+            compute_list_hash(
+                "test.near",
+                "native",
+                &payments,
+                Some(&ListOptions::default())
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compute_list_hash_covers_memo_and_reference() {
+        let mut payments = vec![PaymentInput {
+            recipient: "a.near".to_string(),
+            amount: "100".to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let plain = compute_list_hash("test.near", "native", &payments, None).unwrap();
+
+        payments[0].memo = Some("Invoice INV-42".to_string());
+        let with_memo = compute_list_hash("test.near", "native", &payments, None).unwrap();
+        payments[0].reference = Some("INV-42".to_string());
+        let with_reference = compute_list_hash("test.near", "native", &payments, None).unwrap();
+
+        assert_ne!(plain, with_memo);
+        assert_ne!(with_memo, with_reference);
+    }
+
+    #[test]
+    fn test_compute_list_hash_covers_metadata() {
+        let payments = vec![PaymentInput {
+            recipient: "a.near".to_string(),
+            amount: "100".to_string(),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let options = |title: &str| ListOptions {
+            metadata: Some(ListMetadata {
+                title: Some(title.to_string()),
+                category: Some("payroll".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        assert_ne!(
+            compute_list_hash("test.near", "native", &payments, Some(&options("March"))).unwrap(),
+            compute_list_hash("test.near", "native", &payments, Some(&options("April"))).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compute_split_hash_covers_weights_and_order() {
+        use crate::contract::{SplitRemainder, WeightedShare};
+
+        let split = |shares: &[(&str, u32)]| WeightedSplit {
+            total: "10000".to_string(),
+            shares: shares
+                .iter()
+                .map(|(recipient, weight_bps)| WeightedShare {
+                    recipient: recipient.to_string(),
+                    weight_bps: *weight_bps,
+                    memo: None,
+                    reference: None,
+                })
+                .collect(),
+            remainder: SplitRemainder::Last,
+        };
+        let hash = |split: &WeightedSplit| compute_split_hash("test.near", "native", split, None);
+
+        let proposed = hash(&split(&[("a.near", 6000), ("b.near", 4000)]));
+        assert_ne!(
+            proposed,
+            hash(&split(&[("a.near", 5000), ("b.near", 5000)]))
+        );
+        // The order decides which share gets the remainder
+        assert_ne!(
+            proposed,
+            hash(&split(&[("b.near", 4000), ("a.near", 6000)]))
+        );
+        let mut first = split(&[("a.near", 6000), ("b.near", 4000)]);
+        first.remainder = SplitRemainder::First;
+        assert_ne!(proposed, hash(&first));
+    }
 }
@@ -1,7 +1,9 @@
 //! Background worker for processing approved payment lists
 //!
-//! This worker polls the contract for approved lists and executes
-//! payout batches until all payments are complete.
+//! This worker drains the contract's payout queue with `payout_next`, which pays
+//! approved lists in the order they were approved. Lists submitted through the API are
+//! also tracked here until complete, and paid with `payout_batch` if they are not queued
+//! (e.g. approved before the contract kept a queue).
 
 use anyhow::Result;
 use std::sync::Arc;
@@ -10,7 +12,10 @@ use tokio::sync::RwLock;
 use tokio::time::interval;
 use tracing::{debug, error, info};
 
-use crate::contract::{BulkPaymentClient, ListStatus};
+use crate::contract::{BulkPaymentClient, ListStatus, PayoutMode};
+
+/// Most `payout_next` calls per poll, so one poll cannot run indefinitely
+const MAX_PAYOUT_NEXT_CALLS: usize = 20;
 
 /// Worker configuration
 #[derive(Debug, Clone)]
@@ -19,6 +24,8 @@ pub struct WorkerConfig {
     pub poll_interval: u64,
     /// Caller account ID for executing payouts
     pub caller_id: String,
+    /// Most payments per `payout_batch` call; the contract's gas-based limit if unset
+    pub max_payments_per_batch: Option<u32>,
 }
 
 impl Default for WorkerConfig {
@@ -26,6 +33,7 @@ impl Default for WorkerConfig {
         Self {
             poll_interval: 5,
             caller_id: "test.near".to_string(),
+            max_payments_per_batch: None,
         }
     }
 }
@@ -67,12 +75,41 @@ impl PayoutWorker {
 
         loop {
             poll_interval.tick().await;
+            if let Err(e) = self.drain_payout_queue().await {
+                error!("Error draining payout queue: {}", e);
+            }
             if let Err(e) = self.process_pending_lists().await {
                 error!("Error processing pending lists: {}", e);
             }
         }
     }
 
+    /// Call `payout_next` until the contract's payout queue is empty or stops making progress
+    async fn drain_payout_queue(&self) -> Result<()> {
+        for _ in 0..MAX_PAYOUT_NEXT_CALLS {
+            let queued = self.client.get_approved_lists_with_pending().await?;
+            if queued.is_empty() {
+                debug!("Payout queue is empty");
+                return Ok(());
+            }
+
+            let processed = self
+                .client
+                .payout_next(&self.config.caller_id, None)
+                .await?;
+            info!(
+                "Processed {} payments from the payout queue ({} lists queued)",
+                processed,
+                queued.len()
+            );
+            if processed == 0 {
+                // Only finished lists or lists of other operators were left
+                return Ok(());
+            }
+        }
+        Ok(())
+    }
+
     /// Process all pending lists
     async fn process_pending_lists(&self) -> Result<()> {
         // Get a copy of the pending lists
@@ -87,11 +124,12 @@ impl PayoutWorker {
         }
 
         debug!("Processing {} pending lists", lists.len());
+        let queued = self.client.get_approved_lists_with_pending().await?;
 
         let mut lists_to_remove = Vec::new();
 
         for list_id in &lists {
-            match self.process_list(list_id).await {
+            match self.process_list(list_id, queued.contains(list_id)).await {
                 Ok(complete) => {
                     if complete {
                         lists_to_remove.push(list_id.clone());
@@ -126,13 +164,14 @@ impl PayoutWorker {
 
     /// Process a single payment list
     ///
-    /// Returns true if the list is complete (no more pending payments)
-    async fn process_list(&self, list_id: &str) -> Result<bool> {
+    /// Returns true if the list is complete (no more pending payments). Lists in the
+    /// contract's payout queue are left to `payout_next`.
+    async fn process_list(&self, list_id: &str, queued: bool) -> Result<bool> {
         // Get the list status
         let list = self.client.view_list(list_id).await?;
 
         match list.status {
-            ListStatus::Pending => {
+            ListStatus::Pending | ListStatus::PartiallyFunded => {
                 debug!("List {} is still pending approval", list_id);
                 return Ok(false);
             }
@@ -145,6 +184,26 @@ impl PayoutWorker {
             }
         }
 
+        if list.payout_mode == PayoutMode::Claim {
+            info!(
+                "List {} uses claim mode, recipients claim their own payments",
+                list_id
+            );
+            return Ok(true);
+        }
+
+        if list
+            .operators
+            .as_ref()
+            .is_some_and(|operators| !operators.contains(&self.config.caller_id))
+        {
+            info!(
+                "List {} only accepts payouts from its operators, leaving it to them",
+                list_id
+            );
+            return Ok(true);
+        }
+
         // Check if there are pending payments
         let pending_count = list
             .payments
@@ -157,15 +216,50 @@ impl PayoutWorker {
             return Ok(true);
         }
 
+        // Payments being registered are resolved by the contract's callbacks
+        let in_flight: Vec<u32> = list
+            .registration
+            .as_ref()
+            .map(|registration| registration.in_flight.iter().map(|e| e.index).collect())
+            .unwrap_or_default();
+        let payable_count = list
+            .payments
+            .iter()
+            .enumerate()
+            .filter(|(index, p)| {
+                matches!(p.status, crate::contract::PaymentStatus::Pending)
+                    && !in_flight.contains(&(*index as u32))
+            })
+            .count();
+        if payable_count == 0 {
+            debug!(
+                "List {} has {} payments awaiting recipient registration",
+                list_id, pending_count
+            );
+            return Ok(false);
+        }
+
+        if queued {
+            debug!(
+                "List {} has {} pending payments in the payout queue",
+                list_id, pending_count
+            );
+            return Ok(false);
+        }
+
         info!(
             "Processing list {} with {} pending payments",
             list_id, pending_count
         );
 
-        // Execute payout batch (contract auto-determines batch size)
+        // Execute payout batch (contract auto-determines batch size up to the configured cap)
         let processed = self
             .client
-            .payout_batch(&self.config.caller_id, list_id)
+            .payout_batch(
+                &self.config.caller_id,
+                list_id,
+                self.config.max_payments_per_batch,
+            )
             .await?;
 
         info!(
@@ -178,7 +272,10 @@ impl PayoutWorker {
         let has_pending = self.client.has_pending_payments(list_id).await?;
 
         if has_pending {
-            info!("List {} still has pending payments, will process again", list_id);
+            info!(
+                "List {} still has pending payments, will process again",
+                list_id
+            );
         }
 
         Ok(!has_pending)
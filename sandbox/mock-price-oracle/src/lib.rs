@@ -0,0 +1,116 @@
+// Mock price oracle for the sandbox
+//
+// Serves `get_price_data` in the format of priceoracle.near, so USD-denominated bulk payment
+// lists can lock a price without the mainnet oracle and its reporters. Prices are set by
+// the oracle account itself with `set_price`. Like a live oracle, the data is as recent as
+// the current block, unless `set_timestamp` pins an older timestamp to exercise the
+// staleness check.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::store::IterableMap;
+use near_sdk::{env, near, PanicOnDefault};
+
+/// USD value of one smallest token unit: multiplier / 10^decimals
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+#[near(serializers = [json])]
+pub struct AssetOptionalPrice {
+    pub asset_id: String,
+    pub price: Option<Price>,
+}
+
+#[near(serializers = [json])]
+pub struct PriceData {
+    pub timestamp: U64,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+#[near(contract_state)]
+#[derive(PanicOnDefault)]
+pub struct MockPriceOracle {
+    prices: IterableMap<String, Price>,
+    recency_duration_sec: u32,
+    /// Timestamp (ns) reported instead of the current block's
+    fixed_timestamp: Option<u64>,
+}
+
+#[near]
+impl MockPriceOracle {
+    #[init]
+    pub fn new(recency_duration_sec: Option<u32>) -> Self {
+        Self {
+            prices: IterableMap::new(b"p"),
+            recency_duration_sec: recency_duration_sec.unwrap_or(90),
+            fixed_timestamp: None,
+        }
+    }
+
+    /// Set or, with `None`, remove the price of an asset
+    #[private]
+    pub fn set_price(&mut self, asset_id: String, price: Option<Price>) {
+        match price {
+            Some(price) => self.prices.insert(asset_id, price),
+            None => self.prices.remove(&asset_id),
+        };
+    }
+
+    /// Report `timestamp` (ns) as the time of the prices, or with `None` the current block's
+    #[private]
+    pub fn set_timestamp(&mut self, timestamp: Option<U64>) {
+        self.fixed_timestamp = timestamp.map(|timestamp| timestamp.0);
+    }
+
+    /// Prices of `asset_ids`, or of all assets with a price
+    pub fn get_price_data(&self, asset_ids: Option<Vec<String>>) -> PriceData {
+        let asset_ids =
+            asset_ids.unwrap_or_else(|| self.prices.keys().cloned().collect::<Vec<_>>());
+        PriceData {
+            timestamp: U64(self.fixed_timestamp.unwrap_or_else(env::block_timestamp)),
+            recency_duration_sec: self.recency_duration_sec,
+            prices: asset_ids
+                .into_iter()
+                .map(|asset_id| AssetOptionalPrice {
+                    price: self.prices.get(&asset_id).cloned(),
+                    asset_id,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::VMContextBuilder;
+    use near_sdk::testing_env;
+
+    #[test]
+    fn test_get_price_data_returns_set_prices() {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.build());
+        let mut oracle = MockPriceOracle::new(None);
+
+        context.block_timestamp(42);
+        testing_env!(context.build());
+        let price = Price {
+            multiplier: U128(30000),
+            decimals: 28,
+        };
+        oracle.set_price("wrap.near".to_string(), Some(price.clone()));
+
+        context.block_timestamp(1000);
+        testing_env!(context.build());
+        let data = oracle.get_price_data(Some(vec!["wrap.near".into(), "usdt.near".into()]));
+        assert_eq!(data.timestamp, U64(1000));
+        assert_eq!(data.prices[0].price, Some(price));
+        assert_eq!(data.prices[1].price, None);
+
+        oracle.set_timestamp(Some(U64(42)));
+        assert_eq!(oracle.get_price_data(None).timestamp, U64(42));
+    }
+}
@@ -457,6 +457,47 @@ async fn deploy_bulk_payment_contract(
     Ok(())
 }
 
+/// Deploy the mock price oracle and seed it with a NEAR price for USD-denominated lists
+async fn deploy_price_oracle(
+    network_config: &NetworkConfig,
+    contract_id: &AccountId,
+    wasm_path: &str,
+) -> Result<()> {
+    info!("Deploying mock price oracle to {}", contract_id);
+
+    let contract_code = std::fs::read(wasm_path)
+        .context(format!("Failed to read price oracle WASM from {}", wasm_path))?;
+
+    let contract_signer = get_genesis_signer();
+
+    near_api::Contract::deploy(contract_id.clone())
+        .use_code(contract_code)
+        .with_init_call("new", serde_json::json!({}))
+        .unwrap()
+        .with_signer(contract_signer.clone())
+        .send_to(network_config)
+        .await
+        .context("Failed to deploy mock price oracle")?
+        .assert_success();
+
+    // $3.00 per NEAR: USD value of one yoctoNEAR is 30000 / 10^28
+    near_api::Contract(contract_id.clone())
+        .call_function("set_price", serde_json::json!({
+            "asset_id": "wrap.near",
+            "price": { "multiplier": "30000", "decimals": 28 }
+        }))
+        .unwrap()
+        .transaction()
+        .with_signer(contract_id.clone(), contract_signer)
+        .send_to(network_config)
+        .await
+        .context("Failed to set the wrap.near price")?
+        .assert_success();
+
+    info!("Successfully deployed mock price oracle to {}", contract_id);
+    Ok(())
+}
+
 /// Deploy a DAO contract from a WASM file
 async fn deploy_dao_contract(
     network_config: &NetworkConfig,
@@ -574,6 +615,12 @@ async fn main() -> Result<()> {
             private_key: genesis_account.private_key.clone(),
             public_key: genesis_account.public_key.clone(),
         },
+        GenesisAccount {
+            account_id: "priceoracle.near".parse().unwrap(),
+            balance: NearToken::from_near(100),
+            private_key: genesis_account.private_key.clone(),
+            public_key: genesis_account.public_key.clone(),
+        },
     ];
 
     // Ensure sandbox binary is available
@@ -644,6 +691,18 @@ async fn main() -> Result<()> {
             error!("Failed to initialize sputnik-dao.near: {}", e);
         }
 
+        // Deploy the mock price oracle at the mainnet oracle's account ID, which the bulk
+        // payment contract uses by default for USD-denominated lists
+        let price_oracle_wasm = format!("{}/mock_price_oracle.wasm", contracts_dir);
+        if std::path::Path::new(&price_oracle_wasm).exists() {
+            let price_oracle_id: AccountId = "priceoracle.near".parse().unwrap();
+            if let Err(e) = deploy_price_oracle(&network_config, &price_oracle_id, &price_oracle_wasm).await {
+                error!("Failed to deploy mock price oracle: {}", e);
+            }
+        } else {
+            info!("Mock price oracle not found at {}, skipping", price_oracle_wasm);
+        }
+
         // Note: wrap.near is registered with intents.near via wnear_id in the config during initialization
         // No separate storage_deposit call is needed for the token itself
 
@@ -702,6 +761,7 @@ async fn main() -> Result<()> {
     info!("  - intents.near");
     info!("  - omft.near");
     info!("  - sputnik-dao.near (DAO factory)");
+    info!("  - priceoracle.near (mock price oracle)");
     info!("  - {}", bulk_payment_id);
     info!("");
     info!("Persistent home directory: {:?}", sandbox_home);
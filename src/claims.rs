@@ -0,0 +1,411 @@
+// Pull-based claim mode
+//
+// Lists submitted with `PayoutMode::Claim` are not processed by `payout_batch`.
+// Instead each recipient calls `claim(list_id)` to receive all of their pending
+// payments, paying the gas themselves. For NEP-141 tokens the contract first checks
+// the recipient's storage registration with the token contract, so an unregistered
+// recipient gets a clear log message instead of a failed transfer.
+//
+// After the claim deadline the submitter can call `reclaim_unclaimed(list_id)` to get
+// all unclaimed amounts back.
+use near_sdk::json_types::U128;
+use near_sdk::{
+    env, log, near, require, AccountId, Gas, NearToken, Promise, PromiseError, PromiseOrValue,
+};
+
+use crate::executor::transfer_delivered;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentStatus, PayoutMode,
+};
+
+const GAS_FOR_STORAGE_BALANCE_OF: Gas = Gas::from_tgas(5);
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(10);
+const GAS_FOR_CLAIM_CALLBACK: Gas = Gas::from_tgas(75);
+
+/// NEP-145 storage balance as returned by `storage_balance_of`
+#[near(serializers = [json])]
+pub struct StorageBalance {
+    pub total: U128,
+    pub available: U128,
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Claim all pending payments of the caller in a claim-mode list
+    ///
+    /// For NEP-141 tokens the caller must be registered with the token contract
+    /// (NEP-145 `storage_deposit`), otherwise nothing is transferred and the payments
+    /// stay claimable.
+    ///
+    /// # Panics
+    /// - If the list is not Approved or not in claim mode
+    /// - If the claim deadline has passed
+    /// - If the caller has no pending payments in the list
+    pub fn claim(&mut self, list_id: ListId) -> PromiseOrValue<U128> {
+        let recipient = env::predecessor_account_id();
+
+        let list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found");
+
+        require!(
+            matches!(list.status, ListStatus::Approved),
+            "List must be Approved to claim payments"
+        );
+        require!(
+            list.payout_mode == PayoutMode::Claim,
+            "List does not use claim mode"
+        );
+        require!(
+            list.merkle.is_none(),
+            "Merkle lists are claimed with claim_with_proof"
+        );
+        require!(
+            list.claim_deadline
+                .is_some_and(|deadline| env::block_timestamp() <= deadline),
+            "Claim deadline has passed"
+        );
+        require!(
+            list.payments
+                .iter()
+                .any(|p| p.recipient == recipient && matches!(p.status, PaymentStatus::Pending)),
+            format!("No pending payments for {} in this list", recipient)
+        );
+        if let Some(issue) = self.screening_issue(&list.submitter, &recipient) {
+            env::panic_str(&format!("Recipient {} {}", recipient, issue));
+        }
+
+        // Plain NEP-141 token: verify the recipient is registered before transferring
+        let Some(token_account) = list.token_id.ft_contract().cloned() else {
+            return PromiseOrValue::Promise(self.execute_claim(list_id, recipient));
+        };
+        PromiseOrValue::Promise(
+            Promise::new(token_account)
+                .function_call(
+                    "storage_balance_of".to_string(),
+                    format!(r#"{{"account_id":"{}"}}"#, recipient).into_bytes(),
+                    NearToken::from_yoctonear(0),
+                    GAS_FOR_STORAGE_BALANCE_OF,
+                )
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_CLAIM_CALLBACK)
+                        .on_claim_storage_checked(list_id, recipient),
+                ),
+        )
+    }
+
+    /// Callback after checking the claiming recipient's NEP-145 storage registration
+    #[private]
+    pub fn on_claim_storage_checked(
+        &mut self,
+        list_id: ListId,
+        recipient: AccountId,
+        #[callback_result] storage_balance: Result<Option<StorageBalance>, PromiseError>,
+    ) -> PromiseOrValue<U128> {
+        match storage_balance {
+            Ok(Some(_)) => PromiseOrValue::Promise(self.execute_claim(list_id, recipient)),
+            _ => {
+                let token_id = &self
+                    .payment_lists
+                    .get(&list_id)
+                    .expect("Payment list not found")
+                    .token_id;
+                log!(
+                    "Claim by {} for list {} skipped: recipient is not registered with {}",
+                    recipient,
+                    list_id,
+                    token_id
+                );
+                PromiseOrValue::Value(U128(0))
+            }
+        }
+    }
+
+    /// Return all unclaimed payments of a claim-mode list to the submitter
+    ///
+    /// Only the submitter can call this, and only after the claim deadline.
+    pub fn reclaim_unclaimed(&mut self, list_id: ListId) -> Promise {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can reclaim unclaimed payments"
+        );
+        require!(
+            matches!(list.status, ListStatus::Approved),
+            "List must be Approved to reclaim payments"
+        );
+        require!(
+            list.payout_mode == PayoutMode::Claim,
+            "List does not use claim mode"
+        );
+        require!(
+            list.claim_deadline
+                .is_some_and(|deadline| env::block_timestamp() > deadline),
+            "Claim deadline has not passed yet"
+        );
+
+        if list.merkle.is_some() {
+            return self.reclaim_merkle_unclaimed(list_id, list);
+        }
+
+        let block_height = env::block_height();
+        let mut indices = Vec::new();
+        let mut total: u128 = 0;
+        for (index, payment) in list.payments.iter_mut().enumerate() {
+            if matches!(payment.status, PaymentStatus::Pending) {
+                payment.status = PaymentStatus::Reclaimed { block_height };
+                total = total
+                    .checked_add(payment.amount.0)
+                    .expect("Total payment amount overflow");
+                indices.push(index as u32);
+            }
+        }
+        require!(!indices.is_empty(), "No unclaimed payments to reclaim");
+
+        let executor = list.executor();
+        let submitter = list.submitter.clone();
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Reclaimed {} unclaimed payments ({}) from list {} for {}",
+            indices.len(),
+            total,
+            list_id,
+            submitter
+        );
+
+        executor.transfer(&submitter, total, None).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .on_transfer_resolved(list_id, indices),
+        )
+    }
+
+    /// Callback after a claim or reclaim transfer. Failed transfers put the affected
+    /// records back to Pending so they can be claimed or reclaimed again.
+    #[private]
+    pub fn on_transfer_resolved(&mut self, list_id: ListId, indices: Vec<u32>) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        let amount = indices
+            .iter()
+            .map(|index| list.payments[*index as usize].amount.0)
+            .sum();
+        if transfer_delivered(&list, amount) {
+            return true;
+        }
+
+        for index in &indices {
+            list.payments[*index as usize].status = PaymentStatus::Pending;
+        }
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Transfer for list {} failed, {} payments reverted to Pending",
+            list_id,
+            indices.len()
+        );
+        false
+    }
+
+    /// Mark all pending payments of a recipient as paid and transfer their total
+    fn execute_claim(&mut self, list_id: ListId, recipient: AccountId) -> Promise {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        let block_height = env::block_height();
+        let mut indices = Vec::new();
+        let mut total: u128 = 0;
+        for (index, payment) in list.payments.iter_mut().enumerate() {
+            if payment.recipient == recipient && matches!(payment.status, PaymentStatus::Pending) {
+                payment.status = PaymentStatus::Paid { block_height };
+                total = total
+                    .checked_add(payment.amount.0)
+                    .expect("Total payment amount overflow");
+                indices.push(index as u32);
+            }
+        }
+        require!(
+            !indices.is_empty(),
+            format!("No pending payments for {} in this list", recipient)
+        );
+
+        // A single claimed payment keeps its memo, several are sent as one transfer without
+        let memo = match indices.as_slice() {
+            [index] => list.payments[*index as usize].memo.clone(),
+            _ => None,
+        };
+        let executor = list.executor();
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Claimed {} payments ({}) from list {} by {}",
+            indices.len(),
+            total,
+            list_id,
+            recipient
+        );
+
+        executor.transfer(&recipient, total, memo.as_deref()).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .on_transfer_resolved(list_id, indices),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{approve_list, contract_with_storage};
+    use crate::{ListOptions, PaymentInput, TokenId};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig};
+
+    const LIST_ID: &str = "c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1c1";
+    const DEADLINE: u64 = 1_000_000;
+
+    /// Submit and approve a native claim-mode list paying accounts(1) twice and accounts(2) once
+    fn setup_claim_list() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 10);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 100),
+            PaymentInput::new(accounts(2), 200),
+            PaymentInput::new(accounts(1), 300),
+        ];
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                payout_mode: PayoutMode::Claim,
+                claim_deadline: Some(DEADLINE),
+                ..Default::default()
+            }),
+        );
+
+        approve_list(&mut context, &mut contract, LIST_ID, 600);
+        (context, contract)
+    }
+
+    #[test]
+    fn test_claim_pays_all_records_of_recipient() {
+        let (mut context, mut contract) = setup_claim_list();
+
+        context.predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        contract.claim(LIST_ID.to_string()).detach();
+
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(
+            list.payments[0].status,
+            PaymentStatus::Paid { .. }
+        ));
+        assert!(matches!(list.payments[1].status, PaymentStatus::Pending));
+        assert!(matches!(
+            list.payments[2].status,
+            PaymentStatus::Paid { .. }
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "No pending payments for")]
+    fn test_claim_twice_fails() {
+        let (mut context, mut contract) = setup_claim_list();
+
+        context.predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        contract.claim(LIST_ID.to_string()).detach();
+        contract.claim(LIST_ID.to_string()).detach();
+    }
+
+    #[test]
+    #[should_panic(expected = "Claim deadline has passed")]
+    fn test_claim_after_deadline_fails() {
+        let (mut context, mut contract) = setup_claim_list();
+
+        context.predecessor_account_id(accounts(1));
+        context.block_timestamp(DEADLINE + 1);
+        testing_env!(context.build());
+        contract.claim(LIST_ID.to_string()).detach();
+    }
+
+    #[test]
+    #[should_panic(expected = "List uses claim mode")]
+    fn test_payout_batch_rejects_claim_list() {
+        let (mut context, mut contract) = setup_claim_list();
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Claim deadline has not passed yet")]
+    fn test_reclaim_before_deadline_fails() {
+        let (context, mut contract) = setup_claim_list();
+
+        testing_env!(context.build());
+        contract.reclaim_unclaimed(LIST_ID.to_string()).detach();
+    }
+
+    #[test]
+    fn test_reclaim_and_failed_transfer_revert() {
+        let (mut context, mut contract) = setup_claim_list();
+
+        context.predecessor_account_id(accounts(2));
+        testing_env!(context.build());
+        contract.claim(LIST_ID.to_string()).detach();
+
+        context.predecessor_account_id(accounts(0));
+        context.block_timestamp(DEADLINE + 1);
+        testing_env!(context.build());
+        contract.reclaim_unclaimed(LIST_ID.to_string()).detach();
+
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(
+            list.payments[0].status,
+            PaymentStatus::Reclaimed { .. }
+        ));
+        assert!(matches!(
+            list.payments[1].status,
+            PaymentStatus::Paid { .. }
+        ));
+        assert!(matches!(
+            list.payments[2].status,
+            PaymentStatus::Reclaimed { .. }
+        ));
+
+        // A failed refund transfer makes the records reclaimable again
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_transfer_resolved(LIST_ID.to_string(), vec![0, 2]));
+
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(list.payments[0].status, PaymentStatus::Pending));
+        assert!(matches!(list.payments[2].status, PaymentStatus::Pending));
+    }
+}
@@ -0,0 +1,262 @@
+// External chain destinations of PoA withdrawals
+//
+// PoA bridge tokens (".omft.near") held in NEAR Intents are withdrawn to an address on
+// their origin chain through a `WITHDRAW_TO:<address>` memo. Such addresses are not NEAR
+// account IDs (EIP-55 addresses are mixed case, for example), so a payment can carry a
+// `destination` with the chain and address instead. Addresses are checked when the list is
+// submitted:
+// - Bitcoin: bech32 / bech32m segwit addresses (`bc1...`) or base58check P2PKH / P2SH
+// - EVM chains: `0x` + 40 hex digits, with the EIP-55 checksum if mixed case
+// - Solana: base58 encoded 32-byte public key
+use near_sdk::{env, near, require};
+
+use crate::{IntentsDelivery, PaymentInput, TokenId};
+
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+/// Chain of an external withdrawal address
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chain {
+    Bitcoin,
+    Ethereum,
+    Base,
+    Arbitrum,
+    Solana,
+}
+
+impl Chain {
+    /// Origin chain of a PoA token, from the prefix of its account ID
+    /// (e.g. "btc.omft.near", "eth-0xa0b8...omft.near")
+    fn of_poa_token(token: &str) -> Option<Self> {
+        match token.split(['.', '-']).next()? {
+            "btc" => Some(Chain::Bitcoin),
+            "eth" => Some(Chain::Ethereum),
+            "base" => Some(Chain::Base),
+            "arb" => Some(Chain::Arbitrum),
+            "sol" => Some(Chain::Solana),
+            _ => None,
+        }
+    }
+
+    fn is_valid_address(self, address: &str) -> bool {
+        match self {
+            Chain::Bitcoin => is_bech32_segwit_address(address) || is_base58check_address(address),
+            Chain::Ethereum | Chain::Base | Chain::Arbitrum => is_evm_address(address),
+            Chain::Solana => near_sdk::bs58::decode(address)
+                .into_vec()
+                .is_ok_and(|bytes| bytes.len() == 32),
+        }
+    }
+}
+
+/// Address on an external chain that a PoA payment is withdrawn to
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Destination {
+    pub chain: Chain,
+    pub address: String,
+}
+
+/// Check a bech32 (witness v0) or bech32m (v1+) Bitcoin mainnet address
+fn is_bech32_segwit_address(address: &str) -> bool {
+    if address.len() > 90
+        || (address.bytes().any(|b| b.is_ascii_lowercase())
+            && address.bytes().any(|b| b.is_ascii_uppercase()))
+    {
+        return false;
+    }
+    let address = address.to_ascii_lowercase();
+    let Some((hrp, data)) = address.rsplit_once('1') else {
+        return false;
+    };
+    if hrp != "bc" || data.len() < 7 {
+        return false;
+    }
+    let Some(values) = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|x| *x == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()
+    else {
+        return false;
+    };
+
+    let hrp_expanded = hrp
+        .bytes()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|c| c & 31));
+    let checksum = bech32_polymod(hrp_expanded.chain(values.iter().copied()));
+    let version = values[0];
+    let expected = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    if version > 16 || checksum != expected {
+        return false;
+    }
+
+    // Witness program: 5-bit groups between the version and the 6 checksum characters
+    let program_bits = (values.len() - 7) * 5;
+    let program_len = program_bits / 8;
+    let padding_ok =
+        program_bits % 8 < 5 && values[values.len() - 7] & ((1 << (program_bits % 8)) - 1) == 0;
+    padding_ok
+        && match version {
+            0 => program_len == 20 || program_len == 32,
+            _ => (2..=40).contains(&program_len),
+        }
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    values.fold(1u32, |chk, value| {
+        let top = chk >> 25;
+        let chk = ((chk & 0x1ff_ffff) << 5) ^ value as u32;
+        GENERATOR
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (top >> i) & 1 == 1)
+            .fold(chk, |chk, (_, g)| chk ^ g)
+    })
+}
+
+/// Check a legacy Bitcoin mainnet address (P2PKH "1..." or P2SH "3...")
+fn is_base58check_address(address: &str) -> bool {
+    let Ok(bytes) = near_sdk::bs58::decode(address).into_vec() else {
+        return false;
+    };
+    if bytes.len() != 25 || !matches!(bytes[0], 0x00 | 0x05) {
+        return false;
+    }
+    let (payload, checksum) = bytes.split_at(21);
+    env::sha256(env::sha256(payload))[..4] == *checksum
+}
+
+/// Check an EVM address, verifying the EIP-55 checksum of mixed-case addresses
+fn is_evm_address(address: &str) -> bool {
+    let Some(hex) = address.strip_prefix("0x") else {
+        return false;
+    };
+    if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return false;
+    }
+    let has_lower = hex.bytes().any(|b| b.is_ascii_lowercase());
+    let has_upper = hex.bytes().any(|b| b.is_ascii_uppercase());
+    if !(has_lower && has_upper) {
+        return true;
+    }
+
+    let hash = env::keccak256(hex.to_ascii_lowercase().as_bytes());
+    hex.bytes().enumerate().all(|(i, c)| {
+        let nibble = (hash[i / 2] >> (4 * (1 - i % 2))) & 0x0f;
+        !c.is_ascii_alphabetic() || c.is_ascii_uppercase() == (nibble >= 8)
+    })
+}
+
+/// Check the destinations of a list's payments: they are only allowed for PoA tokens
+/// withdrawn off the intents ledger, must match the token's chain and be valid addresses
+pub(crate) fn validate_destinations(
+    token_id: &TokenId,
+    delivery: IntentsDelivery,
+    payments: &[PaymentInput],
+) {
+    for payment in payments {
+        let Some(destination) = &payment.destination else {
+            continue;
+        };
+        let poa_token = match payment.token_id.as_ref().unwrap_or(token_id) {
+            TokenId::Intents { token, .. }
+                if token.as_str().ends_with(".omft.near")
+                    && delivery == IntentsDelivery::Withdraw =>
+            {
+                Some(token)
+            }
+            _ => None,
+        };
+        let Some(token) = poa_token else {
+            env::panic_str(
+                "Destinations are only supported for PoA tokens withdrawn from NEAR Intents",
+            );
+        };
+        if let Some(chain) = Chain::of_poa_token(token.as_str()) {
+            require!(
+                destination.chain == chain,
+                format!(
+                    "Destination of {} is on {:?}, token {} is on {:?}",
+                    payment.recipient, destination.chain, token, chain
+                )
+            );
+        }
+        require!(
+            destination.chain.is_valid_address(&destination.address),
+            format!(
+                "Invalid {:?} address {} for {}",
+                destination.chain, destination.address, payment.recipient
+            )
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    #[test]
+    fn test_bitcoin_addresses() {
+        testing_env!(VMContextBuilder::new().build());
+        // bech32 (witness v0), bech32m (taproot) and legacy P2PKH
+        assert!(Chain::Bitcoin.is_valid_address("bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh"));
+        assert!(Chain::Bitcoin
+            .is_valid_address("bc1p5d7rjq7g6rdk2yhzks9smlaqtedr4dekq08ge8ztwac72sfr9rusxg3297"));
+        assert!(Chain::Bitcoin.is_valid_address("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa"));
+
+        // Wrong checksum, testnet prefix, mixed case, broken base58check
+        assert!(!Chain::Bitcoin.is_valid_address("bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlj"));
+        assert!(!Chain::Bitcoin.is_valid_address("tb1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh"));
+        assert!(!Chain::Bitcoin.is_valid_address("bc1Qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh"));
+        assert!(!Chain::Bitcoin.is_valid_address("1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNb"));
+    }
+
+    #[test]
+    fn test_evm_and_solana_addresses() {
+        testing_env!(VMContextBuilder::new().build());
+        // EIP-55 examples
+        assert!(Chain::Ethereum.is_valid_address("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"));
+        assert!(Chain::Base.is_valid_address("0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359"));
+        assert!(Chain::Ethereum.is_valid_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+        assert!(!Chain::Ethereum.is_valid_address("0x5AAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"));
+        assert!(!Chain::Ethereum.is_valid_address("5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+
+        assert!(Chain::Solana.is_valid_address("So11111111111111111111111111111111111111112"));
+        assert!(!Chain::Solana.is_valid_address("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed"));
+    }
+
+    #[test]
+    #[should_panic(expected = "is on Ethereum, token btc.omft.near is on Bitcoin")]
+    fn test_destination_chain_must_match_token() {
+        testing_env!(VMContextBuilder::new().build());
+        validate_destinations(
+            &"nep141:btc.omft.near".parse().unwrap(),
+            IntentsDelivery::Withdraw,
+            &[PaymentInput {
+                destination: Some(Destination {
+                    chain: Chain::Ethereum,
+                    address: "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string(),
+                }),
+                ..PaymentInput::new(accounts(1), 1)
+            }],
+        );
+    }
+}
@@ -0,0 +1,264 @@
+// Duplicate recipients in a submitted list
+//
+// Payments are duplicates when they pay the same recipient the same token (and, where set,
+// to the same destination or with the same NFT). By default they are kept as separate
+// records; `ListOptions::duplicates` can instead reject the list, naming every duplicate
+// and its positions, or merge them into one record per recipient. A merged record adds up
+// the amounts, keeps the memo only if all lines share it, joins the distinct references and
+// lists the positions of the payments it replaces in `merged_lines`. The joined reference is
+// held to the same `MAX_PAYMENT_FIELD_LENGTH` as any other, so a merge that would exceed it
+// is rejected. The API applies the
+// same rules before hashing a list, so its list_id covers the records actually stored.
+use near_sdk::json_types::U128;
+use near_sdk::{near, require};
+use std::collections::HashMap;
+
+use crate::{PaymentInput, PaymentRecord, PaymentStatus, MAX_PAYMENT_FIELD_LENGTH};
+
+/// What `submit_list` does with duplicate payments
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Keep every payment as its own record
+    #[default]
+    Allow,
+    /// Reject the list, listing the duplicates
+    Reject,
+    /// Merge the duplicates of each recipient into one record
+    Merge,
+}
+
+/// Positions of the payments in each group of duplicates, in order of first appearance
+pub(crate) fn duplicate_groups(payments: &[PaymentInput]) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_recipient: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (index, payment) in payments.iter().enumerate() {
+        let candidates = by_recipient.entry(payment.recipient.as_str()).or_default();
+        let existing = candidates.iter().copied().find(|group| {
+            let first = &payments[groups[*group][0]];
+            first.token_id == payment.token_id
+                && first.destination == payment.destination
+                && first.nft_token_id == payment.nft_token_id
+        });
+        match existing {
+            Some(group) => groups[group].push(index),
+            None => {
+                candidates.push(groups.len());
+                groups.push(vec![index]);
+            }
+        }
+    }
+    groups.retain(|group| group.len() > 1);
+    groups
+}
+
+/// Panic if any recipient is paid more than once, listing the positions of each duplicate
+pub(crate) fn reject_duplicates(payments: &[PaymentInput]) {
+    let groups = duplicate_groups(payments);
+    require!(
+        groups.is_empty(),
+        format!(
+            "Duplicate payments: {}",
+            groups
+                .iter()
+                .map(|group| format!(
+                    "{} at {}",
+                    payments[group[0]].recipient,
+                    group
+                        .iter()
+                        .map(usize::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    );
+}
+
+/// Storage credits charged for the records `into_records` stores for `payments`
+pub(crate) fn storage_credits(payments: &[PaymentInput], policy: DuplicatePolicy) -> u128 {
+    match policy {
+        DuplicatePolicy::Merge => into_records(payments.to_vec(), policy)
+            .iter()
+            .map(PaymentRecord::storage_credits)
+            .sum(),
+        DuplicatePolicy::Allow | DuplicatePolicy::Reject => {
+            payments.iter().map(PaymentInput::storage_credits).sum()
+        }
+    }
+}
+
+/// Convert payments into Pending records, merging duplicates if `policy` is Merge.
+/// A merged record takes the place of the first of its payments.
+pub(crate) fn into_records(
+    payments: Vec<PaymentInput>,
+    policy: DuplicatePolicy,
+) -> Vec<PaymentRecord> {
+    let groups = match policy {
+        DuplicatePolicy::Merge => duplicate_groups(&payments),
+        DuplicatePolicy::Allow | DuplicatePolicy::Reject => Vec::new(),
+    };
+
+    let mut records: Vec<Option<PaymentRecord>> = payments
+        .into_iter()
+        .map(|input| {
+            Some(PaymentRecord {
+                recipient: input.recipient,
+                amount: input.amount,
+                memo: input.memo,
+                reference: input.reference,
+                nft_token_id: input.nft_token_id,
+                destination: input.destination,
+                token_id: input.token_id,
+                merged_lines: None,
+                net_amount: None,
+                withheld_amount: None,
+                status: PaymentStatus::Pending,
+            })
+        })
+        .collect();
+
+    for group in groups {
+        let lines: Vec<PaymentRecord> = group
+            .iter()
+            .map(|index| records[*index].take().expect("Payment in one group"))
+            .collect();
+        let mut merged = lines[0].clone();
+        merged.amount = U128(
+            lines
+                .iter()
+                .try_fold(0u128, |acc, line| acc.checked_add(line.amount.0))
+                .expect("Total payment amount overflow"),
+        );
+        if lines.iter().any(|line| line.memo != merged.memo) {
+            merged.memo = None;
+        }
+        let mut references: Vec<&str> = Vec::new();
+        for reference in lines.iter().filter_map(|line| line.reference.as_deref()) {
+            if !references.contains(&reference) {
+                references.push(reference);
+            }
+        }
+        merged.reference = (!references.is_empty()).then(|| references.join(", "));
+        require!(
+            merged
+                .reference
+                .as_ref()
+                .is_none_or(|reference| reference.len() <= MAX_PAYMENT_FIELD_LENGTH),
+            format!(
+                "Merged reference of {} exceeds {} bytes",
+                merged.recipient, MAX_PAYMENT_FIELD_LENGTH
+            )
+        );
+        merged.merged_lines = Some(group.iter().map(|index| *index as u32).collect());
+        records[group[0]] = Some(merged);
+    }
+
+    records.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{BulkPaymentContract, ListOptions};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::AccountId;
+
+    const LIST_ID: &str = "c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5c5";
+
+    fn payment(recipient: AccountId, amount: u128, reference: Option<&str>) -> PaymentInput {
+        PaymentInput {
+            memo: Some("March payroll".to_string()),
+            reference: reference.map(str::to_string),
+            ..PaymentInput::new(recipient, amount)
+        }
+    }
+
+    fn payments() -> Vec<PaymentInput> {
+        vec![
+            payment(accounts(1), 100, Some("INV-1")),
+            payment(accounts(2), 200, None),
+            payment(accounts(1), 300, Some("INV-2")),
+            payment(accounts(3), 400, None),
+            payment(accounts(1), 500, Some("INV-1")),
+            payment(accounts(3), 600, None),
+        ]
+    }
+
+    fn submit(policy: DuplicatePolicy) -> BulkPaymentContract {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 12);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            crate::TokenId::Native,
+            payments(),
+            None,
+            Some(ListOptions {
+                duplicates: policy,
+                ..Default::default()
+            }),
+        );
+        contract
+    }
+
+    #[test]
+    fn test_merge_duplicates_keeps_line_references() {
+        let contract = submit(DuplicatePolicy::Merge);
+        let list = contract.view_list(LIST_ID.to_string());
+        assert_eq!(list.payments.len(), 3);
+
+        let bob = &list.payments[0];
+        assert_eq!(bob.recipient, accounts(1));
+        assert_eq!(bob.amount, U128(900));
+        assert_eq!(bob.memo.as_deref(), Some("March payroll"));
+        assert_eq!(bob.reference.as_deref(), Some("INV-1, INV-2"));
+        assert_eq!(bob.merged_lines, Some(vec![0, 2, 4]));
+
+        assert_eq!(list.payments[1].merged_lines, None);
+        assert_eq!(list.payments[2].amount, U128(1000));
+        assert_eq!(list.payments[2].merged_lines, Some(vec![3, 5]));
+
+        // Only the merged records are charged, each with a credit for its memo and lines
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "Merged reference of bob exceeds 128 bytes")]
+    fn test_merged_reference_is_bounded() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 3);
+
+        let reference = "x".repeat(MAX_PAYMENT_FIELD_LENGTH / 2);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            crate::TokenId::Native,
+            vec![
+                payment(accounts(1), 100, Some(&reference)),
+                payment(accounts(1), 200, Some(&reference.replace('x', "y"))),
+            ],
+            None,
+            Some(ListOptions {
+                duplicates: DuplicatePolicy::Merge,
+                ..Default::default()
+            }),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Duplicate payments: bob at 0, 2, 4; danny at 3, 5")]
+    fn test_reject_duplicates_lists_them() {
+        submit(DuplicatePolicy::Reject);
+    }
+
+    #[test]
+    fn test_different_tokens_are_not_duplicates() {
+        let mut payments = payments();
+        payments[2].token_id = Some("usdc.near".parse().unwrap());
+        let groups = duplicate_groups(&payments);
+        assert_eq!(groups, vec![vec![0, 4], vec![3, 5]]);
+    }
+}
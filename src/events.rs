@@ -0,0 +1,22 @@
+// NEP-297 events
+//
+// State changes that indexers and compliance tooling follow are logged as
+// `EVENT_JSON:{"standard":"bulk_payment","version":"1.0.0","event":...,"data":[...]}`.
+use near_sdk::env;
+use near_sdk::serde_json::{json, Value};
+
+const EVENT_STANDARD: &str = "bulk_payment";
+const EVENT_VERSION: &str = "1.0.0";
+
+/// Log a NEP-297 event with a single data entry
+pub(crate) fn emit(event: &str, data: Value) {
+    env::log_str(&format!(
+        "EVENT_JSON:{}",
+        json!({
+            "standard": EVENT_STANDARD,
+            "version": EVENT_VERSION,
+            "event": event,
+            "data": [data],
+        })
+    ));
+}
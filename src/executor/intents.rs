@@ -0,0 +1,244 @@
+// NEAR Intents payouts: `ft_withdraw` of the token from the intents contract, or an
+// `mt_transfer` within it when the list keeps payments on the intents ledger
+//
+// PoA bridge tokens (".omft.near") are withdrawn to the token contract itself with a
+// `WITHDRAW_TO:<address>` memo, which sends them to the payment's `destination` address,
+// or to the recipient if it has none. That memo takes the place of the payment memo.
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::{self, json};
+use near_sdk::{AccountId, Gas, NearToken, Promise, PromiseResult};
+
+use super::{PayoutCall, PayoutExecutor, GAS_FOR_FT_TRANSFER};
+use crate::{IntentsDelivery, PaymentRecord};
+
+/// Gas attached to each `mt_transfer` call; it only moves balances inside intents.near
+const GAS_FOR_MT_TRANSFER: Gas = Gas::from_tgas(15);
+
+pub(crate) struct IntentsExecutor {
+    pub intents_contract: AccountId,
+    pub token: AccountId,
+    pub delivery: IntentsDelivery,
+    /// Gas attached to each call, the default of the delivery if unset
+    pub gas: Option<Gas>,
+}
+
+impl IntentsExecutor {
+    fn is_poa(&self) -> bool {
+        self.token.as_str().ends_with(".omft.near")
+    }
+
+    fn withdraw(&self, args: serde_json::Value) -> Promise {
+        Promise::new(self.intents_contract.clone()).function_call(
+            "ft_withdraw".to_string(),
+            args.to_string().into_bytes(),
+            NearToken::from_yoctonear(1),
+            self.attached_gas(),
+        )
+    }
+
+    /// PoA withdrawal to an address on the token's origin chain
+    fn withdraw_to(&self, address: &str, amount: u128) -> Promise {
+        self.withdraw(json!({
+            "token": self.token,
+            "receiver_id": self.token,
+            "amount": U128(amount),
+            "memo": format!("WITHDRAW_TO:{}", address),
+        }))
+    }
+
+    /// NEP-245 transfer of the token to another account on the intents ledger
+    fn mt_transfer(&self, receiver_id: &AccountId, amount: u128, memo: Option<&str>) -> Promise {
+        let args = json!({
+            "receiver_id": receiver_id,
+            "token_id": format!("nep141:{}", self.token),
+            "amount": U128(amount),
+            "memo": memo,
+        });
+
+        Promise::new(self.intents_contract.clone()).function_call(
+            "mt_transfer".to_string(),
+            args.to_string().into_bytes(),
+            NearToken::from_yoctonear(1),
+            self.attached_gas(),
+        )
+    }
+}
+
+impl PayoutExecutor for IntentsExecutor {
+    fn attached_gas(&self) -> Gas {
+        self.gas.unwrap_or(match self.delivery {
+            IntentsDelivery::Withdraw => GAS_FOR_FT_TRANSFER,
+            IntentsDelivery::Transfer => GAS_FOR_MT_TRANSFER,
+        })
+    }
+
+    fn transfer(&self, receiver_id: &AccountId, amount: u128, memo: Option<&str>) -> Promise {
+        if self.delivery == IntentsDelivery::Transfer {
+            return self.mt_transfer(receiver_id, amount, memo);
+        }
+        self.withdraw(json!({
+            "token": self.token,
+            "receiver_id": receiver_id,
+            "amount": U128(amount),
+            "memo": memo,
+        }))
+    }
+
+    fn pay(&self, recipient: &AccountId, amount: u128, memo: Option<&str>) -> Promise {
+        if !self.is_poa() || self.delivery == IntentsDelivery::Transfer {
+            return self.transfer(recipient, amount, memo);
+        }
+        self.withdraw_to(recipient.as_str(), amount)
+    }
+
+    fn pay_record(&self, payment: &PaymentRecord) -> Promise {
+        match &payment.destination {
+            Some(destination) if self.is_poa() && self.delivery == IntentsDelivery::Withdraw => {
+                self.withdraw_to(&destination.address, payment.amount.0)
+            }
+            _ => self.pay(
+                &payment.recipient,
+                payment.amount.0,
+                payment.memo.as_deref(),
+            ),
+        }
+    }
+
+    fn payout_call(&self, _payment: &PaymentRecord) -> PayoutCall {
+        PayoutCall {
+            receiver_id: self.intents_contract.clone(),
+            method: Some(match self.delivery {
+                IntentsDelivery::Withdraw => "ft_withdraw",
+                IntentsDelivery::Transfer => "mt_transfer",
+            }),
+        }
+    }
+
+    /// `ft_withdraw` resolves to the amount withdrawn, which is 0 if the token
+    /// transfer failed and the intents balance was refunded. `mt_transfer` either
+    /// succeeds or fails as a whole.
+    fn is_delivered(&self, result: &PromiseResult, amount: u128) -> bool {
+        match result {
+            PromiseResult::Successful(_) if self.delivery == IntentsDelivery::Transfer => true,
+            PromiseResult::Successful(bytes) => serde_json::from_slice::<U128>(bytes)
+                .map_or(true, |withdrawn| withdrawn.0 == amount),
+            PromiseResult::Failed => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::test_utils::scheduled_call;
+    use crate::{Chain, Destination, PaymentStatus};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn executor(token: &str) -> IntentsExecutor {
+        IntentsExecutor {
+            intents_contract: "intents.near".parse().unwrap(),
+            token: token.parse().unwrap(),
+            delivery: IntentsDelivery::Withdraw,
+            gas: None,
+        }
+    }
+
+    #[test]
+    fn test_withdraw_to_near_account() {
+        testing_env!(VMContextBuilder::new().build());
+
+        executor("wrap.near")
+            .pay(&accounts(1), 7, Some("memo"))
+            .detach();
+
+        let (receiver_id, method, args, _) = scheduled_call();
+        assert_eq!(receiver_id.as_str(), "intents.near");
+        assert_eq!(method, "ft_withdraw");
+        assert_eq!(
+            args,
+            json!({ "token": "wrap.near", "receiver_id": accounts(1), "amount": "7", "memo": "memo" })
+        );
+    }
+
+    #[test]
+    fn test_poa_withdraw_uses_withdraw_to_memo() {
+        testing_env!(VMContextBuilder::new().build());
+        let btc = executor("btc.omft.near");
+        let address: AccountId = "bc1qxy2kgdygjrsqtzq2n0yrf2493p83kkfjhx0wlh"
+            .parse()
+            .unwrap();
+
+        btc.pay(&address, 7, Some("memo")).detach();
+
+        let (_, _, args, _) = scheduled_call();
+        assert_eq!(args["receiver_id"], "btc.omft.near");
+        assert_eq!(args["memo"], format!("WITHDRAW_TO:{}", address));
+    }
+
+    #[test]
+    fn test_delivery_checks_withdrawn_amount() {
+        let usdc = executor("usdc.near");
+        assert!(usdc.is_delivered(&PromiseResult::Successful(b"\"7\"".to_vec()), 7));
+        assert!(!usdc.is_delivered(&PromiseResult::Successful(b"\"0\"".to_vec()), 7));
+        assert!(!usdc.is_delivered(&PromiseResult::Failed, 7));
+    }
+
+    #[test]
+    fn test_transfer_delivery_stays_on_intents_ledger() {
+        testing_env!(VMContextBuilder::new().build());
+        let btc = IntentsExecutor {
+            delivery: IntentsDelivery::Transfer,
+            ..executor("btc.omft.near")
+        };
+        assert_eq!(btc.attached_gas(), GAS_FOR_MT_TRANSFER);
+
+        btc.pay(&accounts(1), 7, Some("memo")).detach();
+
+        let (receiver_id, method, args, deposit) = scheduled_call();
+        assert_eq!(receiver_id.as_str(), "intents.near");
+        assert_eq!(method, "mt_transfer");
+        assert_eq!(
+            args,
+            json!({
+                "receiver_id": accounts(1),
+                "token_id": "nep141:btc.omft.near",
+                "amount": "7",
+                "memo": "memo",
+            })
+        );
+        assert_eq!(deposit, NearToken::from_yoctonear(1));
+        assert!(btc.is_delivered(&PromiseResult::Successful(vec![]), 7));
+    }
+
+    #[test]
+    fn test_poa_withdraw_to_destination() {
+        testing_env!(VMContextBuilder::new().build());
+        let eth = executor("eth.omft.near");
+
+        eth.pay_record(&PaymentRecord {
+            recipient: accounts(1),
+            amount: U128(7),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: Some(Destination {
+                chain: Chain::Ethereum,
+                address: "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed".to_string(),
+            }),
+            token_id: None,
+            merged_lines: None,
+            net_amount: None,
+            withheld_amount: None,
+            status: PaymentStatus::Pending,
+        })
+        .detach();
+
+        let (_, _, args, _) = scheduled_call();
+        assert_eq!(args["receiver_id"], "eth.omft.near");
+        assert_eq!(
+            args["memo"],
+            "WITHDRAW_TO:0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed"
+        );
+    }
+}
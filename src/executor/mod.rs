@@ -0,0 +1,152 @@
+// Token-specific payout logic
+//
+// Every kind of token a list can be paid in has a `PayoutExecutor`, which knows the gas a
+// payment needs, how to build the transfer promise and how to read its result. Callers get
+// the executor of a list with `PaymentList::executor` and never match on the token
+// themselves, so supporting a new asset type means adding a `TokenId` variant and one
+// module here.
+use near_sdk::{env, AccountId, Gas, Promise, PromiseResult};
+
+use crate::gas::gas_override;
+use crate::{IntentsDelivery, PaymentList, PaymentRecord, TokenId};
+
+mod intents;
+mod native;
+mod nep141;
+mod nft;
+
+pub(crate) use intents::IntentsExecutor;
+pub(crate) use native::NativeExecutor;
+pub(crate) use nep141::Nep141Executor;
+pub(crate) use nft::NftExecutor;
+
+/// Gas attached to each `ft_transfer` / `ft_withdraw` call unless the list overrides it
+const GAS_FOR_FT_TRANSFER: Gas = Gas::from_tgas(50);
+
+/// Gas budgeted for the contract's own work per payment until it has been measured
+pub(crate) const DEFAULT_PAYMENT_OVERHEAD: Gas = Gas::from_tgas(3);
+
+/// Receiver and method of the call paying a record, as shown by `simulate_payout_batch`
+pub(crate) struct PayoutCall {
+    pub receiver_id: AccountId,
+    /// Function called on the receiver, `None` for a plain NEAR transfer
+    pub method: Option<&'static str>,
+}
+
+/// Pays out amounts of one token
+pub(crate) trait PayoutExecutor {
+    /// Gas attached to the call paying one record, none for plain transfers
+    fn attached_gas(&self) -> Gas;
+
+    /// Gas to reserve for each payment when nothing has been measured yet
+    fn gas_per_payment(&self) -> Gas {
+        self.attached_gas().saturating_add(DEFAULT_PAYMENT_OVERHEAD)
+    }
+
+    /// Build the promise transferring `amount` to a NEAR account. The memo is passed
+    /// through where the token supports one.
+    fn transfer(&self, receiver_id: &AccountId, amount: u128, memo: Option<&str>) -> Promise;
+
+    /// Build the promise paying a list recipient; same as `transfer` unless the token
+    /// pays recipients in some other way
+    fn pay(&self, recipient: &AccountId, amount: u128, memo: Option<&str>) -> Promise {
+        self.transfer(recipient, amount, memo)
+    }
+
+    /// Build the promise paying out a record of a payment list
+    fn pay_record(&self, payment: &PaymentRecord) -> Promise {
+        self.pay(
+            &payment.recipient,
+            payment.amount.0,
+            payment.memo.as_deref(),
+        )
+    }
+
+    /// Describe the call `pay_record` makes for `payment`
+    fn payout_call(&self, payment: &PaymentRecord) -> PayoutCall;
+
+    /// Whether the promise built by `transfer` or `pay` delivered `amount`
+    fn is_delivered(&self, result: &PromiseResult, _amount: u128) -> bool {
+        matches!(result, PromiseResult::Successful(_))
+    }
+}
+
+impl PaymentList {
+    /// Executor paying out the token of this list
+    pub(crate) fn executor(&self) -> Box<dyn PayoutExecutor> {
+        self.token_executor(&self.token_id)
+    }
+
+    /// Executor paying out `token_id` for this list, with the list's attached gas
+    pub(crate) fn token_executor(&self, token_id: &TokenId) -> Box<dyn PayoutExecutor> {
+        executor_with_gas(
+            token_id,
+            self.intents_delivery,
+            gas_override(self.attached_gas.as_deref(), token_id),
+        )
+    }
+}
+
+/// Executor paying out `token_id`, with `delivery` applying to NEAR Intents tokens
+pub(crate) fn executor(token_id: &TokenId, delivery: IntentsDelivery) -> Box<dyn PayoutExecutor> {
+    executor_with_gas(token_id, delivery, None)
+}
+
+/// Executor paying out `token_id`, attaching `gas` to each call instead of the default
+pub(crate) fn executor_with_gas(
+    token_id: &TokenId,
+    delivery: IntentsDelivery,
+    gas: Option<Gas>,
+) -> Box<dyn PayoutExecutor> {
+    match token_id {
+        TokenId::Native => Box::new(NativeExecutor),
+        TokenId::Nep141(token_contract) => Box::new(Nep141Executor {
+            token_contract: token_contract.clone(),
+            gas,
+        }),
+        TokenId::Intents { contract, token } => Box::new(IntentsExecutor {
+            intents_contract: contract.clone(),
+            token: token.clone(),
+            delivery,
+            gas,
+        }),
+        TokenId::Nft(nft_contract) => Box::new(NftExecutor {
+            nft_contract: nft_contract.clone(),
+            gas,
+        }),
+    }
+}
+
+/// In a callback of a transfer of `amount` from `list`, check whether it was delivered
+pub(crate) fn transfer_delivered(list: &PaymentList, amount: u128) -> bool {
+    list.executor()
+        .is_delivered(&env::promise_result(0), amount)
+}
+
+#[cfg(test)]
+mod test_utils {
+    use near_sdk::mock::MockAction;
+    use near_sdk::serde_json::Value;
+    use near_sdk::test_utils::get_created_receipts;
+    use near_sdk::{AccountId, NearToken};
+
+    /// Receiver, method, JSON args and deposit of the single function call scheduled so far
+    pub fn scheduled_call() -> (AccountId, String, Value, NearToken) {
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        match &receipts[0].actions[..] {
+            [MockAction::FunctionCallWeight {
+                method_name,
+                args,
+                attached_deposit,
+                ..
+            }] => (
+                receipts[0].receiver_id.clone(),
+                String::from_utf8(method_name.clone()).unwrap(),
+                near_sdk::serde_json::from_slice(args).unwrap(),
+                *attached_deposit,
+            ),
+            actions => panic!("Expected a single function call, got {:?}", actions),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Native NEAR payouts: plain transfers, which cannot carry a memo
+use near_sdk::{AccountId, Gas, NearToken, Promise};
+
+use super::{PayoutCall, PayoutExecutor};
+use crate::PaymentRecord;
+
+pub(crate) struct NativeExecutor;
+
+impl PayoutExecutor for NativeExecutor {
+    fn attached_gas(&self) -> Gas {
+        Gas::from_tgas(0)
+    }
+
+    fn transfer(&self, receiver_id: &AccountId, amount: u128, _memo: Option<&str>) -> Promise {
+        Promise::new(receiver_id.clone()).transfer(NearToken::from_yoctonear(amount))
+    }
+
+    fn payout_call(&self, payment: &PaymentRecord) -> PayoutCall {
+        PayoutCall {
+            receiver_id: payment.recipient.clone(),
+            method: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult};
+
+    #[test]
+    fn test_native_transfer() {
+        testing_env!(VMContextBuilder::new().build());
+
+        NativeExecutor
+            .pay(&accounts(1), 1_000, Some("ignored"))
+            .detach();
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, accounts(1));
+        assert!(matches!(
+            receipts[0].actions[..],
+            [MockAction::Transfer { deposit, .. }] if deposit == NearToken::from_yoctonear(1_000)
+        ));
+        assert!(NativeExecutor.is_delivered(&PromiseResult::Successful(vec![]), 1_000));
+        assert!(!NativeExecutor.is_delivered(&PromiseResult::Failed, 1_000));
+    }
+}
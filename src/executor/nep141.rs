@@ -0,0 +1,69 @@
+// NEP-141 payouts: `ft_transfer` on the token contract
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{AccountId, Gas, NearToken, Promise};
+
+use super::{PayoutCall, PayoutExecutor, GAS_FOR_FT_TRANSFER};
+use crate::PaymentRecord;
+
+pub(crate) struct Nep141Executor {
+    pub token_contract: AccountId,
+    /// Gas attached to `ft_transfer`, `GAS_FOR_FT_TRANSFER` if unset
+    pub gas: Option<Gas>,
+}
+
+impl PayoutExecutor for Nep141Executor {
+    fn attached_gas(&self) -> Gas {
+        self.gas.unwrap_or(GAS_FOR_FT_TRANSFER)
+    }
+
+    fn transfer(&self, receiver_id: &AccountId, amount: u128, memo: Option<&str>) -> Promise {
+        let args = json!({
+            "receiver_id": receiver_id,
+            "amount": U128(amount),
+            "memo": memo,
+        });
+
+        Promise::new(self.token_contract.clone()).function_call(
+            "ft_transfer".to_string(),
+            args.to_string().into_bytes(),
+            NearToken::from_yoctonear(1),
+            self.attached_gas(),
+        )
+    }
+
+    fn payout_call(&self, _payment: &PaymentRecord) -> PayoutCall {
+        PayoutCall {
+            receiver_id: self.token_contract.clone(),
+            method: Some("ft_transfer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::test_utils::scheduled_call;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    #[test]
+    fn test_ft_transfer_with_memo() {
+        testing_env!(VMContextBuilder::new().build());
+        let executor = Nep141Executor {
+            token_contract: "usdc.near".parse().unwrap(),
+            gas: None,
+        };
+
+        executor.pay(&accounts(1), 250, Some("INV-1")).detach();
+
+        let (receiver_id, method, args, deposit) = scheduled_call();
+        assert_eq!(receiver_id.as_str(), "usdc.near");
+        assert_eq!(method, "ft_transfer");
+        assert_eq!(
+            args,
+            json!({ "receiver_id": accounts(1), "amount": "250", "memo": "INV-1" })
+        );
+        assert_eq!(deposit, NearToken::from_yoctonear(1));
+    }
+}
@@ -0,0 +1,110 @@
+// NEP-171 NFT payouts: `nft_transfer` of the token listed in each payment
+//
+// NFT lists only pay out their own records, one token each. Amount-based transfers (claims,
+// reclaims, Merkle lists) are not available for NFTs and are refused when a list is submitted.
+use near_sdk::serde_json::json;
+use near_sdk::{env, AccountId, Gas, NearToken, Promise};
+
+use super::{PayoutCall, PayoutExecutor};
+use crate::PaymentRecord;
+
+/// Gas attached to each `nft_transfer` call unless the list overrides it
+const GAS_FOR_NFT_TRANSFER: Gas = Gas::from_tgas(30);
+
+pub(crate) struct NftExecutor {
+    pub nft_contract: AccountId,
+    /// Gas attached to `nft_transfer`, `GAS_FOR_NFT_TRANSFER` if unset
+    pub gas: Option<Gas>,
+}
+
+impl NftExecutor {
+    /// Build the promise transferring one NFT
+    pub fn transfer_token(
+        &self,
+        receiver_id: &AccountId,
+        token_id: &str,
+        memo: Option<&str>,
+    ) -> Promise {
+        let args = json!({
+            "receiver_id": receiver_id,
+            "token_id": token_id,
+            "memo": memo,
+        });
+
+        Promise::new(self.nft_contract.clone()).function_call(
+            "nft_transfer".to_string(),
+            args.to_string().into_bytes(),
+            NearToken::from_yoctonear(1),
+            self.attached_gas(),
+        )
+    }
+}
+
+impl PayoutExecutor for NftExecutor {
+    fn attached_gas(&self) -> Gas {
+        self.gas.unwrap_or(GAS_FOR_NFT_TRANSFER)
+    }
+
+    fn transfer(&self, _receiver_id: &AccountId, _amount: u128, _memo: Option<&str>) -> Promise {
+        env::panic_str("NFT lists can only pay out their own payments")
+    }
+
+    fn pay_record(&self, payment: &PaymentRecord) -> Promise {
+        let token_id = payment
+            .nft_token_id
+            .as_deref()
+            .expect("NFT payment without nft_token_id");
+        self.transfer_token(&payment.recipient, token_id, payment.memo.as_deref())
+    }
+
+    fn payout_call(&self, _payment: &PaymentRecord) -> PayoutCall {
+        PayoutCall {
+            receiver_id: self.nft_contract.clone(),
+            method: Some("nft_transfer"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::test_utils::scheduled_call;
+    use crate::PaymentStatus;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    #[test]
+    fn test_nft_transfer_of_listed_token() {
+        testing_env!(VMContextBuilder::new().build());
+        let executor = NftExecutor {
+            nft_contract: "badges.near".parse().unwrap(),
+            gas: None,
+        };
+
+        executor
+            .pay_record(&PaymentRecord {
+                recipient: accounts(1),
+                amount: U128(1),
+                memo: None,
+                reference: None,
+                nft_token_id: Some("badge-7".to_string()),
+                destination: None,
+                token_id: None,
+                merged_lines: None,
+                net_amount: None,
+                withheld_amount: None,
+                status: PaymentStatus::Pending,
+            })
+            .detach();
+
+        let (receiver_id, method, args, deposit) = scheduled_call();
+        assert_eq!(receiver_id.as_str(), "badges.near");
+        assert_eq!(method, "nft_transfer");
+        assert_eq!(
+            args,
+            json!({ "receiver_id": accounts(1), "token_id": "badge-7", "memo": null })
+        );
+        assert_eq!(deposit, NearToken::from_yoctonear(1));
+    }
+}
@@ -0,0 +1,522 @@
+// USD-denominated lists
+//
+// Contributor contracts are often in USD while the treasury pays in NEAR or a NEP-141
+// token. A list submitted with `denomination: "UsdCents"` gives its amounts in US cents
+// and cannot be funded until the submitter calls `lock_price`: the contract then reads the
+// token's USD price from the price oracle (`get_price_data`, as implemented by
+// priceoracle.near) and converts every amount to the token, rounding up to its smallest
+// unit. The price, its oracle timestamp and the resulting total are locked into the
+// list's `fiat` field, so funding and payouts use the rate of approval time. Oracle prices
+// older than `max_price_age_sec` are rejected when locking. The lock itself stays valid
+// for `lock_valid_for_sec` (14 days by default), long enough for a DAO to lock in one
+// proposal and fund with `approve_list` or `ft_transfer_call` in a later one; the callback
+// of a lock made in the same proposal would land after the funding. Once expired, funding
+// fails and the submitter locks again. A lock cannot be replaced while it is valid, and the
+// list must be funded with the locked total in one transfer, so a funding proposal sized
+// from `fiat.locked.total` never partially funds a list at another rate.
+//
+// The oracle gives the USD value of one smallest token unit as multiplier / 10^decimals,
+// so `cents` convert to ceil(cents * 10^decimals / (100 * multiplier)).
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde_json::json;
+use near_sdk::{
+    env, log, near, require, AccountId, Gas, GasWeight, NearToken, Promise, PromiseError,
+};
+
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentList, PaymentRecord,
+    TokenId,
+};
+
+const GAS_FOR_GET_PRICE_DATA: Gas = Gas::from_tgas(10);
+/// Static gas of the price callback; it also gets the unused gas to convert large lists
+const GAS_FOR_ON_PRICE_DATA: Gas = Gas::from_tgas(20);
+
+/// Unit in which the amounts of a list are given
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum Denomination {
+    /// Smallest units of the list's token
+    #[default]
+    Token,
+    /// US cents, converted to the token with `lock_price`
+    UsdCents,
+}
+
+/// Price oracle used to convert USD-denominated lists
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PriceOracleConfig {
+    /// Contract implementing priceoracle.near's `get_price_data`
+    pub oracle_id: AccountId,
+    /// Oracle asset ID priced for native NEAR lists; NEP-141 lists use the token contract
+    pub near_asset_id: String,
+    /// Oldest oracle data (by its timestamp) accepted when locking a price
+    pub max_price_age_sec: u32,
+    /// How long a locked price can fund the list, leaving time for a funding proposal
+    pub lock_valid_for_sec: u32,
+}
+
+impl Default for PriceOracleConfig {
+    fn default() -> Self {
+        Self {
+            oracle_id: "priceoracle.near".parse().unwrap(),
+            near_asset_id: "wrap.near".to_string(),
+            max_price_age_sec: 90,
+            lock_valid_for_sec: 14 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// USD value of one smallest token unit: multiplier / 10^decimals
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Price {
+    pub multiplier: U128,
+    pub decimals: u8,
+}
+
+/// Price of one asset in `get_price_data`, missing without recent oracle reports
+#[near(serializers = [json])]
+pub struct AssetOptionalPrice {
+    pub asset_id: String,
+    pub price: Option<Price>,
+}
+
+/// Result of the oracle's `get_price_data`
+#[near(serializers = [json])]
+pub struct PriceData {
+    /// Block timestamp (ns) of the prices
+    pub timestamp: U64,
+    pub recency_duration_sec: u32,
+    pub prices: Vec<AssetOptionalPrice>,
+}
+
+/// Price a USD-denominated list was converted at
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct LockedPrice {
+    pub price: Price,
+    /// Oracle timestamp (ns) of the price
+    pub price_timestamp: U64,
+    /// Block timestamp (ns) of the lock
+    pub locked_at: u64,
+    /// Token amount required to fund the list
+    pub total: U128,
+}
+
+/// USD amounts of a list and the price they were converted at
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct FiatDenomination {
+    /// Oracle asset ID of the list's token
+    pub asset_id: String,
+    /// US cents of each record, in the order of `payments`
+    pub usd_cents: Vec<U128>,
+    /// Set once the price is locked; until then record amounts are US cents
+    pub locked: Option<LockedPrice>,
+}
+
+impl LockedPrice {
+    /// Whether the lock is older than `valid_for_sec` and can no longer fund the list
+    fn is_expired(&self, valid_for_sec: u32) -> bool {
+        env::block_timestamp().saturating_sub(self.locked_at) / 1_000_000_000 > valid_for_sec as u64
+    }
+}
+
+/// Token amount worth `cents` US cents at `price`, rounded up
+pub(crate) fn cents_to_amount(cents: u128, price: &Price) -> Option<u128> {
+    let numerator = cents.checked_mul(10u128.checked_pow(price.decimals as u32)?)?;
+    let denominator = price.multiplier.0.checked_mul(100)?;
+    (denominator > 0).then(|| numerator.div_ceil(denominator))
+}
+
+impl PaymentList {
+    /// Panic unless `amount` can fund the list: for USD-denominated lists, the price must be
+    /// locked within `valid_for_sec` and `amount` must cover the locked total
+    pub(crate) fn require_fundable_at_locked_price(&self, amount: u128, valid_for_sec: u32) {
+        let Some(fiat) = &self.fiat else {
+            return;
+        };
+        let locked = fiat
+            .locked
+            .as_ref()
+            .expect("Lock the USD price with lock_price before funding the list");
+        require!(
+            !locked.is_expired(valid_for_sec),
+            format!(
+                "The USD price was locked more than {} seconds ago, lock it again with lock_price",
+                valid_for_sec
+            )
+        );
+        require!(
+            amount >= locked.total.0,
+            format!(
+                "USD-denominated lists must be funded with the locked total of {} in one transfer",
+                locked.total.0
+            )
+        );
+    }
+
+    /// Panic if the list is funded or its price locked within `valid_for_sec`
+    fn require_price_lockable(&self, valid_for_sec: u32) {
+        require!(
+            matches!(self.status, ListStatus::Pending) && self.funded_amount.0 == 0,
+            "The price can only be locked before the list is funded"
+        );
+        require!(
+            self.fiat
+                .as_ref()
+                .and_then(|fiat| fiat.locked.as_ref())
+                .is_none_or(|locked| locked.is_expired(valid_for_sec)),
+            format!(
+                "The price is already locked; it can be locked again once older than {} seconds",
+                valid_for_sec
+            )
+        );
+    }
+}
+
+impl BulkPaymentContract {
+    /// USD denomination of a new list of `token_id` with `records` in cents
+    pub(crate) fn usd_denomination(
+        &self,
+        token_id: &TokenId,
+        records: &[PaymentRecord],
+    ) -> FiatDenomination {
+        let asset_id = match token_id {
+            TokenId::Native => self.price_oracle.near_asset_id.clone(),
+            TokenId::Nep141(token_contract) => token_contract.to_string(),
+            _ => env::panic_str("USD-denominated lists must pay NEAR or a NEP-141 token"),
+        };
+        FiatDenomination {
+            asset_id,
+            usd_cents: records.iter().map(|record| record.amount).collect(),
+            locked: None,
+        }
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Convert the USD amounts of a list at the oracle's current price (submitter only)
+    ///
+    /// The callback returns the token amount required to fund the list, within
+    /// `lock_valid_for_sec` of the lock. A lock can only be renewed once it has expired and
+    /// while the list has no funds.
+    ///
+    /// # Panics
+    /// - If the list is funded or its price locked less than `lock_valid_for_sec` ago
+    /// - In the callback, if the oracle has no price for the token or its data is older
+    ///   than `max_price_age_sec`
+    pub fn lock_price(&mut self, list_id: ListId) -> Promise {
+        let list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found");
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can lock the price"
+        );
+        let fiat = list.fiat.as_ref().expect("List is not denominated in USD");
+        list.require_price_lockable(self.price_oracle.lock_valid_for_sec);
+
+        Promise::new(self.price_oracle.oracle_id.clone())
+            .function_call_weight(
+                "get_price_data".to_string(),
+                json!({ "asset_ids": [fiat.asset_id] })
+                    .to_string()
+                    .into_bytes(),
+                NearToken::from_yoctonear(0),
+                GAS_FOR_GET_PRICE_DATA,
+                GasWeight(0),
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_PRICE_DATA)
+                    .on_price_data(list_id),
+            )
+    }
+
+    /// Callback with the oracle's prices: convert the list's amounts and lock the price
+    #[private]
+    pub fn on_price_data(
+        &mut self,
+        list_id: ListId,
+        #[callback_result] price_data: Result<PriceData, PromiseError>,
+    ) -> U128 {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        require!(list.fiat.is_some(), "List is not denominated in USD");
+        list.require_price_lockable(self.price_oracle.lock_valid_for_sec);
+        let mut fiat = list.fiat.take().expect("List is not denominated in USD");
+
+        let price_data = price_data.unwrap_or_else(|_| env::panic_str("Price oracle call failed"));
+        let price = price_data
+            .prices
+            .into_iter()
+            .find(|price| price.asset_id == fiat.asset_id)
+            .and_then(|price| price.price)
+            .filter(|price| price.multiplier.0 > 0)
+            .unwrap_or_else(|| {
+                env::panic_str(&format!("Price oracle has no price for {}", fiat.asset_id))
+            });
+        let age_sec = env::block_timestamp().saturating_sub(price_data.timestamp.0) / 1_000_000_000;
+        require!(
+            age_sec <= self.price_oracle.max_price_age_sec as u64,
+            format!(
+                "Price of {} is stale: {} seconds old, at most {} allowed",
+                fiat.asset_id, age_sec, self.price_oracle.max_price_age_sec
+            )
+        );
+
+        for (record, cents) in list.payments.iter_mut().zip(&fiat.usd_cents) {
+            record.amount =
+                U128(cents_to_amount(cents.0, &price).expect("USD amount too large to convert"));
+        }
+        self.require_valid_amounts(&list.token_id, &list.payments);
+        if let Some(withholding) = list.withholding.clone() {
+            withholding.apply(&mut list.payments);
+        }
+        let total = U128(list.total_amount());
+        log!(
+            "Price of {} locked for list {}: {} US cents = {}",
+            fiat.asset_id,
+            list_id,
+            fiat.usd_cents.iter().map(|cents| cents.0).sum::<u128>(),
+            total.0
+        );
+        fiat.locked = Some(LockedPrice {
+            price,
+            price_timestamp: price_data.timestamp,
+            locked_at: env::block_timestamp(),
+            total,
+        });
+        list.fiat = Some(fiat);
+        self.payment_lists.insert(list_id, list);
+        total
+    }
+
+    /// Set the price oracle used to convert USD-denominated lists (contract account only)
+    #[private]
+    pub fn set_price_oracle(&mut self, config: PriceOracleConfig) {
+        require!(
+            config.max_price_age_sec > 0,
+            "max_price_age_sec must be positive"
+        );
+        require!(
+            config.lock_valid_for_sec > 0,
+            "lock_valid_for_sec must be positive"
+        );
+        self.price_oracle = config;
+    }
+
+    /// Price oracle used to convert USD-denominated lists
+    pub fn view_price_oracle(&self) -> PriceOracleConfig {
+        self.price_oracle.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{ListOptions, PaymentInput, TokenAmount, ValidationConfig};
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const LIST_ID: &str = "d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5d5";
+    const NOW: u64 = 1_700_000_000_000_000_000;
+
+    /// $3.115 per NEAR, as priceoracle.near reports wrap.near
+    fn near_price() -> Price {
+        Price {
+            multiplier: U128(31150),
+            decimals: 28,
+        }
+    }
+
+    fn price_data(age_sec: u64) -> PriceData {
+        PriceData {
+            timestamp: U64(NOW - age_sec * 1_000_000_000),
+            recency_duration_sec: 90,
+            prices: vec![AssetOptionalPrice {
+                asset_id: "wrap.near".to_string(),
+                price: Some(near_price()),
+            }],
+        }
+    }
+
+    /// Native list paying $100.00 and $0.01, submitted by accounts(0)
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(NOW);
+        let mut contract = contract_with_storage(&mut context, 2);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 10_000),
+            PaymentInput::new(accounts(2), 1),
+        ];
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                denomination: Denomination::UsdCents,
+                ..Default::default()
+            }),
+        );
+        (context, contract)
+    }
+
+    #[test]
+    fn test_cents_to_amount_rounds_up() {
+        // $1 at $3.115 per NEAR is 0.3210272873194221508828... NEAR
+        assert_eq!(
+            cents_to_amount(100, &near_price()),
+            Some(321_027_287_319_422_150_882_826)
+        );
+        // USDT with 6 decimals at $1.0001
+        let usdt = Price {
+            multiplier: U128(10001),
+            decimals: 10,
+        };
+        assert_eq!(cents_to_amount(100, &usdt), Some(999_901));
+        assert_eq!(
+            cents_to_amount(
+                1,
+                &Price {
+                    multiplier: U128(0),
+                    decimals: 6
+                }
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lock_price_converts_amounts() {
+        let (_, mut contract) = setup();
+        contract.lock_price(LIST_ID.to_string()).detach();
+        let receipts = get_created_receipts();
+        assert_eq!(receipts[0].receiver_id.as_str(), "priceoracle.near");
+        match &receipts[0].actions[..] {
+            [MockAction::FunctionCallWeight {
+                method_name, args, ..
+            }] => {
+                assert_eq!(method_name, b"get_price_data");
+                let args: near_sdk::serde_json::Value =
+                    near_sdk::serde_json::from_slice(args).unwrap();
+                assert_eq!(args, json!({ "asset_ids": ["wrap.near"] }));
+            }
+            actions => panic!("Expected a function call, got {:?}", actions),
+        }
+
+        let total = contract.on_price_data(LIST_ID.to_string(), Ok(price_data(30)));
+        let list = contract.view_list(LIST_ID.to_string());
+        let amounts: Vec<u128> = list.payments.iter().map(|p| p.amount.0).collect();
+        assert_eq!(
+            amounts,
+            vec![
+                32_102_728_731_942_215_088_282_505,
+                3_210_272_873_194_221_508_829
+            ]
+        );
+        assert_eq!(total.0, amounts.iter().sum::<u128>());
+        let fiat = list.fiat.unwrap();
+        assert_eq!(fiat.usd_cents, vec![U128(10_000), U128(1)]);
+        assert_eq!(fiat.locked.unwrap().total, total);
+    }
+
+    #[test]
+    #[should_panic(expected = "Price of wrap.near is stale: 91 seconds old, at most 90 allowed")]
+    fn test_stale_price_rejected() {
+        let (_, mut contract) = setup();
+        contract.on_price_data(LIST_ID.to_string(), Ok(price_data(91)));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Invalid payments: payment 1: amount 3210272873194221508829 is below the minimum of 10000000000000000000000 for native"
+    )]
+    fn test_converted_amounts_checked_against_minimum() {
+        let (_, mut contract) = setup();
+        contract.set_validation_config(ValidationConfig {
+            min_amounts: vec![TokenAmount {
+                token_id: TokenId::Native,
+                amount: U128(10u128.pow(22)),
+            }],
+            ..Default::default()
+        });
+        contract.on_price_data(LIST_ID.to_string(), Ok(price_data(0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Lock the USD price with lock_price before funding the list")]
+    fn test_funding_requires_locked_price() {
+        let (mut context, mut contract) = setup();
+        context.attached_deposit(NearToken::from_near(40));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+    }
+
+    #[test]
+    fn test_lock_funds_in_a_later_proposal() {
+        let (mut context, mut contract) = setup();
+        let total = contract.on_price_data(LIST_ID.to_string(), Ok(price_data(0)));
+
+        // A week later, well past the oracle's price age
+        context
+            .block_timestamp(NOW + 7 * 24 * 60 * 60 * 1_000_000_000)
+            .attached_deposit(NearToken::from_yoctonear(total.0));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(list.status, ListStatus::Approved));
+        assert_eq!(list.funded_amount, total);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The USD price was locked more than 1209600 seconds ago, lock it again with lock_price"
+    )]
+    fn test_funding_rejects_expired_lock() {
+        let (mut context, mut contract) = setup();
+        contract.on_price_data(LIST_ID.to_string(), Ok(price_data(0)));
+
+        context
+            .block_timestamp(NOW + 1_209_601 * 1_000_000_000)
+            .attached_deposit(NearToken::from_near(40));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "The price is already locked; it can be locked again once older than 1209600 seconds"
+    )]
+    fn test_valid_lock_cannot_be_replaced() {
+        let (_, mut contract) = setup();
+        contract.on_price_data(LIST_ID.to_string(), Ok(price_data(0)));
+        contract.lock_price(LIST_ID.to_string()).detach();
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "USD-denominated lists must be funded with the locked total of 32105939004815409309791334 in one transfer"
+    )]
+    fn test_partial_funding_rejected() {
+        let (mut context, mut contract) = setup();
+        let total = contract.on_price_data(LIST_ID.to_string(), Ok(price_data(0)));
+
+        context.attached_deposit(NearToken::from_yoctonear(total.0 - 1));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+    }
+}
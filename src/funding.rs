@@ -0,0 +1,224 @@
+// Incremental list funding
+//
+// A list can be funded in tranches: each `approve_list` deposit, `ft_transfer_call` or
+// `mt_transfer_call` adds to its `funded_amount` (for mixed-token lists, to the funded total
+// of that token) and moves it to PartiallyFunded until everything is covered, when it becomes
+// Approved. Whatever exceeds the outstanding amount is returned: attached NEAR is refunded to
+// the caller and the token receiver callbacks report it as unused. Rejecting a partially
+// funded list returns the funds to the submitter.
+use near_sdk::json_types::U128;
+use near_sdk::{log, require};
+
+use crate::executor::executor;
+use crate::{
+    BulkPaymentContract, IntentsDelivery, ListId, ListStatus, PaymentList, TokenAmount, TokenId,
+};
+
+impl PaymentList {
+    /// Whether the list still accepts funding
+    pub fn is_awaiting_funding(&self) -> bool {
+        matches!(
+            self.status,
+            ListStatus::Pending | ListStatus::PartiallyFunded
+        )
+    }
+
+    /// Amounts funded so far, per token
+    pub fn funded_totals(&self) -> Vec<TokenAmount> {
+        match &self.funded {
+            Some(funded) => funded.clone(),
+            None if self.funded_amount.0 > 0 => vec![TokenAmount {
+                token_id: self.token_id.clone(),
+                amount: self.funded_amount,
+            }],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Add up to the outstanding part of `amount` to `funded`, returning the excess
+pub(crate) fn add_funding(funded: &mut U128, required: u128, amount: u128) -> u128 {
+    let accepted = amount.min(required.saturating_sub(funded.0));
+    funded.0 += accepted;
+    amount - accepted
+}
+
+impl BulkPaymentContract {
+    /// Fund a single-token list with `amount`, approving it once the total is covered
+    ///
+    /// # Returns
+    /// The part of `amount` exceeding what was outstanding, to be returned to the funder
+    pub(crate) fn fund_list(
+        &mut self,
+        list_id: ListId,
+        mut list: PaymentList,
+        amount: u128,
+    ) -> u128 {
+        require!(amount > 0, "Funding amount must be positive");
+        list.require_fundable_at_locked_price(amount, self.price_oracle.lock_valid_for_sec);
+        self.check_spending_limits(&list);
+
+        let total = list.total_amount();
+        let excess = add_funding(&mut list.funded_amount, total, amount);
+        let funded = list.funded_amount.0;
+        list.status = if funded == total {
+            self.record_spending(&list);
+            self.enqueue_payout(&list_id, &list);
+            ListStatus::Approved
+        } else {
+            ListStatus::PartiallyFunded
+        };
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Payment list {} funded with {} ({}/{}), {} returned",
+            list_id,
+            amount - excess,
+            funded,
+            total,
+            excess
+        );
+        if funded == total {
+            log!("Payment list {} approved", list_id);
+        }
+        excess
+    }
+
+    /// Send the funds received so far by a list back to its submitter. Intents tokens
+    /// stay on the intents ledger they were funded from.
+    pub(crate) fn return_funding(&self, list_id: &ListId) {
+        let list = self
+            .payment_lists
+            .get(list_id)
+            .expect("Payment list not found");
+        if matches!(list.token_id, TokenId::Nft(_)) {
+            return;
+        }
+
+        let funded = list.funded_totals();
+        for total in funded.iter().filter(|total| total.amount.0 > 0) {
+            executor(&total.token_id, IntentsDelivery::Transfer)
+                .transfer(&list.submitter, total.amount.0, None)
+                .detach();
+        }
+        if !funded.is_empty() {
+            log!(
+                "Returned the funding of list {} to {}",
+                list_id,
+                list.submitter
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{MultiTokenReceiver, PaymentInput};
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, AccountId, NearToken, PromiseOrValue};
+
+    const LIST_ID: &str = "b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4b4";
+
+    /// Submit a list paying 100 and 200 of `token_id` from accounts(0)
+    fn setup_list(token_id: TokenId) -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 100),
+            PaymentInput::new(accounts(2), 200),
+        ];
+        contract.submit_list(LIST_ID.to_string(), token_id, payments, None, None);
+        (context, contract)
+    }
+
+    fn status(contract: &BulkPaymentContract) -> ListStatus {
+        contract.view_list(LIST_ID.to_string()).status
+    }
+
+    #[test]
+    fn test_native_funding_in_tranches_refunds_excess() {
+        let (mut context, mut contract) = setup_list(TokenId::Native);
+
+        context.attached_deposit(NearToken::from_yoctonear(120));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+        assert!(matches!(status(&contract), ListStatus::PartiallyFunded));
+        assert!(get_created_receipts().is_empty());
+
+        context.attached_deposit(NearToken::from_yoctonear(250));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(list.status, ListStatus::Approved));
+        assert_eq!(list.funded_amount, U128(300));
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, accounts(0));
+        assert!(matches!(
+            receipts[0].actions[..],
+            [MockAction::Transfer { deposit, .. }] if deposit == NearToken::from_yoctonear(70)
+        ));
+    }
+
+    #[test]
+    fn test_token_funding_returns_unused_amount() {
+        let usdc: AccountId = "usdc.near".parse().unwrap();
+        let (mut context, mut contract) = setup_list(TokenId::Nep141(usdc.clone()));
+
+        context.predecessor_account_id(usdc);
+        testing_env!(context.build());
+        assert_eq!(
+            contract.ft_on_transfer(accounts(0), U128(250), LIST_ID.to_string()),
+            U128(0)
+        );
+        assert!(matches!(status(&contract), ListStatus::PartiallyFunded));
+        assert_eq!(
+            contract.ft_on_transfer(accounts(0), U128(80), LIST_ID.to_string()),
+            U128(30)
+        );
+        assert!(matches!(status(&contract), ListStatus::Approved));
+    }
+
+    #[test]
+    fn test_intents_funding_returns_unused_amount() {
+        let (mut context, mut contract) = setup_list("nep141:wrap.near".parse().unwrap());
+
+        context.predecessor_account_id("intents.near".parse().unwrap());
+        testing_env!(context.build());
+        let unused = contract.mt_on_transfer(
+            accounts(0),
+            vec![accounts(0)],
+            vec!["nep141:wrap.near".to_string()],
+            vec![U128(500)],
+            LIST_ID.to_string(),
+        );
+        assert!(matches!(unused, PromiseOrValue::Value(amounts) if amounts == vec![U128(200)]));
+        assert!(matches!(status(&contract), ListStatus::Approved));
+    }
+
+    #[test]
+    fn test_reject_returns_partial_funding() {
+        let (mut context, mut contract) = setup_list(TokenId::Native);
+
+        context.attached_deposit(NearToken::from_yoctonear(120));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.reject_list(LIST_ID.to_string());
+
+        assert!(matches!(status(&contract), ListStatus::Rejected));
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert!(matches!(
+            receipts[0].actions[..],
+            [MockAction::Transfer { deposit, .. }] if deposit == NearToken::from_yoctonear(120)
+        ));
+    }
+}
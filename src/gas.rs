@@ -0,0 +1,423 @@
+// Payout gas metering
+//
+// `payout_batch` budgets each payment with the gas attached to its call plus the gas the
+// contract itself spends on the payment. The latter starts at a conservative default and
+// is then measured: every payment's `env::used_gas()` delta minus its attached gas, the
+// largest of a call kept on the list for later calls and for `estimate_payout_batches`.
+// Submitters can override the attached gas per token with `options.attached_gas`, e.g.
+// for token contracts whose `ft_transfer` needs more or much less than the default 50 TGas.
+// `simulate_payout_batch` plans a single call the same way, listing the records it would pay.
+// Both are estimates: they assume a flat cost to load each stored record and the latest
+// measured overhead, while `payout_batch` meters the gas it actually has left. A simulated
+// batch ended by `max_payments` or by the end of the list is what the call pays as long as
+// its gas suffices; one ended by the gas estimate may be paid in more or fewer payments.
+use near_sdk::{near, require, AccountId, Gas};
+
+use crate::executor::DEFAULT_PAYMENT_OVERHEAD;
+use crate::registration::registering_payment_gas;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, PaymentList, PaymentRecord, PaymentStatus,
+    TokenId,
+};
+
+/// Gas kept back in `payout_batch` for storing the list and logging
+pub(crate) const GAS_RESERVE: Gas = Gas::from_tgas(15);
+/// Approximate gas to load and store one record of a list in a payout call
+const GAS_PER_STORED_RECORD: Gas = Gas::from_ggas(300);
+/// Prepaid gas `estimate_payout_batches` assumes by default
+const DEFAULT_PREPAID_GAS: Gas = Gas::from_tgas(300);
+const MIN_ATTACHED_GAS: Gas = Gas::from_tgas(5);
+const MAX_ATTACHED_GAS: Gas = Gas::from_tgas(150);
+
+/// Gas to attach to each payment call of a token
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenGas {
+    pub token_id: TokenId,
+    pub gas: Gas,
+}
+
+/// How a list's pending payments split into payout calls
+#[near(serializers = [json])]
+#[derive(Debug, PartialEq)]
+pub struct PayoutEstimate {
+    /// Payments still to pay
+    pub pending: u64,
+    /// Payments the next call can pay
+    pub records_per_call: u64,
+    /// Calls needed to pay all pending payments; `None` if the prepaid gas does not
+    /// cover a single payment
+    pub calls_remaining: Option<u64>,
+}
+
+/// Payment a `payout_batch` call would make
+#[near(serializers = [json])]
+#[derive(Debug, PartialEq)]
+pub struct SimulatedPayment {
+    /// Position of the record in the list
+    pub index: u32,
+    pub receiver_id: AccountId,
+    /// Function called on the receiver, `None` for a plain NEAR transfer. Lists registering
+    /// recipients check the registration first.
+    pub method: Option<String>,
+    pub attached_gas: Gas,
+    /// Gas the call budgets for the payment, including its attached gas
+    pub budgeted_gas: Gas,
+}
+
+/// Payments a `payout_batch` call would make and what would be left afterwards
+#[near(serializers = [json])]
+#[derive(Debug, PartialEq)]
+pub struct SimulatedBatch {
+    pub payments: Vec<SimulatedPayment>,
+    /// Payments still pending after the call
+    pub remaining: u64,
+    /// Whether the batch is ended by the gas estimate rather than by `max_payments` or the
+    /// end of the list, in which case the real call may stop at a different payment
+    pub gas_limited: bool,
+}
+
+/// Check the attached gas overrides requested for a list
+pub(crate) fn validate_attached_gas(overrides: &[TokenGas]) {
+    for (index, token_gas) in overrides.iter().enumerate() {
+        require!(
+            !token_gas.token_id.is_native(),
+            "Native NEAR transfers do not attach gas"
+        );
+        require!(
+            token_gas.gas >= MIN_ATTACHED_GAS && token_gas.gas <= MAX_ATTACHED_GAS,
+            format!(
+                "Attached gas for {} must be between 5 and 150 TGas",
+                token_gas.token_id
+            )
+        );
+        require!(
+            !overrides[..index]
+                .iter()
+                .any(|other| other.token_id == token_gas.token_id),
+            format!("Attached gas for {} is set twice", token_gas.token_id)
+        );
+    }
+}
+
+/// Attached gas set for `token_id`, if any
+pub(crate) fn gas_override(overrides: Option<&[TokenGas]>, token_id: &TokenId) -> Option<Gas> {
+    overrides?
+        .iter()
+        .find(|token_gas| token_gas.token_id == *token_id)
+        .map(|token_gas| token_gas.gas)
+}
+
+/// Number of payments costing `costs` that one call pays from the front, given the gas
+/// left for payments
+fn fit_batch(costs: &[Gas], budget: Gas, max_payments: Option<u64>) -> usize {
+    let mut left = budget;
+    let mut count = 0;
+    for cost in costs {
+        if max_payments.is_some_and(|max| count as u64 >= max) || *cost > left {
+            break;
+        }
+        left = left.saturating_sub(*cost);
+        count += 1;
+    }
+    count
+}
+
+/// Gas of a call left for payments after loading the list and the final reserve
+fn payment_budget(list: &PaymentList, prepaid_gas: Option<Gas>) -> Gas {
+    let fixed = GAS_PER_STORED_RECORD
+        .saturating_mul(list.payments.len() as u64)
+        .saturating_add(GAS_RESERVE);
+    prepaid_gas
+        .unwrap_or(DEFAULT_PREPAID_GAS)
+        .saturating_sub(fixed)
+}
+
+impl PaymentList {
+    /// Gas the contract spends per payment besides the attached gas, as last measured
+    pub(crate) fn payment_overhead(&self) -> Gas {
+        self.measured_gas_overhead
+            .unwrap_or(DEFAULT_PAYMENT_OVERHEAD)
+    }
+
+    /// Gas to budget for paying `payment`
+    fn payment_gas(&self, payment: &PaymentRecord) -> Gas {
+        let transfer_gas = self
+            .token_executor(self.payment_token(payment))
+            .attached_gas();
+        if self.registration.is_some() {
+            return registering_payment_gas(transfer_gas);
+        }
+        transfer_gas.saturating_add(self.payment_overhead())
+    }
+}
+
+impl BulkPaymentContract {
+    /// Positions of the pending payments a payout would pay, in order; recipients screened
+    /// out since submission would be blocked instead, and payments being registered skipped
+    fn payable_indices(&self, list: &PaymentList) -> Vec<usize> {
+        list.payments
+            .iter()
+            .enumerate()
+            .filter(|(index, p)| {
+                matches!(p.status, PaymentStatus::Pending)
+                    && !list.is_registering(*index)
+                    && self
+                        .screening_issue(&list.submitter, &p.recipient)
+                        .is_none()
+            })
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Estimate how the pending payments of a list split into `payout_batch` calls with
+    /// `prepaid_gas` each (300 TGas by default)
+    pub fn estimate_payout_batches(
+        &self,
+        list_id: ListId,
+        prepaid_gas: Option<Gas>,
+    ) -> PayoutEstimate {
+        let list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found");
+
+        let budget = payment_budget(list, prepaid_gas);
+        let costs: Vec<Gas> = self
+            .payable_indices(list)
+            .into_iter()
+            .map(|index| list.payment_gas(&list.payments[index]))
+            .collect();
+
+        let mut estimate = PayoutEstimate {
+            pending: costs.len() as u64,
+            records_per_call: 0,
+            calls_remaining: Some(0),
+        };
+        let mut next = 0;
+        while next < costs.len() {
+            let count = fit_batch(&costs[next..], budget, None);
+            if count == 0 {
+                estimate.calls_remaining = None;
+                break;
+            }
+            if next == 0 {
+                estimate.records_per_call = count as u64;
+            }
+            next += count;
+            estimate.calls_remaining = estimate.calls_remaining.map(|calls| calls + 1);
+        }
+        estimate
+    }
+
+    /// Show which records a `payout_batch` call with `prepaid_gas` (300 TGas by default)
+    /// and `max_payments` would pay, with the call and gas of each payment. The batch
+    /// boundary is only certain when `gas_limited` is false.
+    pub fn simulate_payout_batch(
+        &self,
+        list_id: ListId,
+        prepaid_gas: Option<Gas>,
+        max_payments: Option<u32>,
+    ) -> SimulatedBatch {
+        let list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found");
+
+        let indices = self.payable_indices(list);
+        let costs: Vec<Gas> = indices
+            .iter()
+            .map(|index| list.payment_gas(&list.payments[*index]))
+            .collect();
+        let count = fit_batch(
+            &costs,
+            payment_budget(list, prepaid_gas),
+            max_payments.map(u64::from),
+        );
+
+        let payments: Vec<SimulatedPayment> = indices[..count]
+            .iter()
+            .zip(&costs)
+            .map(|(index, budgeted_gas)| {
+                let payment = &list.payments[*index];
+                let executor = list.token_executor(list.payment_token(payment));
+                let call = executor.payout_call(payment);
+                SimulatedPayment {
+                    index: *index as u32,
+                    receiver_id: call.receiver_id,
+                    method: call.method.map(str::to_string),
+                    attached_gas: executor.attached_gas(),
+                    budgeted_gas: *budgeted_gas,
+                }
+            })
+            .collect();
+        // Records before the first one left unpaid are paid or blocked by the call
+        let stop = match count {
+            0 => 0,
+            count if count == indices.len() => list.payments.len(),
+            count => indices[count - 1] + 1,
+        };
+        SimulatedBatch {
+            payments,
+            remaining: list.payments[stop..]
+                .iter()
+                .filter(|p| matches!(p.status, PaymentStatus::Pending))
+                .count() as u64,
+            gas_limited: count < indices.len()
+                && max_payments.is_none_or(|max| count < max as usize),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{ListOptions, PaymentInput};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const LIST_ID: &str = "b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9b9";
+
+    fn usdc() -> TokenId {
+        TokenId::Nep141("usdc.near".parse().unwrap())
+    }
+
+    /// USDC list of `count` payments from accounts(0)
+    fn setup(count: u64, attached_gas: Option<Vec<TokenGas>>) -> BulkPaymentContract {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, count);
+        let payments = (0..count)
+            .map(|n| PaymentInput::new(format!("user{}.near", n).parse().unwrap(), 100))
+            .collect();
+        contract.submit_list(
+            LIST_ID.to_string(),
+            usdc(),
+            payments,
+            None,
+            Some(ListOptions {
+                attached_gas,
+                ..Default::default()
+            }),
+        );
+        contract
+    }
+
+    #[test]
+    fn test_estimate_with_default_and_overridden_gas() {
+        // 53 TGas per payment: 5 fit into 300 TGas after the reserve and the list itself
+        let contract = setup(12, None);
+        assert_eq!(
+            contract.estimate_payout_batches(LIST_ID.to_string(), None),
+            PayoutEstimate {
+                pending: 12,
+                records_per_call: 5,
+                calls_remaining: Some(3),
+            }
+        );
+
+        // 13 TGas per payment
+        let contract = setup(
+            12,
+            Some(vec![TokenGas {
+                token_id: usdc(),
+                gas: Gas::from_tgas(10),
+            }]),
+        );
+        let estimate = contract.estimate_payout_batches(LIST_ID.to_string(), None);
+        assert_eq!(estimate.records_per_call, 12);
+        assert_eq!(estimate.calls_remaining, Some(1));
+        assert_eq!(
+            contract
+                .estimate_payout_batches(LIST_ID.to_string(), Some(Gas::from_tgas(20)))
+                .calls_remaining,
+            None
+        );
+    }
+
+    #[test]
+    fn test_payout_measures_overhead() {
+        let mut contract = setup(12, None);
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(accounts(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        let mut list = contract.payment_lists.get(LIST_ID).unwrap().clone();
+        list.status = crate::ListStatus::Approved;
+        contract.payment_lists.insert(LIST_ID.to_string(), list);
+
+        contract.payout_batch(LIST_ID.to_string(), None);
+        let overhead = contract
+            .view_list(LIST_ID.to_string())
+            .measured_gas_overhead
+            .expect("Overhead measured");
+        // Building an `ft_transfer` receipt costs more than the default
+        assert!(overhead > DEFAULT_PAYMENT_OVERHEAD, "{}", overhead);
+
+        // 18.6 TGas for the list and the reserve leave room for two payments of 53 TGas,
+        // but only one at the measured cost
+        let estimate =
+            contract.estimate_payout_batches(LIST_ID.to_string(), Some(Gas::from_ggas(125_100)));
+        assert_eq!(estimate.pending, 7);
+        assert_eq!(estimate.records_per_call, 1);
+    }
+
+    #[test]
+    fn test_simulated_batch_matches_payout() {
+        let mut contract = setup(12, None);
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(accounts(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        let mut list = contract.payment_lists.get(LIST_ID).unwrap().clone();
+        list.status = crate::ListStatus::Approved;
+        contract.payment_lists.insert(LIST_ID.to_string(), list);
+        contract.add_to_denylist(vec!["user1.near".parse().unwrap()]);
+
+        let batch = contract.simulate_payout_batch(LIST_ID.to_string(), None, Some(2));
+        assert_eq!(
+            batch.payments[0],
+            SimulatedPayment {
+                index: 0,
+                receiver_id: "usdc.near".parse().unwrap(),
+                method: Some("ft_transfer".to_string()),
+                attached_gas: Gas::from_tgas(50),
+                budgeted_gas: Gas::from_tgas(53),
+            }
+        );
+        assert_eq!(batch.payments[1].index, 2);
+        assert_eq!(batch.remaining, 9);
+        assert!(!batch.gas_limited);
+        // Without a limit the gas estimate ends the batch
+        assert!(
+            contract
+                .simulate_payout_batch(LIST_ID.to_string(), None, None)
+                .gas_limited
+        );
+
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), Some(2)), 9);
+        let statuses: Vec<bool> = contract.view_list(LIST_ID.to_string()).payments[..4]
+            .iter()
+            .map(|p| matches!(p.status, PaymentStatus::Paid { .. }))
+            .collect();
+        assert_eq!(statuses, vec![true, false, true, false]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Attached gas for usdc.near must be between 5 and 150 TGas")]
+    fn test_attached_gas_out_of_range() {
+        setup(
+            1,
+            Some(vec![TokenGas {
+                token_id: usdc(),
+                gas: Gas::from_tgas(200),
+            }]),
+        );
+    }
+}
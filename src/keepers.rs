@@ -0,0 +1,314 @@
+// Keeper rewards and payout operators
+//
+// `payout_batch` can be called by anyone, which keeps lists moving but leaves the caller
+// paying the gas. A list submitted with a `keeper_fee` reimburses its keepers: the fee is
+// paid in NEAR to the caller of each batch for every payment the batch processes, out of a
+// budget of `keeper_fee` times the number of payments. The budget is covered first by the
+// `approve_list` deposit, or attached with `fund_keeper_budget` for lists funded in tokens.
+// Whatever is not paid out (e.g. for blocked payments) can be refunded to the submitter.
+//
+// A list can also name `operators`, in which case only they can call `payout_batch`.
+use near_sdk::json_types::U128;
+use near_sdk::{env, log, near, require, AccountId, NearToken, Promise};
+
+use crate::funding::add_funding;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentList, PaymentStatus,
+};
+
+/// Most operators a list can name
+pub(crate) const MAX_OPERATORS: usize = 10;
+
+/// NEAR budget of a list for rewarding the callers of `payout_batch`
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct KeeperRewards {
+    /// NEAR paid per processed payment
+    pub fee_per_payment: U128,
+    /// NEAR deposited for rewards
+    pub budget: U128,
+    /// NEAR paid to keepers so far
+    pub paid: U128,
+}
+
+/// Check the operators requested for a list
+pub(crate) fn validate_operators(operators: &Option<Vec<AccountId>>) {
+    if let Some(operators) = operators {
+        require!(
+            !operators.is_empty() && operators.len() <= MAX_OPERATORS,
+            format!("A list can have between 1 and {} operators", MAX_OPERATORS)
+        );
+    }
+}
+
+impl PaymentList {
+    /// Budget needed to reward every payment of the list
+    fn required_keeper_budget(&self) -> u128 {
+        self.keeper_rewards.as_ref().map_or(0, |rewards| {
+            rewards
+                .fee_per_payment
+                .0
+                .checked_mul(self.payments.len() as u128)
+                .expect("Keeper budget overflow")
+        })
+    }
+
+    /// Put `amount` towards the outstanding keeper budget, returning what is left of it
+    pub(crate) fn fund_keeper_rewards(&mut self, amount: u128) -> u128 {
+        let required = self.required_keeper_budget();
+        match self.keeper_rewards.as_mut() {
+            Some(rewards) => add_funding(&mut rewards.budget, required, amount),
+            None => amount,
+        }
+    }
+
+    /// Panic unless the caller may trigger payouts of the list
+    pub(crate) fn require_operator(&self) {
+        if let Some(operators) = &self.operators {
+            require!(
+                operators.contains(&env::predecessor_account_id()),
+                "Only the list's operators can trigger payouts"
+            );
+        }
+    }
+
+    /// Pay the caller of `payout_batch` for `processed` payments, as far as the budget goes
+    pub(crate) fn reward_keeper(&mut self, processed: u64) {
+        let Some(rewards) = self.keeper_rewards.as_mut() else {
+            return;
+        };
+        let reward = rewards
+            .fee_per_payment
+            .0
+            .saturating_mul(processed as u128)
+            .min(rewards.budget.0 - rewards.paid.0);
+        if reward == 0 {
+            return;
+        }
+        rewards.paid.0 += reward;
+        let keeper = env::predecessor_account_id();
+        log!("Keeper {} rewarded with {}", keeper, reward);
+        Promise::new(keeper)
+            .transfer(NearToken::from_yoctonear(reward))
+            .detach();
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Attach NEAR to the keeper budget of a list (submitter only); the part beyond what
+    /// the list's payments need is refunded
+    #[payable]
+    pub fn fund_keeper_budget(&mut self, list_id: ListId) -> U128 {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        let caller = env::predecessor_account_id();
+        require!(
+            list.submitter == caller,
+            "Only the submitter can fund the keeper budget"
+        );
+        require!(
+            !matches!(list.status, ListStatus::Rejected),
+            "List is rejected"
+        );
+        require!(
+            list.keeper_rewards.is_some(),
+            "List does not reward keepers"
+        );
+        let attached = env::attached_deposit().as_yoctonear();
+        require!(attached > 0, "Attach NEAR to fund the keeper budget");
+
+        let excess = list.fund_keeper_rewards(attached);
+        let budget = list.keeper_rewards.as_ref().map_or(U128(0), |r| r.budget);
+        self.payment_lists.insert(list_id.clone(), list);
+
+        if excess > 0 {
+            Promise::new(caller)
+                .transfer(NearToken::from_yoctonear(excess))
+                .detach();
+        }
+        log!(
+            "Keeper budget of list {} funded with {}, total {}",
+            list_id,
+            attached - excess,
+            budget.0
+        );
+        budget
+    }
+
+    /// Refund the keeper budget not paid out to the submitter
+    ///
+    /// Allowed once the list is rejected or has no pending payments left.
+    pub fn refund_keeper_budget(&mut self, list_id: ListId) -> Promise {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can refund the keeper budget"
+        );
+        let finished = matches!(list.status, ListStatus::Rejected)
+            || (matches!(list.status, ListStatus::Approved)
+                && !list
+                    .payments
+                    .iter()
+                    .any(|p| matches!(p.status, PaymentStatus::Pending)));
+        require!(
+            finished,
+            "Keeper budget can be refunded once all payments are processed"
+        );
+        let rewards = list
+            .keeper_rewards
+            .as_mut()
+            .expect("List does not reward keepers");
+        let leftover = rewards.budget.0 - rewards.paid.0;
+        require!(leftover > 0, "No keeper budget left to refund");
+        rewards.budget = rewards.paid;
+
+        let submitter = list.submitter.clone();
+        self.payment_lists.insert(list_id.clone(), list);
+        log!(
+            "Refunded {} of the keeper budget of list {} to {}",
+            leftover,
+            list_id,
+            submitter
+        );
+        Promise::new(submitter).transfer(NearToken::from_yoctonear(leftover))
+    }
+
+    /// Replace the operators of a list; `None` lets anyone trigger payouts (submitter only)
+    pub fn set_operators(&mut self, list_id: ListId, operators: Option<Vec<AccountId>>) {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can set the operators"
+        );
+        validate_operators(&operators);
+        list.operators = operators;
+        self.payment_lists.insert(list_id.clone(), list);
+        log!("Operators of list {} updated", list_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{ListOptions, PaymentInput, TokenId};
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, Gas};
+
+    const LIST_ID: &str = "a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8a8";
+
+    /// List of 3 native payments of 100 with a keeper fee of 10, submitted by accounts(0)
+    fn setup(operators: Option<Vec<AccountId>>) -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 3);
+        let payments = (1..4)
+            .map(|n| PaymentInput::new(accounts(n), 100))
+            .collect();
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                keeper_fee: Some(U128(10)),
+                operators,
+                ..Default::default()
+            }),
+        );
+        (context, contract)
+    }
+
+    fn transfers() -> Vec<(AccountId, u128)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver = receipt.receiver_id.clone();
+                receipt
+                    .actions
+                    .into_iter()
+                    .filter_map(move |action| match action {
+                        MockAction::Transfer { deposit, .. } => {
+                            Some((receiver.clone(), deposit.as_yoctonear()))
+                        }
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_keeper_rewarded_per_processed_payment() {
+        let (mut context, mut contract) = setup(None);
+
+        // The keeper budget is covered first, then the payments
+        context.attached_deposit(NearToken::from_yoctonear(330));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(list.status, ListStatus::Approved));
+        assert_eq!(list.keeper_rewards.unwrap().budget, U128(30));
+
+        context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 0);
+        assert!(transfers().contains(&(accounts(4), 30)));
+        assert_eq!(
+            contract
+                .view_list(LIST_ID.to_string())
+                .keeper_rewards
+                .unwrap()
+                .paid,
+            U128(30)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only the list's operators can trigger payouts")]
+    fn test_only_operators_trigger_payouts() {
+        let (mut context, mut contract) = setup(Some(vec![accounts(5)]));
+        context.attached_deposit(NearToken::from_yoctonear(330));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+
+        context
+            .predecessor_account_id(accounts(4))
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+    }
+
+    #[test]
+    fn test_fund_and_refund_keeper_budget() {
+        let (mut context, mut contract) = setup(None);
+        context.attached_deposit(NearToken::from_yoctonear(50));
+        testing_env!(context.build());
+        assert_eq!(contract.fund_keeper_budget(LIST_ID.to_string()), U128(30));
+        assert!(transfers().contains(&(accounts(0), 20)));
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.reject_list(LIST_ID.to_string());
+        contract.refund_keeper_budget(LIST_ID.to_string()).detach();
+        assert!(transfers().contains(&(accounts(0), 30)));
+    }
+}
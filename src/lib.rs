@@ -11,9 +11,65 @@
 // - Integrity verification (hash proves list contents)
 // - No auto-incrementing counters needed
 use near_sdk::json_types::U128;
-use near_sdk::store::IterableMap;
+use near_sdk::store::{IterableMap, IterableSet, LookupMap, LookupSet};
 use near_sdk::{env, log, near, require, AccountId, Gas, NearToken, Promise, PromiseOrValue};
 
+mod claims;
+mod destination;
+mod duplicates;
+mod events;
+mod executor;
+mod fiat;
+mod funding;
+mod gas;
+mod keepers;
+mod limits;
+mod merkle;
+mod migration;
+mod mixed;
+mod nft;
+mod preflight;
+mod queue;
+mod registration;
+mod screening;
+mod split;
+mod templates;
+#[cfg(test)]
+mod test_utils;
+mod token;
+mod validation;
+mod withholding;
+
+pub use claims::StorageBalance;
+pub use destination::{Chain, Destination};
+pub use duplicates::DuplicatePolicy;
+pub use fiat::{Denomination, FiatDenomination, LockedPrice, Price, PriceOracleConfig};
+pub use gas::{PayoutEstimate, TokenGas};
+pub use keepers::KeeperRewards;
+pub use limits::{SpendingAllowance, SpendingLimit};
+pub use merkle::MerkleDistribution;
+pub use migration::MigrationProgress;
+pub use mixed::TokenAmount;
+pub use nft::NonFungibleTokenReceiver;
+pub use preflight::{PreflightIssue, PreflightIssueKind, PreflightReport};
+pub use registration::{RecipientRegistration, RegistrationInFlight, StorageBalanceBounds};
+pub use split::{SplitRemainder, SplitTerms, WeightedShare, WeightedSplit};
+pub use templates::{Cadence, PayrollTemplate, TemplateId, TemplateInstance, TemplateSchedule};
+pub use token::TokenId;
+pub use validation::{ValidationConfig, ValidationIssue};
+pub use withholding::{Withholding, WithholdingRule};
+
+/// Maximum length in bytes of a payment memo or reference
+pub(crate) const MAX_PAYMENT_FIELD_LENGTH: usize = 128;
+
+/// Storage bytes covered by one storage credit
+///
+/// - AccountId: 100 bytes max (UTF-8 string)
+/// - amount: 16 bytes (u128)
+/// - status: ~50 bytes (enum with error string)
+/// - overhead: ~50 bytes for Vec storage
+const BYTES_PER_RECORD: u64 = 216;
+
 /// List ID is a hex-encoded SHA-256 hash (64 characters)
 /// Example: "a1b2c3d4e5f6..." (64 hex chars = 32 bytes)
 pub type ListId = String;
@@ -23,12 +79,43 @@ pub struct BulkPaymentContract {
     /// Payment lists indexed by their content hash (hex-encoded SHA-256)
     payment_lists: IterableMap<ListId, PaymentList>,
     storage_credits: IterableMap<AccountId, NearToken>,
+    /// Recurring payroll templates from which payment lists are materialised
+    payroll_templates: IterableMap<TemplateId, PayrollTemplate>,
+    /// Claimed-index bitmaps of Merkle lists, as 64-bit words keyed by (list, word index)
+    merkle_claims: LookupMap<(ListId, u32), u64>,
+    /// Limits applied to submitted payments
+    validation_config: ValidationConfig,
+    /// Spending limits and recent spending per submitter
+    spending_limits: LookupMap<AccountId, limits::SubmitterLimits>,
+    /// Accounts no list may pay
+    denylist: IterableSet<AccountId>,
+    /// Submitters whose lists may only pay accounts on their allowlist
+    allowlist_submitters: LookupSet<AccountId>,
+    /// Allowlist entries as (submitter, recipient)
+    allowlists: LookupSet<(AccountId, AccountId)>,
+    /// Approved push-mode lists in the order `payout_next` pays them
+    payout_queue: queue::PayoutQueue,
+    /// Price oracle converting USD-denominated lists
+    price_oracle: PriceOracleConfig,
+    /// Lists of the original contract not yet converted by `migrate_lists`
+    legacy_lists: Option<migration::LegacyLists>,
 }
 
-#[near(serializers = [json])]
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
 pub struct PaymentInput {
     pub recipient: AccountId,
     pub amount: U128,
+    /// Passed through as the `memo` of the `ft_transfer` / `ft_withdraw` call
+    pub memo: Option<String>,
+    /// Free-form reference for the payment, e.g. an invoice ID
+    pub reference: Option<String>,
+    /// NFT sent to the recipient (NFT lists only, where `amount` must be 1)
+    pub nft_token_id: Option<String>,
+    /// External chain address a PoA token payment is withdrawn to instead of `recipient`
+    pub destination: Option<Destination>,
+    /// Token of this payment if it differs from the list's token
+    pub token_id: Option<TokenId>,
 }
 
 #[near(serializers = [json, borsh])]
@@ -36,9 +123,50 @@ pub struct PaymentInput {
 pub struct PaymentRecord {
     pub recipient: AccountId,
     pub amount: U128,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+    pub nft_token_id: Option<String>,
+    pub destination: Option<Destination>,
+    pub token_id: Option<TokenId>,
+    /// Positions in the submitted list of the payments merged into this record
+    pub merged_lines: Option<Vec<u32>>,
+    /// Amount sent to the recipient when tax is withheld, `amount` being the gross
+    pub net_amount: Option<U128>,
+    /// Tax withheld from `amount` for the list's withholding account
+    pub withheld_amount: Option<U128>,
     pub status: PaymentStatus,
 }
 
+/// Storage credits charged for a record: one, plus one per started record size of its memo,
+/// reference and merged line positions
+fn record_storage_credits(memo: &Option<String>, reference: &Option<String>, lines: usize) -> u128 {
+    let bytes = [memo, reference]
+        .into_iter()
+        .flatten()
+        .map(String::len)
+        .sum::<usize>()
+        + lines * std::mem::size_of::<u32>();
+    1 + (bytes as u64).div_ceil(BYTES_PER_RECORD) as u128
+}
+
+impl PaymentInput {
+    /// Storage credits charged for the record of this payment
+    fn storage_credits(&self) -> u128 {
+        record_storage_credits(&self.memo, &self.reference, 0)
+    }
+}
+
+impl PaymentRecord {
+    /// Storage credits charged for this record
+    fn storage_credits(&self) -> u128 {
+        record_storage_credits(
+            &self.memo,
+            &self.reference,
+            self.merged_lines.as_ref().map_or(0, Vec::len),
+        )
+    }
+}
+
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub enum PaymentStatus {
@@ -48,22 +176,203 @@ pub enum PaymentStatus {
     Paid {
         block_height: u64,
     },
+    /// Unclaimed payment returned to the submitter after the claim deadline
+    Reclaimed {
+        block_height: u64,
+    },
+    /// Recipient was screened out at payout; the payment is refundable to the submitter
+    Blocked {
+        block_height: u64,
+    },
 }
 
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub struct PaymentList {
-    pub token_id: String,
+    pub token_id: TokenId,
     pub submitter: AccountId,
     pub status: ListStatus,
     pub payments: Vec<PaymentRecord>,
     pub created_at: u64,
+    /// Payroll template this list was materialised from, if any
+    pub template_id: Option<TemplateId>,
+    pub payout_mode: PayoutMode,
+    /// Block timestamp (ns) after which unclaimed payments can be reclaimed (claim mode only)
+    pub claim_deadline: Option<u64>,
+    /// Set for Merkle lists, which store only the tree root instead of `payments`
+    pub merkle: Option<MerkleDistribution>,
+    /// Descriptive information for approvers, covered by the list hash
+    pub metadata: Option<ListMetadata>,
+    /// Set when recipients are registered with the token contract before payout
+    pub registration: Option<RecipientRegistration>,
+    /// Outcome of the latest `preflight_list` checks
+    pub preflight: Option<PreflightReport>,
+    /// Indices of the payments whose NFT has been received (NFT lists only)
+    pub nft_received: Option<Vec<u32>>,
+    pub intents_delivery: IntentsDelivery,
+    /// Amount deposited so far (single-token lists)
+    pub funded_amount: U128,
+    /// Amounts funded so far per token (mixed-token lists only)
+    pub funded: Option<Vec<TokenAmount>>,
+    /// Set when the callers of `payout_batch` are rewarded
+    pub keeper_rewards: Option<KeeperRewards>,
+    /// Accounts allowed to call `payout_batch`; anyone if unset
+    pub operators: Option<Vec<AccountId>>,
+    /// Gas attached to each payment call per token, where it differs from the default
+    pub attached_gas: Option<Vec<TokenGas>>,
+    /// Largest gas a payment used besides its attached gas in the latest payout call
+    pub measured_gas_overhead: Option<Gas>,
+    /// Total, weights and rounding rule the amounts were computed from (weighted lists only)
+    pub split: Option<SplitTerms>,
+    /// Tax withheld from each payment for a withholding account
+    pub withholding: Option<Withholding>,
+    /// US cent amounts and the price they are converted at (USD-denominated lists only)
+    pub fiat: Option<FiatDenomination>,
+}
+
+impl PaymentList {
+    /// Total amount required to fund the list
+    pub fn total_amount(&self) -> u128 {
+        if let Some(merkle) = &self.merkle {
+            return merkle.total.0;
+        }
+        self.payments
+            .iter()
+            .map(|p| p.amount.0)
+            .try_fold(0u128, |acc, x| acc.checked_add(x))
+            .expect("Total payment amount overflow")
+    }
+}
+
+/// How recipients receive their payments
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub enum PayoutMode {
+    /// Payments are pushed to recipients via `payout_batch`
+    #[default]
+    Push,
+    /// Recipients pull their own payments via `claim` until the claim deadline
+    Claim,
+}
+
+/// How NEAR Intents ("nep141:") tokens reach recipients
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum IntentsDelivery {
+    /// `ft_withdraw` from intents.near to the recipient's NEAR account (or external
+    /// chain address for PoA tokens)
+    #[default]
+    Withdraw,
+    /// `mt_transfer` within intents.near, so recipients keep the balance on the
+    /// intents ledger
+    Transfer,
+}
+
+/// Optional settings for a payment list, passed to `submit_list`
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct ListOptions {
+    #[serde(default)]
+    pub payout_mode: PayoutMode,
+    /// Block timestamp (ns) after which unclaimed payments return to the submitter.
+    /// Required in claim mode.
+    pub claim_deadline: Option<u64>,
+    /// Descriptive information shown to approvers
+    pub metadata: Option<ListMetadata>,
+    /// Register unregistered recipients with the NEP-141 token contract (NEP-145
+    /// `storage_deposit`) before transferring, paid from the list's registration budget
+    #[serde(default)]
+    pub register_recipients: bool,
+    /// Delivery of NEAR Intents tokens; `Transfer` keeps payments on the intents ledger
+    #[serde(default)]
+    pub intents_delivery: IntentsDelivery,
+    /// Whether payments to the same recipient are kept, rejected or merged
+    #[serde(default)]
+    pub duplicates: DuplicatePolicy,
+    /// NEAR paid to the caller of `payout_batch` per payment it processes (push mode only)
+    pub keeper_fee: Option<U128>,
+    /// Accounts allowed to call `payout_batch`; anyone if unset
+    pub operators: Option<Vec<AccountId>>,
+    /// Gas to attach to each payment call per token, instead of the default
+    pub attached_gas: Option<Vec<TokenGas>>,
+    /// Withhold a share of each payment for a tax escrow account (push mode only)
+    pub withholding: Option<WithholdingRule>,
+    /// Unit of the payment amounts; `UsdCents` lists are converted with `lock_price`
+    #[serde(default)]
+    pub denomination: Denomination,
+}
+
+impl ListOptions {
+    /// Storage credits charged for a list of `payments` with these options: its records, as
+    /// stored under the duplicate policy, plus its metadata
+    fn storage_credits(&self, payments: &[PaymentInput]) -> u128 {
+        duplicates::storage_credits(payments, self.duplicates)
+            + self
+                .metadata
+                .as_ref()
+                .map_or(0, ListMetadata::storage_credits)
+    }
+}
+
+/// Descriptive list information for approvers, e.g. the invoice batch a list settles
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct ListMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// URL or hash of an off-chain document backing the list
+    pub reference: Option<String>,
+    /// Free-form category, e.g. "payroll" or "grants"
+    pub category: Option<String>,
+}
+
+impl ListMetadata {
+    const MAX_TITLE_LENGTH: usize = 128;
+    const MAX_DESCRIPTION_LENGTH: usize = 1024;
+    const MAX_REFERENCE_LENGTH: usize = 256;
+    const MAX_CATEGORY_LENGTH: usize = 32;
+
+    /// Panic if any field exceeds its maximum length
+    fn validate(&self) {
+        for (field, value, max_length) in [
+            ("title", &self.title, Self::MAX_TITLE_LENGTH),
+            (
+                "description",
+                &self.description,
+                Self::MAX_DESCRIPTION_LENGTH,
+            ),
+            ("reference", &self.reference, Self::MAX_REFERENCE_LENGTH),
+            ("category", &self.category, Self::MAX_CATEGORY_LENGTH),
+        ] {
+            require!(
+                value.as_ref().is_none_or(|value| value.len() <= max_length),
+                format!("List {} exceeds {} bytes", field, max_length)
+            );
+        }
+    }
+
+    /// Storage credits charged for the metadata, one per started record size
+    fn storage_credits(&self) -> u128 {
+        let bytes: usize = [
+            &self.title,
+            &self.description,
+            &self.reference,
+            &self.category,
+        ]
+        .into_iter()
+        .flatten()
+        .map(String::len)
+        .sum();
+        (bytes as u64).div_ceil(BYTES_PER_RECORD) as u128
+    }
 }
 
 #[near(serializers = [json, borsh])]
 #[derive(Clone)]
 pub enum ListStatus {
     Pending,
+    /// Some but not all of the list's total has been deposited
+    PartiallyFunded,
     Approved,
     Rejected,
 }
@@ -73,7 +382,12 @@ pub enum ListStatus {
 #[derive(Clone)]
 pub struct PaymentTransaction {
     pub recipient: AccountId,
+    /// Gross amount of the payment
     pub amount: U128,
+    /// Amount the recipient received, if tax was withheld
+    pub net_amount: Option<U128>,
+    pub withheld_amount: Option<U128>,
+    pub reference: Option<String>,
     pub block_height: u64,
 }
 
@@ -82,10 +396,25 @@ impl Default for BulkPaymentContract {
         Self {
             payment_lists: IterableMap::new(b"p"),
             storage_credits: IterableMap::new(b"s"),
+            payroll_templates: IterableMap::new(b"t"),
+            merkle_claims: LookupMap::new(b"m"),
+            validation_config: ValidationConfig::default(),
+            spending_limits: LookupMap::new(b"l"),
+            denylist: IterableSet::new(b"d"),
+            allowlist_submitters: LookupSet::new(b"e"),
+            allowlists: LookupSet::new(b"a"),
+            payout_queue: queue::PayoutQueue::new(b"q"),
+            price_oracle: PriceOracleConfig::default(),
+            legacy_lists: None,
         }
     }
 }
 
+/// Hex-encode a byte slice (lowercase), as used for list and template IDs
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
 /// NEP-245 Multi-Token Receiver trait
 /// This trait defines the callback interface for receiving multi-token transfers
 pub trait MultiTokenReceiver {
@@ -119,13 +448,6 @@ impl BulkPaymentContract {
     pub fn calculate_storage_cost(&self, num_records: u64) -> NearToken {
         require!(num_records > 0, "Number of records must be greater than 0");
 
-        // Calculate storage per record:
-        // - AccountId: 100 bytes max (UTF-8 string)
-        // - amount: 16 bytes (u128)
-        // - status: ~50 bytes (enum with error string)
-        // - overhead: ~50 bytes for Vec storage
-        const BYTES_PER_RECORD: u64 = 216;
-
         let storage_bytes = BYTES_PER_RECORD
             .checked_mul(num_records)
             .expect("Storage bytes calculation overflow");
@@ -175,7 +497,7 @@ impl BulkPaymentContract {
         );
 
         // Determine who receives the storage credits
-        let beneficiary = beneficiary_account_id.unwrap_or_else(|| env::predecessor_account_id());
+        let beneficiary = beneficiary_account_id.unwrap_or_else(env::predecessor_account_id);
 
         // Track storage credits for the beneficiary account
         let current_credits = self
@@ -214,28 +536,37 @@ impl BulkPaymentContract {
     ///               This hash should be calculated by the client and verified against
     ///               a pending DAO proposal before submission.
     /// * `token_id` - The token to use for payments ("native" for NEAR, or token contract ID)
-    /// * `payments` - List of payment records with recipient and amount
+    /// * `payments` - List of payment records with recipient, amount and optional memo/reference
     /// * `submitter_id` - Optional submitter account ID. If provided, only the contract account
     ///                    can call this function to submit on behalf of another account (e.g., a DAO).
     ///                    The submitter must have sufficient storage credits.
     ///                    If not provided, the caller becomes the submitter.
+    /// * `options` - Optional list settings, e.g. claim mode with a claim deadline or list
+    ///               metadata (charged one extra storage credit per started 216 bytes)
     ///
     /// # Returns
     /// The list_id that was passed in (for convenience in logging/tracking)
     pub fn submit_list(
         &mut self,
         list_id: ListId,
-        token_id: String,
+        token_id: TokenId,
         payments: Vec<PaymentInput>,
         submitter_id: Option<AccountId>,
+        options: Option<ListOptions>,
     ) -> ListId {
         require!(!payments.is_empty(), "Payment list cannot be empty");
+        let denomination = options
+            .as_ref()
+            .map_or(Denomination::Token, |options| options.denomination);
+        self.require_valid_payments(&token_id, &payments, denomination);
+        Self::validate_nft_payments(&token_id, &payments);
+        mixed::validate_payment_tokens(&payments);
         require!(
             Self::validate_list_id(&list_id),
             "Invalid list_id: must be a 64-character hex string (SHA-256 hash)"
         );
         require!(
-            self.payment_lists.get(&list_id).is_none(),
+            !self.is_list_id_taken(&list_id),
             "Payment list with this ID already exists"
         );
 
@@ -252,12 +583,179 @@ impl BulkPaymentContract {
         } else {
             caller.clone()
         };
+        self.require_screened_recipients(&submitter, &payments);
+
+        let options = options.unwrap_or_default();
+        Self::validate_list_options(&token_id, &payments, &options);
+
+        // Verify and deduct storage credits for the submitter
+        self.use_storage_credits(&submitter, options.storage_credits(&payments));
+
+        self.insert_pending_list(
+            list_id.clone(),
+            token_id,
+            submitter,
+            payments,
+            None,
+            options,
+        );
+
+        list_id
+    }
+
+    /// Pay the pending records of an Approved push-mode list as far as the gas allows,
+    /// then store the list and reward the caller if the list has a keeper fee
+    ///
+    /// Stops after `max_payments` payments if set. With `require_progress`, panics if
+    /// there is not enough gas for a single payment.
+    ///
+    /// # Returns
+    /// Number of payments of the list still pending
+    fn process_payouts(
+        &mut self,
+        list_id: &ListId,
+        mut list: PaymentList,
+        require_progress: bool,
+        max_payments: Option<u64>,
+    ) -> u64 {
+        // Reserve gas for final operations (storing list, logging)
+        let gas_reserve = gas::GAS_RESERVE;
+
+        let mut processed: u64 = 0;
+        let mut withheld: u128 = 0;
+        let mut first_pending_found = false;
+        // Gas used per payment besides the attached gas, replaced by what this call measures
+        let mut overhead = list.payment_overhead();
+        let mut measured: Option<Gas> = None;
+
+        for (index, payment) in list.payments.iter_mut().enumerate() {
+            if max_payments.is_some_and(|max| processed >= max) {
+                break;
+            }
+            if matches!(payment.status, PaymentStatus::Pending) {
+                if list
+                    .registration
+                    .as_ref()
+                    .is_some_and(|registration| registration.is_in_flight(index as u32))
+                {
+                    // Its registration callback decides whether it is paid
+                    continue;
+                }
+                // Screening lists may have changed since submission
+                if self
+                    .screening_issue(&list.submitter, &payment.recipient)
+                    .is_some()
+                {
+                    payment.status = PaymentStatus::Blocked {
+                        block_height: env::block_height(),
+                    };
+                    continue;
+                }
+
+                // Determine gas needed for this payment based on its token
+                let token_id = payment.token_id.as_ref().unwrap_or(&list.token_id);
+                let executor = executor::executor_with_gas(
+                    token_id,
+                    list.intents_delivery,
+                    gas::gas_override(list.attached_gas.as_deref(), token_id),
+                );
+                let gas_per_payment: Gas = if list.registration.is_some() {
+                    // Storage registration check, optional storage_deposit, then ft_transfer
+                    registration::registering_payment_gas(executor.attached_gas())
+                } else {
+                    executor.attached_gas().saturating_add(overhead)
+                };
+
+                // Check if we have enough gas for this payment
+                let gas_remaining = env::prepaid_gas()
+                    .as_gas()
+                    .saturating_sub(env::used_gas().as_gas());
+
+                if gas_remaining < gas_per_payment.as_gas() + gas_reserve.as_gas() {
+                    // Not enough gas for another payment
+                    if !first_pending_found && require_progress {
+                        // Haven't processed any payments yet - panic
+                        env::panic_str(&format!(
+                            "Insufficient gas to process payments. Need at least {} TGas, have {} TGas remaining",
+                            (gas_per_payment.as_gas() + gas_reserve.as_gas()) / 1_000_000_000_000,
+                            gas_remaining / 1_000_000_000_000
+                        ));
+                    }
+                    // Stop and let the caller call again
+                    break;
+                }
 
-        // Verify storage credits for the submitter
-        let required_credits = payments.len() as u128;
+                first_pending_found = true;
+                let used_before = env::used_gas();
+
+                if let Some(registration) = list.registration.as_mut() {
+                    // Marked paid once its recipient is registered and the transfer sent
+                    registration.start(index as u32);
+                    Self::registering_payment_promise(
+                        list.token_id
+                            .ft_contract()
+                            .expect("Registration requires a NEP-141 token"),
+                        executor.attached_gas(),
+                        list_id,
+                        index as u32,
+                        &payment.recipient,
+                    )
+                    .detach();
+                } else {
+                    withholding::pay_net(&*executor, payment).detach();
+
+                    // Mark as Paid with current block height
+                    payment.status = PaymentStatus::Paid {
+                        block_height: env::block_height(),
+                    };
+                    withheld += payment.withheld_amount.map_or(0, |amount| amount.0);
+                }
+                processed += 1;
+
+                if list.registration.is_none() {
+                    let spent = env::used_gas()
+                        .saturating_sub(used_before)
+                        .saturating_sub(executor.attached_gas());
+                    overhead = measured.map_or(spent, |measured| measured.max(spent));
+                    measured = Some(overhead);
+                }
+            }
+        }
+        if measured.is_some() {
+            list.measured_gas_overhead = measured;
+        }
+
+        list.reward_keeper(processed);
+        list.add_withheld(withheld);
+
+        // Count remaining pending payments
+        let remaining_pending = list
+            .payments
+            .iter()
+            .filter(|p| matches!(p.status, PaymentStatus::Pending))
+            .count() as u64;
+        if remaining_pending == 0 {
+            self.try_remit_withholding(list_id, &mut list);
+        }
+
+        // Update the list
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Processed {} payments for list {}, {} remaining",
+            processed,
+            list_id,
+            remaining_pending
+        );
+
+        remaining_pending
+    }
+
+    /// Deduct storage credits from an account, panicking if it has too few
+    fn use_storage_credits(&mut self, account_id: &AccountId, required_credits: u128) {
         let current_credits = self
             .storage_credits
-            .get(&submitter)
+            .get(account_id)
             .copied()
             .unwrap_or(NearToken::from_yoctonear(0))
             .as_yoctonear();
@@ -270,26 +768,153 @@ impl BulkPaymentContract {
             )
         );
 
-        // Deduct storage credits from the submitter
         let new_credits = NearToken::from_yoctonear(current_credits - required_credits);
-        self.storage_credits.insert(submitter.clone(), new_credits);
+        self.storage_credits.insert(account_id.clone(), new_credits);
+    }
+
+    /// Store a new payment list with all records in Pending status
+    /// Panic unless `options` are valid for a list of `token_id` paying `payments`
+    fn validate_list_options(token_id: &TokenId, payments: &[PaymentInput], options: &ListOptions) {
+        match options.payout_mode {
+            PayoutMode::Push => require!(
+                options.claim_deadline.is_none(),
+                "claim_deadline is only supported in claim mode"
+            ),
+            PayoutMode::Claim => require!(
+                options
+                    .claim_deadline
+                    .is_some_and(|deadline| deadline > env::block_timestamp()),
+                "Claim mode requires a claim_deadline in the future"
+            ),
+        }
+
+        if matches!(token_id, TokenId::Nft(_)) {
+            require!(
+                options.payout_mode == PayoutMode::Push,
+                "NFT lists are only supported in push mode"
+            );
+        }
+
+        if options.intents_delivery == IntentsDelivery::Transfer {
+            require!(
+                matches!(token_id, TokenId::Intents { .. }),
+                "intents_delivery Transfer requires a NEAR Intents (nep141:) token"
+            );
+        }
+
+        if mixed::has_mixed_tokens(token_id, payments) {
+            require!(
+                options.payout_mode == PayoutMode::Push && !options.register_recipients,
+                "Mixed-token lists only support push mode without recipient registration"
+            );
+        }
+
+        destination::validate_destinations(token_id, options.intents_delivery, payments);
+
+        if options.register_recipients {
+            require!(
+                options.payout_mode == PayoutMode::Push,
+                "register_recipients is only supported in push mode"
+            );
+            require!(
+                token_id.ft_contract().is_some(),
+                "register_recipients requires a NEP-141 token contract"
+            );
+        }
+
+        if options.keeper_fee.is_some() {
+            require!(
+                options.payout_mode == PayoutMode::Push,
+                "keeper_fee is only supported in push mode"
+            );
+            require!(
+                options.keeper_fee.is_some_and(|fee| fee.0 > 0),
+                "keeper_fee must be positive"
+            );
+        }
+        keepers::validate_operators(&options.operators);
+        if let Some(attached_gas) = &options.attached_gas {
+            gas::validate_attached_gas(attached_gas);
+        }
+        if options.denomination == Denomination::UsdCents {
+            require!(
+                matches!(token_id, TokenId::Native | TokenId::Nep141(_))
+                    && !mixed::has_mixed_tokens(token_id, payments),
+                "USD-denominated lists must pay NEAR or a NEP-141 token"
+            );
+        }
+        if let Some(withholding) = &options.withholding {
+            withholding.validate();
+            require!(
+                options.payout_mode == PayoutMode::Push,
+                "withholding is only supported in push mode"
+            );
+            require!(
+                !matches!(token_id, TokenId::Nft(_))
+                    && !mixed::has_mixed_tokens(token_id, payments),
+                "withholding requires a single fungible token"
+            );
+        }
+        if let Some(metadata) = &options.metadata {
+            metadata.validate();
+        }
+        if options.duplicates == DuplicatePolicy::Reject {
+            duplicates::reject_duplicates(payments);
+        }
+    }
+
+    fn insert_pending_list(
+        &mut self,
+        list_id: ListId,
+        token_id: TokenId,
+        submitter: AccountId,
+        payments: Vec<PaymentInput>,
+        template_id: Option<TemplateId>,
+        options: ListOptions,
+    ) {
+        let funded = mixed::has_mixed_tokens(&token_id, &payments).then(Vec::new);
 
         // Convert PaymentInput to PaymentRecord with Pending status
-        let payment_records: Vec<PaymentRecord> = payments
-            .into_iter()
-            .map(|input| PaymentRecord {
-                recipient: input.recipient,
-                amount: input.amount,
-                status: PaymentStatus::Pending,
-            })
-            .collect();
+        let mut payment_records = duplicates::into_records(payments, options.duplicates);
+        let withholding = options
+            .withholding
+            .map(withholding::WithholdingRule::into_withholding);
+        if let Some(withholding) = &withholding {
+            withholding.apply(&mut payment_records);
+        }
+        let fiat = (options.denomination == Denomination::UsdCents)
+            .then(|| self.usd_denomination(&token_id, &payment_records));
 
+        let nft_received = matches!(token_id, TokenId::Nft(_)).then(Vec::new);
         let payment_list = PaymentList {
             token_id,
             submitter: submitter.clone(),
             status: ListStatus::Pending,
             payments: payment_records,
             created_at: env::block_timestamp(),
+            template_id,
+            payout_mode: options.payout_mode,
+            claim_deadline: options.claim_deadline,
+            merkle: None,
+            metadata: options.metadata,
+            registration: options
+                .register_recipients
+                .then(RecipientRegistration::default),
+            preflight: None,
+            nft_received,
+            intents_delivery: options.intents_delivery,
+            funded_amount: U128(0),
+            funded,
+            keeper_rewards: options.keeper_fee.map(|fee_per_payment| KeeperRewards {
+                fee_per_payment,
+                ..Default::default()
+            }),
+            operators: options.operators,
+            attached_gas: options.attached_gas,
+            measured_gas_overhead: None,
+            split: None,
+            withholding,
+            fiat,
         };
 
         let num_payments = payment_list.payments.len();
@@ -301,16 +926,16 @@ impl BulkPaymentContract {
             submitter,
             num_payments
         );
-
-        list_id
     }
 
-    /// Approve a payment list and attach the exact deposit amount
+    /// Fund a native NEAR payment list with the attached deposit, approving it once the
+    /// total is covered. The list may be funded over several calls; any deposit beyond
+    /// the outstanding amount is refunded.
     #[payable]
     pub fn approve_list(&mut self, list_id: ListId) {
         let caller = env::predecessor_account_id();
 
-        let mut list = self
+        let list = self
             .payment_lists
             .get(&list_id)
             .expect("Payment list not found")
@@ -322,38 +947,55 @@ impl BulkPaymentContract {
         );
 
         require!(
-            matches!(list.status, ListStatus::Pending),
-            "List must be in Pending status"
+            list.is_awaiting_funding(),
+            "List must be in Pending or PartiallyFunded status"
         );
-
-        // Calculate total payment amount (with overflow check)
-        let total_amount: u128 = list
-            .payments
-            .iter()
-            .map(|p| p.amount.0)
-            .try_fold(0u128, |acc, x| acc.checked_add(x))
-            .expect("Total payment amount overflow");
-
-        let attached = env::attached_deposit();
-        let required = NearToken::from_yoctonear(total_amount);
-
         require!(
-            attached == required,
-            format!(
-                "Exact deposit required: {}, attached: {}",
-                required, attached
-            )
+            list.nft_received.is_none(),
+            "NFT lists are funded via nft_transfer_call"
         );
 
-        // Update list status
-        list.status = ListStatus::Approved;
-        self.payment_lists.insert(list_id.clone(), list);
+        // The keeper budget is covered first, then lists registering recipients keep the
+        // rest as their registration budget
+        let mut list = list;
+        let deposit = env::attached_deposit().as_yoctonear();
+        let for_keepers = list.fund_keeper_rewards(deposit);
+        let attached = list.fund_registration(for_keepers);
+        if attached == 0 && deposit > 0 {
+            self.payment_lists.insert(list_id.clone(), list);
+            if deposit > for_keepers {
+                log!(
+                    "Keeper budget of list {} funded with {}",
+                    list_id,
+                    deposit - for_keepers
+                );
+            }
+            if for_keepers > 0 {
+                log!(
+                    "Registration budget of list {} funded with {}",
+                    list_id,
+                    for_keepers
+                );
+            }
+            return;
+        }
 
-        log!(
-            "Payment list {} approved with deposit {}",
-            list_id,
-            attached
-        );
+        let excess = if list.is_mixed() {
+            // Only funds the NEAR payments, the other tokens arrive separately
+            self.fund_mixed_list(list_id, list, TokenId::Native, attached)
+        } else {
+            require!(
+                list.token_id.is_native(),
+                "Token lists are funded via ft_transfer_call or mt_transfer_call"
+            );
+            self.fund_list(list_id, list, attached)
+        };
+
+        if excess > 0 {
+            Promise::new(caller)
+                .transfer(NearToken::from_yoctonear(excess))
+                .detach();
+        }
     }
 
     /// Process payments in batches (public function, anyone can call)
@@ -362,10 +1004,11 @@ impl BulkPaymentContract {
     /// within the available gas. It checks remaining gas before each payment and stops
     /// when there's not enough gas for another payment plus reserve for final operations.
     ///
-    /// Gas costs per payment type:
-    /// - Native NEAR: ~3 TGas per transfer
-    /// - NEP-141 FT: ~50 TGas per ft_transfer
-    /// - NEAR Intents: ~50 TGas per ft_withdraw
+    /// Gas per payment is the gas attached to its call plus the contract's own cost,
+    /// measured per payment (3 TGas until measured):
+    /// - Native NEAR: no attached gas
+    /// - NEP-141 FT: 50 TGas per ft_transfer, unless the list sets `attached_gas`
+    /// - NEAR Intents: 50 TGas per ft_withdraw, 15 TGas per mt_transfer
     ///
     /// Worker should call with 300 TGas for maximum throughput.
     ///
@@ -376,6 +1019,9 @@ impl BulkPaymentContract {
     /// Future optimization: Use IterableMap for payments instead of Vec to avoid full clone,
     /// or implement pagination for the payment list.
     ///
+    /// `max_payments` caps the number of payments processed by this call, e.g. to
+    /// throttle payouts; `simulate_payout_batch` shows which records a call would pay.
+    ///
     /// # Returns
     /// Number of remaining pending payments after this batch. Returns 0 when all payments
     /// are complete. The caller should keep calling until this returns 0.
@@ -383,9 +1029,11 @@ impl BulkPaymentContract {
     /// # Panics
     /// - If the payment list is not found
     /// - If the list is not in Approved status
+    /// - If `max_payments` is 0
     /// - If there's not enough gas to process at least one payment
-    pub fn payout_batch(&mut self, list_id: ListId) -> u64 {
-        let mut list = self
+    pub fn payout_batch(&mut self, list_id: ListId, max_payments: Option<u32>) -> u64 {
+        require!(max_payments != Some(0), "max_payments must be positive");
+        let list = self
             .payment_lists
             .get(&list_id)
             .expect("Payment list not found")
@@ -395,126 +1043,13 @@ impl BulkPaymentContract {
             matches!(list.status, ListStatus::Approved),
             "List must be Approved to process payments"
         );
-
-        // Determine gas needed per payment based on token type
-        let gas_per_payment: Gas = if list.token_id.starts_with("nep141:") {
-            // NEAR Intents: ft_withdraw cross-contract call
-            Gas::from_tgas(50)
-        } else if list.token_id == "native" || list.token_id == "near" || list.token_id == "NEAR" {
-            // Native NEAR: minimal gas per transfer
-            Gas::from_tgas(3)
-        } else {
-            // NEP-141 FT: ft_transfer cross-contract call
-            Gas::from_tgas(50)
-        };
-
-        // Reserve gas for final operations (storing list, logging)
-        let gas_reserve = Gas::from_tgas(15);
-
-        let mut processed: u64 = 0;
-        let mut first_pending_found = false;
-
-        for payment in list.payments.iter_mut() {
-            if matches!(payment.status, PaymentStatus::Pending) {
-                // Check if we have enough gas for this payment
-                let gas_remaining = env::prepaid_gas()
-                    .as_gas()
-                    .saturating_sub(env::used_gas().as_gas());
-
-                if gas_remaining < gas_per_payment.as_gas() + gas_reserve.as_gas() {
-                    // Not enough gas for another payment
-                    if !first_pending_found {
-                        // Haven't processed any payments yet - panic
-                        env::panic_str(&format!(
-                            "Insufficient gas to process payments. Need at least {} TGas, have {} TGas remaining",
-                            (gas_per_payment.as_gas() + gas_reserve.as_gas()) / 1_000_000_000_000,
-                            gas_remaining / 1_000_000_000_000
-                        ));
-                    }
-                    // Already processed some, stop and let caller call again
-                    break;
-                }
-
-                first_pending_found = true;
-
-                if list.token_id.starts_with("nep141:") {
-                    // NEAR Intents - call ft_withdraw on intents.near
-                    let token_contract = list.token_id.strip_prefix("nep141:").unwrap();
-
-                    // PoA tokens require WITHDRAW_TO memo for external chain withdrawals
-                    let is_poa_token = token_contract.ends_with(".omft.near");
-
-                    let args_json = if is_poa_token {
-                        format!(
-                            r#"{{"token":"{}","receiver_id":"{}","amount":"{}","memo":"WITHDRAW_TO:{}"}}"#,
-                            token_contract, token_contract, payment.amount.0, payment.recipient
-                        )
-                    } else {
-                        format!(
-                            r#"{{"token":"{}","receiver_id":"{}","amount":"{}"}}"#,
-                            token_contract, payment.recipient, payment.amount.0
-                        )
-                    };
-
-                    Promise::new("intents.near".parse().unwrap()).function_call(
-                        "ft_withdraw".to_string(),
-                        args_json.into_bytes(),
-                        NearToken::from_yoctonear(1),
-                        Gas::from_tgas(50),
-                    );
-                } else if list.token_id == "native"
-                    || list.token_id == "near"
-                    || list.token_id == "NEAR"
-                {
-                    // Native NEAR transfer
-                    Promise::new(payment.recipient.clone())
-                        .transfer(NearToken::from_yoctonear(payment.amount.0));
-                } else {
-                    // NEP-141 fungible token transfer
-                    let token_account: AccountId = list
-                        .token_id
-                        .parse()
-                        .expect("Invalid token contract address");
-
-                    let args = format!(
-                        r#"{{"receiver_id":"{}","amount":"{}"}}"#,
-                        payment.recipient, payment.amount.0
-                    );
-
-                    Promise::new(token_account).function_call(
-                        "ft_transfer".to_string(),
-                        args.into_bytes(),
-                        NearToken::from_yoctonear(1),
-                        Gas::from_tgas(50),
-                    );
-                }
-
-                // Mark as Paid with current block height
-                payment.status = PaymentStatus::Paid {
-                    block_height: env::block_height(),
-                };
-                processed += 1;
-            }
-        }
-
-        // Update the list
-        self.payment_lists.insert(list_id.clone(), list.clone());
-
-        // Count remaining pending payments
-        let remaining_pending = list
-            .payments
-            .iter()
-            .filter(|p| matches!(p.status, PaymentStatus::Pending))
-            .count() as u64;
-
-        log!(
-            "Processed {} payments for list {}, {} remaining",
-            processed,
-            list_id,
-            remaining_pending
+        require!(
+            list.payout_mode == PayoutMode::Push,
+            "List uses claim mode, payments must be claimed by recipients"
         );
+        list.require_operator();
 
-        remaining_pending
+        self.process_payouts(&list_id, list, true, max_payments.map(u64::from))
     }
 
     /// Reject a payment list (only allowed before approval)
@@ -533,9 +1068,11 @@ impl BulkPaymentContract {
         );
 
         require!(
-            matches!(list.status, ListStatus::Pending),
+            list.is_awaiting_funding(),
             "Only pending lists can be rejected"
         );
+        self.return_received_nfts(&list_id);
+        self.return_funding(&list_id);
 
         // Update status
         list.status = ListStatus::Rejected;
@@ -568,6 +1105,9 @@ impl BulkPaymentContract {
                     Some(PaymentTransaction {
                         recipient: p.recipient.clone(),
                         amount: p.amount,
+                        net_amount: p.net_amount,
+                        withheld_amount: p.withheld_amount,
+                        reference: p.reference.clone(),
                         block_height: *block_height,
                     })
                 } else {
@@ -587,7 +1127,7 @@ impl BulkPaymentContract {
 
     /// NEP-141 ft_on_transfer callback for fungible token approval flow
     /// This is called by the token contract after ft_transfer_call
-    /// Returns the amount to refund (0 if all tokens are kept)
+    /// Returns the amount to refund: whatever exceeds the outstanding total of the list
     ///
     /// The `msg` parameter should be the list_id (hex-encoded SHA-256 hash)
     pub fn ft_on_transfer(&mut self, sender_id: AccountId, amount: U128, msg: String) -> U128 {
@@ -600,7 +1140,7 @@ impl BulkPaymentContract {
         );
 
         // Get the list
-        let mut list = self
+        let list = self
             .payment_lists
             .get(&list_id)
             .expect("Payment list not found")
@@ -612,41 +1152,32 @@ impl BulkPaymentContract {
             "Only the submitter can approve the list via ft_transfer_call"
         );
 
-        // Validate list is in Pending status
+        // Validate list is awaiting funding
+        require!(
+            list.is_awaiting_funding(),
+            "List must be in Pending or PartiallyFunded status"
+        );
         require!(
-            matches!(list.status, ListStatus::Pending),
-            "List must be in Pending status"
+            list.nft_received.is_none(),
+            "NFT lists are funded via nft_transfer_call"
         );
 
-        // Calculate total payment amount
-        let total_amount: u128 = list
-            .payments
-            .iter()
-            .map(|p| p.amount.0)
-            .try_fold(0u128, |acc, x| acc.checked_add(x))
-            .expect("Total payment amount overflow");
+        let token_id = TokenId::Nep141(env::predecessor_account_id());
+        if list.is_mixed() {
+            return U128(self.fund_mixed_list(list_id, list, token_id, amount.0));
+        }
 
-        // Validate amount matches total
+        // Validate token matches the list
         require!(
-            amount.0 == total_amount,
+            list.token_id == token_id,
             format!(
-                "Exact token amount required: {}, received: {}",
-                total_amount, amount.0
+                "Token mismatch: list expects '{}', received '{}'",
+                list.token_id, token_id
             )
         );
 
-        // Approve the list
-        list.status = ListStatus::Approved;
-        self.payment_lists.insert(list_id.clone(), list);
-
-        log!(
-            "Payment list {} approved via ft_transfer_call with {} tokens",
-            list_id,
-            amount.0
-        );
-
-        // Return 0 to keep all tokens
-        U128(0)
+        // Keep what the list still needs, return the rest
+        U128(self.fund_list(list_id, list, amount.0))
     }
 }
 
@@ -669,14 +1200,15 @@ impl MultiTokenReceiver for BulkPaymentContract {
     /// * `msg` - Message containing the list_id (hex-encoded SHA-256 hash)
     ///
     /// # Returns
-    /// Array of unused token amounts to refund (as strings). Returns all zeros to keep all tokens.
+    /// Array of unused token amounts to refund (as strings): whatever exceeds the outstanding
+    /// total of the list.
     ///
     /// # Panics
     /// - If msg is not a valid list_id (64-character hex string)
     /// - If payment list is not found
-    /// - If list is not in Pending status
+    /// - If list is not in Pending or PartiallyFunded status
     /// - If token_ids/amounts arrays don't match expectations
-    /// - If transferred amount doesn't match required total
+    /// - If the caller is not the intents contract of the transferred token
     fn mt_on_transfer(
         &mut self,
         sender_id: AccountId,
@@ -697,7 +1229,7 @@ impl MultiTokenReceiver for BulkPaymentContract {
         );
 
         // Get the list
-        let mut list = self
+        let list = self
             .payment_lists
             .get(&list_id)
             .expect("Payment list not found")
@@ -708,10 +1240,10 @@ impl MultiTokenReceiver for BulkPaymentContract {
         // The submitter is the account that created the payment list
         // We allow token owners to approve lists even if they didn't submit them
 
-        // Validate list is in Pending status
+        // Validate list is awaiting funding
         require!(
-            matches!(list.status, ListStatus::Pending),
-            "List must be in Pending status to approve via mt_transfer_call"
+            list.is_awaiting_funding(),
+            "List must be in Pending or PartiallyFunded status to fund via mt_transfer_call"
         );
 
         // For single token transfers, expect exactly one token
@@ -720,48 +1252,35 @@ impl MultiTokenReceiver for BulkPaymentContract {
             "Expected exactly one token transfer"
         );
 
-        let token_id = &token_ids[0];
+        let token_id: TokenId = token_ids[0]
+            .parse()
+            .unwrap_or_else(|err: String| env::panic_str(&err));
         let amount = amounts[0];
 
-        // Validate token_id matches the list
+        // Only the multi-token contract holding the tokens can report a transfer of them
         require!(
-            list.token_id == *token_id,
-            format!(
-                "Token ID mismatch: list expects '{}', received '{}'",
-                list.token_id, token_id
-            )
+            matches!(&token_id, TokenId::Intents { contract, .. }
+                if *contract == env::predecessor_account_id()),
+            "mt_on_transfer can only be called by the intents contract for its own tokens"
         );
 
-        // Calculate total payment amount
-        let total_amount: u128 = list
-            .payments
-            .iter()
-            .map(|p| p.amount.0)
-            .try_fold(0u128, |acc, x| acc.checked_add(x))
-            .expect("Total payment amount overflow");
+        if list.is_mixed() {
+            let excess = self.fund_mixed_list(list_id, list, token_id, amount.0);
+            return PromiseOrValue::Value(vec![U128(excess)]);
+        }
 
-        // Validate amount matches total
+        // Validate token_id matches the list
         require!(
-            amount.0 == total_amount,
+            list.token_id == token_id,
             format!(
-                "Exact token amount required: {}, received: {}",
-                total_amount, amount.0
+                "Token ID mismatch: list expects '{}', received '{}'",
+                list.token_id, token_id
             )
         );
 
-        // Approve the list
-        list.status = ListStatus::Approved;
-        self.payment_lists.insert(list_id.clone(), list);
-
-        log!(
-            "Payment list {} approved via mt_transfer_call with {} tokens ({})",
-            list_id,
-            amount.0,
-            token_id
-        );
-
-        // Return all zeros to keep all tokens (no refunds)
-        PromiseOrValue::Value(vec![U128(0); token_ids.len()])
+        // Keep what the list still needs, return the rest
+        let excess = self.fund_list(list_id, list, amount.0);
+        PromiseOrValue::Value(vec![U128(excess)])
     }
 }
 
@@ -850,16 +1369,26 @@ mod tests {
             PaymentInput {
                 recipient: accounts(1),
                 amount: U128(1_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
             PaymentInput {
                 recipient: accounts(2),
                 amount: U128(2_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
         ];
 
         let list_id = test_list_id("1");
         let returned_id =
-            contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+            contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // Verify credits were deducted (10 - 2 = 8)
         let credits = contract.view_storage_credits(accounts(0));
@@ -883,10 +1412,15 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         // Should panic - no storage credits
-        contract.submit_list(test_list_id("1"), "native".to_string(), payments, None);
+        contract.submit_list(test_list_id("1"), TokenId::Native, payments, None, None);
     }
 
     #[test]
@@ -908,15 +1442,25 @@ mod tests {
             PaymentInput {
                 recipient: accounts(1),
                 amount: U128(1_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
             PaymentInput {
                 recipient: accounts(2),
                 amount: U128(2_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
         ];
 
         let list_id = test_list_id("approve_test");
-        contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // Approve with exact deposit (3 NEAR total)
         let total_deposit = NearToken::from_yoctonear(3_000_000_000_000_000_000_000_000);
@@ -931,8 +1475,7 @@ mod tests {
     }
 
     #[test]
-    #[should_panic(expected = "Exact deposit required")]
-    fn test_approve_list_wrong_deposit() {
+    fn test_approve_list_partial_deposit() {
         let mut context = get_context(accounts(0));
 
         // Setup
@@ -949,17 +1492,26 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
-        let list_id = test_list_id("wrong_deposit");
-        contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+        let list_id = test_list_id("partial_deposit");
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
-        // Try to approve with wrong deposit
-        let wrong_deposit = NearToken::from_yoctonear(500_000_000_000_000_000_000_000);
-        context.attached_deposit(wrong_deposit);
+        // Fund half of the total
+        let partial_deposit = NearToken::from_yoctonear(500_000_000_000_000_000_000_000);
+        context.attached_deposit(partial_deposit);
         testing_env!(context.build());
 
-        contract.approve_list(list_id); // Should panic
+        contract.approve_list(list_id.clone());
+
+        let list = contract.view_list(list_id);
+        assert!(matches!(list.status, ListStatus::PartiallyFunded));
+        assert_eq!(list.funded_amount, U128(500_000_000_000_000_000_000_000));
     }
 
     #[test]
@@ -981,10 +1533,15 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         let list_id = test_list_id("unauthorized");
-        contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // User 1 tries to approve (should fail)
         context = get_context(accounts(1));
@@ -1013,10 +1570,15 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         let list_id = test_list_id("reject_test");
-        contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // Reject without approval first
         contract.reject_list(list_id.clone());
@@ -1044,10 +1606,15 @@ mod tests {
         let payments = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         let list_id = test_list_id("reject_approved");
-        contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // Approve the list
         context.attached_deposit(NearToken::from_yoctonear(1_000_000_000_000_000_000_000_000));
@@ -1079,20 +1646,30 @@ mod tests {
         let payments1 = vec![PaymentInput {
             recipient: accounts(1),
             amount: U128(1_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         let payments2 = vec![PaymentInput {
             recipient: accounts(2),
             amount: U128(2_000_000_000_000_000_000_000_000),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
         }];
 
         let list_id1 = test_list_id("multi_1");
         let list_id2 = test_list_id("multi_2");
 
         let returned_id1 =
-            contract.submit_list(list_id1.clone(), "native".to_string(), payments1, None);
+            contract.submit_list(list_id1.clone(), TokenId::Native, payments1, None, None);
         let returned_id2 =
-            contract.submit_list(list_id2.clone(), "native".to_string(), payments2, None);
+            contract.submit_list(list_id2.clone(), TokenId::Native, payments2, None, None);
 
         assert_eq!(returned_id1, list_id1);
         assert_eq!(returned_id2, list_id2);
@@ -1198,16 +1775,26 @@ mod tests {
             PaymentInput {
                 recipient: accounts(2),
                 amount: U128(1_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
             PaymentInput {
                 recipient: accounts(3),
                 amount: U128(2_000_000_000_000_000_000_000_000),
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
             },
         ];
 
         let list_id = test_list_id("beneficiary_test");
         let returned_id =
-            contract.submit_list(list_id.clone(), "native".to_string(), payments, None);
+            contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
 
         // Verify credits were deducted from User 1 (10 - 2 = 8)
         let credits = contract.view_storage_credits(accounts(1));
@@ -1220,6 +1807,191 @@ mod tests {
         assert_eq!(list.submitter, accounts(1));
     }
 
+    #[test]
+    fn test_payment_memo_and_reference_are_stored() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(2));
+        testing_env!(context.build());
+
+        let mut contract = BulkPaymentContract::default();
+        contract.buy_storage(2, None);
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+
+        let payments = vec![PaymentInput {
+            recipient: accounts(1),
+            amount: U128(100),
+            memo: Some("Invoice INV-42".to_string()),
+            reference: Some("INV-42".to_string()),
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let list_id = test_list_id("memo");
+        contract.submit_list(list_id.clone(), TokenId::Native, payments, None, None);
+
+        let list = contract.view_list(list_id);
+        assert_eq!(list.payments[0].memo.as_deref(), Some("Invoice INV-42"));
+        assert_eq!(list.payments[0].reference.as_deref(), Some("INV-42"));
+        // The memo and reference use a second credit
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "payment 0: memo exceeds 128 bytes")]
+    fn test_submit_list_memo_too_long() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(1));
+        testing_env!(context.build());
+
+        let mut contract = BulkPaymentContract::default();
+        contract.buy_storage(1, None);
+
+        let payments = vec![PaymentInput {
+            recipient: accounts(1),
+            amount: U128(100),
+            memo: Some("x".repeat(MAX_PAYMENT_FIELD_LENGTH + 1)),
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        contract.submit_list(test_list_id("memo"), TokenId::Native, payments, None, None);
+    }
+
+    #[test]
+    fn test_submit_list_with_metadata() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(10));
+        testing_env!(context.build());
+
+        let mut contract = BulkPaymentContract::default();
+        contract.buy_storage(10, None);
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+
+        let payments = vec![PaymentInput {
+            recipient: accounts(1),
+            amount: U128(100),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let list_id = test_list_id("metadata");
+        contract.submit_list(
+            list_id.clone(),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                metadata: Some(ListMetadata {
+                    title: Some("March payroll".to_string()),
+                    category: Some("payroll".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+        );
+
+        // One credit for the payment, one for the metadata
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 8);
+        let metadata = contract.view_list(list_id).metadata.unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("March payroll"));
+        assert_eq!(metadata.category.as_deref(), Some("payroll"));
+        assert!(metadata.description.is_none());
+    }
+
+    #[test]
+    fn test_intents_transfer_delivery_uses_mt_transfer() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(1));
+        testing_env!(context.build());
+
+        let mut contract = BulkPaymentContract::default();
+        contract.buy_storage(1, None);
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+
+        let payments = vec![PaymentInput {
+            recipient: accounts(1),
+            amount: U128(100),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        let list_id = test_list_id("mt");
+        contract.submit_list(
+            list_id.clone(),
+            "nep141:wrap.near".parse().unwrap(),
+            payments,
+            None,
+            Some(ListOptions {
+                intents_delivery: IntentsDelivery::Transfer,
+                ..Default::default()
+            }),
+        );
+
+        context.predecessor_account_id("intents.near".parse().unwrap());
+        testing_env!(context.build());
+        let _ = contract.mt_on_transfer(
+            accounts(0),
+            vec![accounts(0)],
+            vec!["nep141:wrap.near".to_string()],
+            vec![U128(100)],
+            list_id.clone(),
+        );
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(list_id, None);
+
+        let receipts = near_sdk::test_utils::get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert!(matches!(
+            &receipts[0].actions[..],
+            [near_sdk::mock::MockAction::FunctionCallWeight { method_name, .. }]
+                if method_name == b"mt_transfer"
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "intents_delivery Transfer requires a NEAR Intents (nep141:) token")]
+    fn test_intents_transfer_delivery_requires_intents_token() {
+        let mut context = get_context(accounts(0));
+        context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(1));
+        testing_env!(context.build());
+
+        let mut contract = BulkPaymentContract::default();
+        contract.buy_storage(1, None);
+
+        let payments = vec![PaymentInput {
+            recipient: accounts(1),
+            amount: U128(100),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }];
+        contract.submit_list(
+            test_list_id("mt"),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                intents_delivery: IntentsDelivery::Transfer,
+                ..Default::default()
+            }),
+        );
+    }
+
     // Note: Overflow protection tests are implicitly validated by the NEAR runtime environment.
     // The environment checks account balances and prevents unrealistic values before our
     // contract code executes, providing an additional layer of security. Our checked_*
@@ -0,0 +1,383 @@
+// Spending limits per submitter and token
+//
+// A submitter can be given limits per token: the most a single list may pay, the most a
+// single recipient may receive from one list and the most all of its lists may pay within
+// a rolling 30-day window. Limits are checked whenever a list is funded, so funding that
+// would approve a list beyond them is refused, and the list total counts towards the
+// window once the list is Approved. Limits are set by the contract account or by the
+// submitter itself (typically a DAO, through a proposal).
+use near_sdk::json_types::U128;
+use near_sdk::{env, log, near, require, AccountId};
+
+use crate::{BulkPaymentContract, BulkPaymentContractExt, PaymentList, TokenAmount, TokenId};
+
+/// Length of the rolling spending window
+const SPENDING_WINDOW_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Limits of a submitter for one token; unset limits do not apply
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpendingLimit {
+    pub token_id: TokenId,
+    pub max_per_list: Option<U128>,
+    pub max_per_recipient: Option<U128>,
+    pub max_per_30_days: Option<U128>,
+}
+
+/// Amount of an approved list counting towards the rolling window
+#[near(serializers = [borsh])]
+#[derive(Clone)]
+struct Spend {
+    token_id: TokenId,
+    amount: u128,
+    approved_at: u64,
+}
+
+/// Limits of a submitter and its spending within the current window
+#[near(serializers = [borsh])]
+#[derive(Clone, Default)]
+pub(crate) struct SubmitterLimits {
+    limits: Vec<SpendingLimit>,
+    spends: Vec<Spend>,
+}
+
+impl Spend {
+    fn in_window(&self) -> bool {
+        self.approved_at.saturating_add(SPENDING_WINDOW_NS) > env::block_timestamp()
+    }
+}
+
+impl SubmitterLimits {
+    /// Total approved in `token_id` within the last 30 days
+    fn spent_in_window(&self, token_id: &TokenId) -> u128 {
+        self.spends
+            .iter()
+            .filter(|spend| spend.token_id == *token_id && spend.in_window())
+            .map(|spend| spend.amount)
+            .sum()
+    }
+}
+
+/// What a submitter may still spend in a token
+#[near(serializers = [json])]
+pub struct SpendingAllowance {
+    pub limit: Option<SpendingLimit>,
+    /// Total of the lists approved within the last 30 days
+    pub spent_last_30_days: U128,
+    /// Left of `max_per_30_days`, if set
+    pub remaining_30_days: Option<U128>,
+}
+
+impl BulkPaymentContract {
+    /// Panic if paying out `list` would exceed a spending limit of its submitter
+    pub(crate) fn check_spending_limits(&self, list: &PaymentList) {
+        let Some(submitter) = self.spending_limits.get(&list.submitter) else {
+            return;
+        };
+        for TokenAmount { token_id, amount } in list.token_totals() {
+            let Some(limit) = submitter.limits.iter().find(|l| l.token_id == token_id) else {
+                continue;
+            };
+            if let Some(max) = limit.max_per_list {
+                require!(
+                    amount.0 <= max.0,
+                    format!(
+                        "List pays {} {}, the limit per list is {}",
+                        amount.0, token_id, max.0
+                    )
+                );
+            }
+            if let Some(max) = limit.max_per_recipient {
+                let mut per_recipient: Vec<(&AccountId, u128)> = Vec::new();
+                for payment in list
+                    .payments
+                    .iter()
+                    .filter(|p| *list.payment_token(p) == token_id)
+                {
+                    match per_recipient
+                        .iter_mut()
+                        .find(|(recipient, _)| **recipient == payment.recipient)
+                    {
+                        Some((_, total)) => *total += payment.amount.0,
+                        None => per_recipient.push((&payment.recipient, payment.amount.0)),
+                    }
+                }
+                for (recipient, total) in per_recipient {
+                    require!(
+                        total <= max.0,
+                        format!(
+                            "List pays {} {} to {}, the limit per recipient is {}",
+                            total, token_id, recipient, max.0
+                        )
+                    );
+                }
+            }
+            if let Some(max) = limit.max_per_30_days {
+                let spent = submitter.spent_in_window(&token_id);
+                require!(
+                    spent.saturating_add(amount.0) <= max.0,
+                    format!(
+                        "List pays {} {}, {} of the 30-day limit of {} is left",
+                        amount.0,
+                        token_id,
+                        max.0.saturating_sub(spent),
+                        max.0
+                    )
+                );
+            }
+        }
+    }
+
+    /// Count an approved list towards the rolling limits of its submitter
+    pub(crate) fn record_spending(&mut self, list: &PaymentList) {
+        let Some(submitter) = self.spending_limits.get_mut(&list.submitter) else {
+            return;
+        };
+        submitter.spends.retain(Spend::in_window);
+        for total in list.token_totals() {
+            if submitter
+                .limits
+                .iter()
+                .any(|l| l.token_id == total.token_id && l.max_per_30_days.is_some())
+            {
+                submitter.spends.push(Spend {
+                    token_id: total.token_id,
+                    amount: total.amount.0,
+                    approved_at: env::block_timestamp(),
+                });
+            }
+        }
+    }
+
+    fn require_limit_admin(submitter_id: &AccountId) {
+        let caller = env::predecessor_account_id();
+        require!(
+            caller == env::current_account_id() || caller == *submitter_id,
+            "Only the contract account or the submitter can change its spending limits"
+        );
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Set the spending limit of a submitter for one token, replacing any previous one
+    /// (contract account or the submitter itself). A submitter setting a limit for a new
+    /// token uses one storage credit.
+    pub fn set_spending_limit(&mut self, submitter_id: AccountId, limit: SpendingLimit) {
+        Self::require_limit_admin(&submitter_id);
+        let is_new = self
+            .spending_limits
+            .get(&submitter_id)
+            .is_none_or(|s| s.limits.iter().all(|l| l.token_id != limit.token_id));
+        if is_new && env::predecessor_account_id() != env::current_account_id() {
+            self.use_storage_credits(&submitter_id, 1);
+        }
+        let submitter = self
+            .spending_limits
+            .entry(submitter_id.clone())
+            .or_default();
+        submitter.limits.retain(|l| l.token_id != limit.token_id);
+        log!(
+            "Spending limit of {} for {} set by {}",
+            submitter_id,
+            limit.token_id,
+            env::predecessor_account_id()
+        );
+        submitter.limits.push(limit);
+    }
+
+    /// Remove the spending limit of a submitter for one token (contract account or the
+    /// submitter itself)
+    pub fn remove_spending_limit(&mut self, submitter_id: AccountId, token_id: TokenId) {
+        Self::require_limit_admin(&submitter_id);
+        if let Some(submitter) = self.spending_limits.get_mut(&submitter_id) {
+            submitter.limits.retain(|l| l.token_id != token_id);
+        }
+        log!(
+            "Spending limit of {} for {} removed",
+            submitter_id,
+            token_id
+        );
+    }
+
+    /// Spending limits of a submitter
+    pub fn view_spending_limits(&self, submitter_id: AccountId) -> Vec<SpendingLimit> {
+        self.spending_limits
+            .get(&submitter_id)
+            .map(|submitter| submitter.limits.clone())
+            .unwrap_or_default()
+    }
+
+    /// Limit of a submitter for a token and what it may still spend in the 30-day window
+    pub fn view_spending_allowance(
+        &self,
+        submitter_id: AccountId,
+        token_id: TokenId,
+    ) -> SpendingAllowance {
+        let submitter = self.spending_limits.get(&submitter_id);
+        let limit = submitter.and_then(|s| s.limits.iter().find(|l| l.token_id == token_id));
+        let spent = submitter.map_or(0, |s| s.spent_in_window(&token_id));
+        SpendingAllowance {
+            remaining_30_days: limit
+                .and_then(|l| l.max_per_30_days)
+                .map(|max| U128(max.0.saturating_sub(spent))),
+            limit: limit.cloned(),
+            spent_last_30_days: U128(spent),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{approve_list, contract_with_storage};
+    use crate::{ListStatus, PaymentInput};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken};
+
+    fn list_id(n: u8) -> String {
+        format!("{:02x}", n).repeat(32)
+    }
+
+    /// accounts(0) limited to 500 per list, 300 per recipient and 800 per 30 days of NEAR
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 10);
+        contract.set_spending_limit(
+            accounts(0),
+            SpendingLimit {
+                token_id: TokenId::Native,
+                max_per_list: Some(U128(500)),
+                max_per_recipient: Some(U128(300)),
+                max_per_30_days: Some(U128(800)),
+            },
+        );
+        (context, contract)
+    }
+
+    fn submit_and_fund(
+        context: &mut VMContextBuilder,
+        contract: &mut BulkPaymentContract,
+        n: u8,
+        payments: &[(AccountId, u128)],
+    ) {
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        let inputs = payments
+            .iter()
+            .map(|(recipient, amount)| PaymentInput::new(recipient.clone(), *amount))
+            .collect();
+        contract.submit_list(list_id(n), TokenId::Native, inputs, None, None);
+
+        let total = payments.iter().map(|(_, amount)| amount).sum();
+        approve_list(context, contract, &list_id(n), total);
+    }
+
+    #[test]
+    fn test_rolling_limit_and_allowance() {
+        let (mut context, mut contract) = setup();
+        submit_and_fund(
+            &mut context,
+            &mut contract,
+            1,
+            &[(accounts(1), 300), (accounts(2), 200)],
+        );
+        assert!(matches!(
+            contract.view_list(list_id(1)).status,
+            ListStatus::Approved
+        ));
+
+        let allowance = contract.view_spending_allowance(accounts(0), TokenId::Native);
+        assert_eq!(allowance.spent_last_30_days, U128(500));
+        assert_eq!(allowance.remaining_30_days, Some(U128(300)));
+
+        // The window moves on after 30 days
+        context.block_timestamp(SPENDING_WINDOW_NS + 1);
+        testing_env!(context.build());
+        let allowance = contract.view_spending_allowance(accounts(0), TokenId::Native);
+        assert_eq!(allowance.remaining_30_days, Some(U128(800)));
+    }
+
+    #[test]
+    #[should_panic(expected = "List pays 400 native, 300 of the 30-day limit of 800 is left")]
+    fn test_rolling_limit_enforced_at_approval() {
+        let (mut context, mut contract) = setup();
+        submit_and_fund(
+            &mut context,
+            &mut contract,
+            1,
+            &[(accounts(1), 300), (accounts(2), 200)],
+        );
+        submit_and_fund(
+            &mut context,
+            &mut contract,
+            2,
+            &[(accounts(1), 200), (accounts(2), 200)],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "List pays 400 native to charlie, the limit per recipient is 300")]
+    fn test_per_recipient_limit() {
+        let (mut context, mut contract) = setup();
+        submit_and_fund(
+            &mut context,
+            &mut contract,
+            1,
+            &[(accounts(2), 250), (accounts(2), 150)],
+        );
+    }
+
+    #[test]
+    fn test_submitter_pays_storage_for_new_limits() {
+        let (mut context, mut contract) = setup();
+        context.predecessor_account_id(accounts(1));
+        context.attached_deposit(contract.calculate_storage_cost(1));
+        testing_env!(context.build());
+        contract.buy_storage(1, None);
+
+        let limit = SpendingLimit {
+            token_id: TokenId::Native,
+            max_per_list: Some(U128(100)),
+            max_per_recipient: None,
+            max_per_30_days: None,
+        };
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.set_spending_limit(accounts(1), limit.clone());
+        assert_eq!(contract.view_storage_credits(accounts(1)).as_yoctonear(), 0);
+
+        // Replacing a limit writes no new entry
+        contract.set_spending_limit(accounts(1), limit);
+        assert_eq!(contract.view_spending_limits(accounts(1)).len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage credits. Required: 1, Available: 0")]
+    fn test_new_limit_without_storage_credits() {
+        let (mut context, mut contract) = setup();
+        context.predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        contract.set_spending_limit(
+            accounts(1),
+            SpendingLimit {
+                token_id: TokenId::Native,
+                max_per_list: Some(U128(100)),
+                max_per_recipient: None,
+                max_per_30_days: None,
+            },
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Only the contract account or the submitter can change its spending limits"
+    )]
+    fn test_others_cannot_set_limits() {
+        let (mut context, mut contract) = setup();
+        context.predecessor_account_id(accounts(1));
+        testing_env!(context.build());
+        contract.remove_spending_limit(accounts(0), TokenId::Native);
+    }
+}
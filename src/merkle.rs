@@ -0,0 +1,579 @@
+// Merkle-root lists for airdrop-scale distributions
+//
+// A Merkle list stores only the root of a Merkle tree over its payments, plus the total
+// amount and the number of leaves, instead of one PaymentRecord per payment. The list ID
+// IS the Merkle root, so it commits to every leaf. Recipients (or a relayer on their
+// behalf) call `claim_with_proof` with a leaf and its proof; claimed indices are tracked
+// in a bitmap so every leaf can be paid out only once.
+//
+// After the deadline the submitter reclaims the remainder: the total less what was claimed
+// and what was already reclaimed. A claim made up to the deadline can still fail after a
+// reclaim; its leaf cannot be claimed any more, so its amount adds to the remainder and
+// the submitter reclaims again.
+//
+// Tree format (must match bulk-payment-api/src/merkle.rs):
+// - leaf   = SHA-256("{index}:{recipient}:{amount}"), amount as a decimal string
+// - parent = SHA-256(min(left, right) || max(left, right)), i.e. sibling pairs are sorted
+// - a node without a sibling is carried up to the next level unchanged
+use near_sdk::json_types::U128;
+use near_sdk::{env, log, near, require, AccountId, Gas, Promise};
+
+use crate::executor::transfer_delivered;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, IntentsDelivery, ListId, ListStatus, PaymentList,
+    PayoutMode, TokenId,
+};
+
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(10);
+
+/// Number of leaves covered by one storage credit (one bitmap word)
+const LEAVES_PER_CREDIT: u32 = 64;
+
+/// Summary of a Merkle list; the leaves themselves are kept off-chain
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct MerkleDistribution {
+    pub total: U128,
+    pub count: u32,
+    pub claimed_count: u32,
+    pub claimed_amount: U128,
+    /// Amount returned to the submitter after the deadline
+    pub reclaimed_amount: U128,
+    /// Block height at which the unclaimed remainder was last returned to the submitter
+    pub reclaimed_at: Option<u64>,
+}
+
+impl MerkleDistribution {
+    /// Amount neither claimed nor reclaimed
+    fn remainder(&self) -> u128 {
+        self.total.0 - self.claimed_amount.0 - self.reclaimed_amount.0
+    }
+}
+
+/// Hash of a single Merkle leaf
+pub(crate) fn leaf_hash(index: u32, recipient: &AccountId, amount: u128) -> Vec<u8> {
+    env::sha256(format!("{}:{}:{}", index, recipient, amount).as_bytes())
+}
+
+/// Fold a leaf hash with its proof (sibling hashes from the bottom up) into a root
+fn compute_root(leaf: Vec<u8>, proof: &[Vec<u8>]) -> Vec<u8> {
+    proof.iter().fold(leaf, |node, sibling| {
+        let mut pair = Vec::with_capacity(64);
+        if node <= *sibling {
+            pair.extend_from_slice(&node);
+            pair.extend_from_slice(sibling);
+        } else {
+            pair.extend_from_slice(sibling);
+            pair.extend_from_slice(&node);
+        }
+        env::sha256(&pair)
+    })
+}
+
+/// Decode a hex-encoded 32-byte hash
+fn decode_hash(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    (0..64)
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Submit a Merkle list with pending status
+    ///
+    /// Merkle lists always use claim mode: nothing is paid out until recipients claim
+    /// with `claim_with_proof`. One storage credit is charged for the list plus one per
+    /// 64 leaves for the claimed bitmap.
+    ///
+    /// # Arguments
+    /// * `list_id` - The hex-encoded Merkle root of the payment leaves
+    /// * `token_id` - The token to use for payments ("native" for NEAR, or token contract ID)
+    /// * `total` - Sum of all leaf amounts; this is the amount required to approve the list
+    /// * `count` - Number of leaves in the tree
+    /// * `submitter_id` - Optional submitter account ID, as in `submit_list`
+    /// * `claim_deadline` - Block timestamp (ns) after which the unclaimed remainder can be reclaimed
+    pub fn submit_merkle_list(
+        &mut self,
+        list_id: ListId,
+        token_id: TokenId,
+        total: U128,
+        count: u32,
+        submitter_id: Option<AccountId>,
+        claim_deadline: u64,
+    ) -> ListId {
+        require!(
+            Self::validate_list_id(&list_id),
+            "Invalid list_id: must be a 64-character hex string (Merkle root)"
+        );
+        require!(
+            !self.is_list_id_taken(&list_id),
+            "Payment list with this ID already exists"
+        );
+        require!(
+            !matches!(token_id, TokenId::Nft(_)),
+            "Merkle lists do not support NFTs"
+        );
+        require!(count > 0, "Merkle list must have at least one leaf");
+        require!(total.0 > 0, "Merkle list total must be greater than 0");
+        require!(
+            claim_deadline > env::block_timestamp(),
+            "Claim mode requires a claim_deadline in the future"
+        );
+
+        let caller = env::predecessor_account_id();
+        let submitter = if let Some(sid) = submitter_id {
+            require!(
+                caller == env::current_account_id(),
+                "Only the contract account can submit on behalf of another account"
+            );
+            sid
+        } else {
+            caller
+        };
+
+        self.use_storage_credits(&submitter, 1 + count.div_ceil(LEAVES_PER_CREDIT) as u128);
+
+        let payment_list = PaymentList {
+            token_id,
+            submitter: submitter.clone(),
+            status: ListStatus::Pending,
+            payments: Vec::new(),
+            created_at: env::block_timestamp(),
+            template_id: None,
+            payout_mode: PayoutMode::Claim,
+            claim_deadline: Some(claim_deadline),
+            merkle: Some(MerkleDistribution {
+                total,
+                count,
+                claimed_count: 0,
+                claimed_amount: U128(0),
+                reclaimed_amount: U128(0),
+                reclaimed_at: None,
+            }),
+            metadata: None,
+            registration: None,
+            preflight: None,
+            nft_received: None,
+            intents_delivery: IntentsDelivery::Withdraw,
+            funded_amount: U128(0),
+            funded: None,
+            keeper_rewards: None,
+            operators: None,
+            attached_gas: None,
+            measured_gas_overhead: None,
+            split: None,
+            withholding: None,
+            fiat: None,
+        };
+        self.payment_lists.insert(list_id.clone(), payment_list);
+
+        log!(
+            "Payment list {} submitted by {} with {} payments",
+            list_id,
+            submitter,
+            count
+        );
+
+        list_id
+    }
+
+    /// Claim one leaf of a Merkle list (anyone can call, e.g. a relayer)
+    ///
+    /// # Arguments
+    /// * `proof` - Hex-encoded sibling hashes from the leaf up to the root
+    ///
+    /// # Panics
+    /// - If the list is not an Approved Merkle list or the claim deadline has passed
+    /// - If the index is out of range or already claimed
+    /// - If the proof does not lead to the list's Merkle root
+    pub fn claim_with_proof(
+        &mut self,
+        list_id: ListId,
+        index: u32,
+        recipient: AccountId,
+        amount: U128,
+        proof: Vec<String>,
+    ) -> Promise {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            matches!(list.status, ListStatus::Approved),
+            "List must be Approved to claim payments"
+        );
+        require!(
+            list.claim_deadline
+                .is_some_and(|deadline| env::block_timestamp() <= deadline),
+            "Claim deadline has passed"
+        );
+        if let Some(issue) = self.screening_issue(&list.submitter, &recipient) {
+            env::panic_str(&format!("Recipient {} {}", recipient, issue));
+        }
+        let merkle = list.merkle.as_mut().expect("List is not a Merkle list");
+        require!(index < merkle.count, "Leaf index out of range");
+        require!(
+            !self.is_claimed(list_id.clone(), index),
+            "Leaf has already been claimed"
+        );
+
+        let proof: Vec<Vec<u8>> = proof
+            .iter()
+            .map(|hash| decode_hash(hash).expect("Proof hashes must be 64-character hex strings"))
+            .collect();
+        let root = compute_root(leaf_hash(index, &recipient, amount.0), &proof);
+        require!(
+            decode_hash(&list_id).is_some_and(|expected| expected == root),
+            "Invalid Merkle proof"
+        );
+
+        require!(
+            amount.0 <= merkle.remainder(),
+            "Claim exceeds the list total"
+        );
+        let claimed_amount = merkle.claimed_amount.0 + amount.0;
+        merkle.claimed_amount = U128(claimed_amount);
+        merkle.claimed_count += 1;
+
+        let executor = list.executor();
+        self.payment_lists.insert(list_id.clone(), list);
+        self.set_claimed(&list_id, index, true);
+
+        log!(
+            "Claimed leaf {} ({}) from list {} for {}",
+            index,
+            amount.0,
+            list_id,
+            recipient
+        );
+
+        executor.pay(&recipient, amount.0, None).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .on_merkle_claim_resolved(list_id, index, amount),
+        )
+    }
+
+    /// Check whether a leaf of a Merkle list has been claimed
+    pub fn is_claimed(&self, list_id: ListId, index: u32) -> bool {
+        let word = self
+            .merkle_claims
+            .get(&(list_id, index / 64))
+            .copied()
+            .unwrap_or(0);
+        word & (1 << (index % 64)) != 0
+    }
+
+    /// Callback after a Merkle claim transfer. A failed transfer makes the leaf claimable again
+    /// before the deadline, and adds its amount to the reclaimable remainder after it.
+    #[private]
+    pub fn on_merkle_claim_resolved(&mut self, list_id: ListId, index: u32, amount: U128) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        if transfer_delivered(&list, amount.0) {
+            return true;
+        }
+
+        let merkle = list.merkle.as_mut().expect("List is not a Merkle list");
+        merkle.claimed_amount = U128(merkle.claimed_amount.0 - amount.0);
+        merkle.claimed_count -= 1;
+        let reclaimable = merkle.reclaimed_at.is_some()
+            || list
+                .claim_deadline
+                .is_some_and(|deadline| env::block_timestamp() > deadline);
+        self.payment_lists.insert(list_id.clone(), list);
+        self.set_claimed(&list_id, index, false);
+
+        if reclaimable {
+            log!(
+                "Transfer for leaf {} of list {} failed after the deadline, {} can be reclaimed",
+                index,
+                list_id,
+                amount.0
+            );
+        } else {
+            log!(
+                "Transfer for leaf {} of list {} failed, leaf is claimable again",
+                index,
+                list_id
+            );
+        }
+        false
+    }
+
+    /// Callback after returning `amount`, the unclaimed remainder, of a Merkle list
+    #[private]
+    pub fn on_merkle_reclaim_resolved(&mut self, list_id: ListId, amount: U128) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        if transfer_delivered(&list, amount.0) {
+            return true;
+        }
+
+        let merkle = list.merkle.as_mut().expect("List is not a Merkle list");
+        merkle.reclaimed_amount = U128(merkle.reclaimed_amount.0 - amount.0);
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Reclaim transfer for list {} failed, remainder can be reclaimed again",
+            list_id
+        );
+        false
+    }
+}
+
+impl BulkPaymentContract {
+    /// Return the unclaimed remainder of a Merkle list to its submitter.
+    /// Called from `reclaim_unclaimed` once the deadline and caller checks have passed.
+    pub(crate) fn reclaim_merkle_unclaimed(
+        &mut self,
+        list_id: ListId,
+        mut list: PaymentList,
+    ) -> Promise {
+        let merkle = list.merkle.as_mut().expect("List is not a Merkle list");
+        let remainder = merkle.remainder();
+        require!(remainder > 0, "No unclaimed payments to reclaim");
+        merkle.reclaimed_amount = U128(merkle.reclaimed_amount.0 + remainder);
+        merkle.reclaimed_at = Some(env::block_height());
+
+        let executor = list.executor();
+        let submitter = list.submitter.clone();
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Reclaimed unclaimed remainder ({}) of Merkle list {} for {}",
+            remainder,
+            list_id,
+            submitter
+        );
+
+        executor.transfer(&submitter, remainder, None).then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                .on_merkle_reclaim_resolved(list_id, U128(remainder)),
+        )
+    }
+
+    fn set_claimed(&mut self, list_id: &ListId, index: u32, claimed: bool) {
+        let key = (list_id.clone(), index / 64);
+        let word = self.merkle_claims.get(&key).copied().unwrap_or(0);
+        let bit = 1u64 << (index % 64);
+        let word = if claimed { word | bit } else { word & !bit };
+        self.merkle_claims.insert(key, word);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::to_hex;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken, PromiseResult, RuntimeFeesConfig};
+
+    const DEADLINE: u64 = 1_000_000;
+
+    /// Leaves of the test tree: (recipient, amount)
+    fn leaves() -> Vec<(AccountId, u128)> {
+        vec![
+            ("alice.near".parse().unwrap(), 100),
+            ("bob.near".parse().unwrap(), 200),
+            ("carol.near".parse().unwrap(), 300),
+        ]
+    }
+
+    /// Proofs of the three-leaf test tree: leaves 0 and 1 are paired, leaf 2 is carried up
+    fn proof(index: u32) -> Vec<String> {
+        let hashes: Vec<Vec<u8>> = leaves()
+            .iter()
+            .enumerate()
+            .map(|(i, (recipient, amount))| leaf_hash(i as u32, recipient, *amount))
+            .collect();
+        let pair_01 = compute_root(hashes[0].clone(), &[hashes[1].clone()]);
+        match index {
+            0 => vec![to_hex(&hashes[1]), to_hex(&hashes[2])],
+            1 => vec![to_hex(&hashes[0]), to_hex(&hashes[2])],
+            _ => vec![to_hex(&pair_01)],
+        }
+    }
+
+    /// Root of the three-leaf test tree, shared with the API's Merkle tests
+    const ROOT: &str = "303a9135f5a9d4812356022b7d582f44fe514800ffb565526ab8da837a0975f9";
+
+    fn setup_merkle_list() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        contract.submit_merkle_list(
+            ROOT.to_string(),
+            TokenId::Native,
+            U128(600),
+            3,
+            None,
+            DEADLINE,
+        );
+
+        context.attached_deposit(NearToken::from_yoctonear(600));
+        testing_env!(context.build());
+        contract.approve_list(ROOT.to_string());
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        context.predecessor_account_id(accounts(3));
+        testing_env!(context.build());
+        (context, contract)
+    }
+
+    #[test]
+    fn test_claim_with_proof() {
+        let (_context, mut contract) = setup_merkle_list();
+
+        for (index, (recipient, amount)) in leaves().into_iter().enumerate() {
+            let index = index as u32;
+            contract
+                .claim_with_proof(
+                    ROOT.to_string(),
+                    index,
+                    recipient,
+                    U128(amount),
+                    proof(index),
+                )
+                .detach();
+            assert!(contract.is_claimed(ROOT.to_string(), index));
+        }
+
+        let merkle = contract.view_list(ROOT.to_string()).merkle.unwrap();
+        assert_eq!(merkle.claimed_count, 3);
+        assert_eq!(merkle.claimed_amount.0, 600);
+    }
+
+    #[test]
+    #[should_panic(expected = "Leaf has already been claimed")]
+    fn test_claim_with_proof_twice_fails() {
+        let (_context, mut contract) = setup_merkle_list();
+
+        let (recipient, amount) = leaves()[1].clone();
+        contract
+            .claim_with_proof(
+                ROOT.to_string(),
+                1,
+                recipient.clone(),
+                U128(amount),
+                proof(1),
+            )
+            .detach();
+        contract
+            .claim_with_proof(ROOT.to_string(), 1, recipient, U128(amount), proof(1))
+            .detach();
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid Merkle proof")]
+    fn test_claim_with_wrong_amount_fails() {
+        let (_context, mut contract) = setup_merkle_list();
+
+        let (recipient, _) = leaves()[0].clone();
+        contract
+            .claim_with_proof(ROOT.to_string(), 0, recipient, U128(1_000), proof(0))
+            .detach();
+    }
+
+    #[test]
+    fn test_failed_claim_and_reclaim_remainder() {
+        let (mut context, mut contract) = setup_merkle_list();
+
+        let (recipient, amount) = leaves()[2].clone();
+        contract
+            .claim_with_proof(ROOT.to_string(), 2, recipient, U128(amount), proof(2))
+            .detach();
+
+        // A failed transfer makes the leaf claimable again
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_merkle_claim_resolved(ROOT.to_string(), 2, U128(amount)));
+        assert!(!contract.is_claimed(ROOT.to_string(), 2));
+
+        context.predecessor_account_id(accounts(0));
+        context.block_timestamp(DEADLINE + 1);
+        testing_env!(context.build());
+        contract.reclaim_unclaimed(ROOT.to_string()).detach();
+
+        let merkle = contract.view_list(ROOT.to_string()).merkle.unwrap();
+        assert_eq!(merkle.claimed_amount.0, 0);
+        assert!(merkle.reclaimed_at.is_some());
+    }
+
+    #[test]
+    fn test_claim_failing_after_reclaim_is_reclaimable() {
+        let (mut context, mut contract) = setup_merkle_list();
+
+        // Claimed at the deadline, its transfer fails only after the reclaim
+        context.block_timestamp(DEADLINE);
+        testing_env!(context.build());
+        let (recipient, amount) = leaves()[2].clone();
+        contract
+            .claim_with_proof(ROOT.to_string(), 2, recipient, U128(amount), proof(2))
+            .detach();
+
+        context.predecessor_account_id(accounts(0));
+        context.block_timestamp(DEADLINE + 1);
+        testing_env!(context.build());
+        contract.reclaim_unclaimed(ROOT.to_string()).detach();
+
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_merkle_claim_resolved(ROOT.to_string(), 2, U128(amount)));
+        let merkle = contract.view_list(ROOT.to_string()).merkle.unwrap();
+        assert_eq!(merkle.claimed_amount.0, 0);
+        assert_eq!(merkle.reclaimed_amount.0, 300);
+
+        // The failed claim's amount is reclaimed in a second transfer
+        context
+            .predecessor_account_id(accounts(0))
+            .current_account_id(accounts(0));
+        testing_env!(context.build());
+        contract.reclaim_unclaimed(ROOT.to_string()).detach();
+        let merkle = contract.view_list(ROOT.to_string()).merkle.unwrap();
+        assert_eq!(merkle.reclaimed_amount.0, 600);
+
+        // A failed reclaim only gives back the amount it sent
+        context
+            .predecessor_account_id(accounts(4))
+            .current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_merkle_reclaim_resolved(ROOT.to_string(), U128(amount)));
+        let merkle = contract.view_list(ROOT.to_string()).merkle.unwrap();
+        assert_eq!(merkle.reclaimed_amount.0, 300);
+    }
+}
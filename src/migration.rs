@@ -0,0 +1,391 @@
+// State migration from the original contract
+//
+// The first release stored only `payment_lists` and `storage_credits`, with lists holding a
+// token ID string and records without memo, reference or the later per-payment settings.
+// Its list status enum also had no PartiallyFunded variant, so the stored lists cannot be
+// read with the current types. `migrate` only rewrites the contract's root state: storage
+// credits keep their layout and are carried over, every newer collection starts empty, and
+// the old lists stay under their prefix while converted lists are stored under a new one.
+// `migrate_lists` then converts a bounded number of lists per call, so deployments of any
+// size can be upgraded. Approved lists with pending payments join the payout queue, as they
+// would have on approval. Lists whose token string no longer parses are kept in the old
+// layout behind the cursor and reported by `view_migration`.
+//
+// Only deployments of the first release are covered; later layouts are not.
+use near_sdk::json_types::U128;
+use near_sdk::store::IterableMap;
+use near_sdk::{env, log, near, require, AccountId, NearToken};
+
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, IntentsDelivery, ListId, ListStatus, PaymentList,
+    PaymentRecord, PaymentStatus, PayoutMode, TokenId,
+};
+
+/// Prefix of the payment lists of a migrated deployment, the old one holding legacy lists
+const MIGRATED_LISTS_PREFIX: &[u8] = b"P";
+
+#[near(serializers = [borsh])]
+enum OldPaymentStatus {
+    Pending,
+    Paid { block_height: u64 },
+}
+
+#[near(serializers = [borsh])]
+struct OldPaymentRecord {
+    recipient: AccountId,
+    amount: U128,
+    status: OldPaymentStatus,
+}
+
+#[near(serializers = [borsh])]
+enum OldListStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+#[near(serializers = [borsh])]
+pub(crate) struct OldPaymentList {
+    token_id: String,
+    submitter: AccountId,
+    status: OldListStatus,
+    payments: Vec<OldPaymentRecord>,
+    created_at: u64,
+}
+
+#[near(serializers = [borsh])]
+struct OldState {
+    payment_lists: IterableMap<ListId, OldPaymentList>,
+    storage_credits: IterableMap<AccountId, NearToken>,
+}
+
+/// Lists of the original contract still in the old layout
+#[near(serializers = [borsh])]
+pub(crate) struct LegacyLists {
+    lists: IterableMap<ListId, OldPaymentList>,
+    /// Position of the next list to convert; the lists before it cannot be converted
+    cursor: u32,
+}
+
+/// Progress of `migrate_lists`
+#[near(serializers = [json])]
+pub struct MigrationProgress {
+    /// Lists still to convert
+    pub pending: u32,
+    /// Lists kept in the old layout because their token does not parse
+    pub unconvertible: Vec<ListId>,
+}
+
+impl PaymentList {
+    fn from_old(old: OldPaymentList, token_id: TokenId) -> Self {
+        let payments: Vec<PaymentRecord> = old
+            .payments
+            .into_iter()
+            .map(|record| PaymentRecord {
+                recipient: record.recipient,
+                amount: record.amount,
+                memo: None,
+                reference: None,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
+                merged_lines: None,
+                net_amount: None,
+                withheld_amount: None,
+                status: match record.status {
+                    OldPaymentStatus::Pending => PaymentStatus::Pending,
+                    OldPaymentStatus::Paid { block_height } => PaymentStatus::Paid { block_height },
+                },
+            })
+            .collect();
+        let status = match old.status {
+            OldListStatus::Pending => ListStatus::Pending,
+            OldListStatus::Approved => ListStatus::Approved,
+            OldListStatus::Rejected => ListStatus::Rejected,
+        };
+        let mut list = PaymentList {
+            token_id,
+            submitter: old.submitter,
+            status,
+            payments,
+            created_at: old.created_at,
+            template_id: None,
+            payout_mode: PayoutMode::Push,
+            claim_deadline: None,
+            merkle: None,
+            metadata: None,
+            registration: None,
+            preflight: None,
+            nft_received: None,
+            intents_delivery: IntentsDelivery::Withdraw,
+            funded_amount: U128(0),
+            funded: None,
+            keeper_rewards: None,
+            operators: None,
+            attached_gas: None,
+            measured_gas_overhead: None,
+            split: None,
+            withholding: None,
+            fiat: None,
+        };
+        // Lists were only approved with their full total attached
+        if matches!(list.status, ListStatus::Approved) {
+            list.funded_amount = U128(list.total_amount());
+        }
+        list
+    }
+}
+
+impl BulkPaymentContract {
+    /// Whether `list_id` is taken by a list, converted or not
+    pub(crate) fn is_list_id_taken(&self, list_id: &ListId) -> bool {
+        self.payment_lists.contains_key(list_id)
+            || self
+                .legacy_lists
+                .as_ref()
+                .is_some_and(|legacy| legacy.lists.contains_key(list_id))
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Convert the root state of the original contract to the current layout (contract
+    /// account only, once after deploying the new code); the lists are then converted with
+    /// `migrate_lists`
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: OldState = env::state_read().expect("No contract state to migrate");
+        log!(
+            "Migrating state with {} payment lists to convert",
+            old.payment_lists.len()
+        );
+        Self {
+            payment_lists: IterableMap::new(MIGRATED_LISTS_PREFIX),
+            storage_credits: old.storage_credits,
+            legacy_lists: Some(LegacyLists {
+                lists: old.payment_lists,
+                cursor: 0,
+            }),
+            ..Self::default()
+        }
+    }
+
+    /// Convert up to `limit` lists of the original contract (contract account only); call
+    /// again until it returns 0
+    ///
+    /// Lists whose token does not parse are skipped and stay in the old layout.
+    ///
+    /// # Returns
+    /// Number of lists still to convert
+    #[private]
+    pub fn migrate_lists(&mut self, limit: u32) -> u32 {
+        require!(limit > 0, "limit must be positive");
+        let mut legacy = self
+            .legacy_lists
+            .take()
+            .expect("No state migration in progress");
+        let mut converted = 0;
+        let mut skipped = 0;
+        for _ in 0..limit {
+            let Some((list_id, old_list)) = legacy.lists.iter().nth(legacy.cursor as usize) else {
+                break;
+            };
+            let list_id = list_id.clone();
+            let token_id = match old_list.token_id.parse::<TokenId>() {
+                Ok(token_id) => token_id,
+                Err(error) => {
+                    log!("List {} kept in the old layout: {}", list_id, error);
+                    legacy.cursor += 1;
+                    skipped += 1;
+                    continue;
+                }
+            };
+            // The last list moves to the cursor, which therefore stays
+            let old_list = legacy
+                .lists
+                .remove(&list_id)
+                .expect("Legacy list not found");
+            let list = PaymentList::from_old(old_list, token_id);
+            if matches!(list.status, ListStatus::Approved)
+                && list
+                    .payments
+                    .iter()
+                    .any(|p| matches!(p.status, PaymentStatus::Pending))
+            {
+                self.enqueue_payout(&list_id, &list);
+            }
+            self.payment_lists.insert(list_id, list);
+            converted += 1;
+        }
+
+        let pending = legacy.lists.len() - legacy.cursor;
+        log!(
+            "Converted {} payment lists, skipped {}, {} left",
+            converted,
+            skipped,
+            pending
+        );
+        if !legacy.lists.is_empty() {
+            self.legacy_lists = Some(legacy);
+        }
+        pending
+    }
+
+    /// Progress of the state migration, `None` once every list is converted
+    pub fn view_migration(&self) -> Option<MigrationProgress> {
+        self.legacy_lists.as_ref().map(|legacy| MigrationProgress {
+            pending: legacy.lists.len() - legacy.cursor,
+            unconvertible: legacy
+                .lists
+                .keys()
+                .take(legacy.cursor as usize)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn list_id(n: u8) -> ListId {
+        format!("{:02x}", n).repeat(32)
+    }
+
+    #[test]
+    fn test_migrate_from_original_layout() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+
+        let mut old = OldState {
+            payment_lists: IterableMap::new(b"p"),
+            storage_credits: IterableMap::new(b"s"),
+        };
+        old.payment_lists.insert(
+            list_id(1),
+            OldPaymentList {
+                token_id: "native".to_string(),
+                submitter: accounts(1),
+                status: OldListStatus::Approved,
+                payments: vec![
+                    OldPaymentRecord {
+                        recipient: accounts(2),
+                        amount: U128(100),
+                        status: OldPaymentStatus::Paid { block_height: 7 },
+                    },
+                    OldPaymentRecord {
+                        recipient: accounts(3),
+                        amount: U128(200),
+                        status: OldPaymentStatus::Pending,
+                    },
+                ],
+                created_at: 5,
+            },
+        );
+        old.payment_lists.insert(
+            list_id(2),
+            OldPaymentList {
+                token_id: "nep141:usdc.near".to_string(),
+                submitter: accounts(1),
+                status: OldListStatus::Rejected,
+                payments: vec![OldPaymentRecord {
+                    recipient: accounts(2),
+                    amount: U128(300),
+                    status: OldPaymentStatus::Pending,
+                }],
+                created_at: 6,
+            },
+        );
+        old.payment_lists.insert(
+            list_id(3),
+            OldPaymentList {
+                token_id: "not a token".to_string(),
+                submitter: accounts(1),
+                status: OldListStatus::Pending,
+                payments: vec![],
+                created_at: 7,
+            },
+        );
+        old.storage_credits
+            .insert(accounts(1), NearToken::from_yoctonear(4));
+        old.payment_lists.flush();
+        old.storage_credits.flush();
+        env::state_write(&old);
+        drop(old);
+
+        let mut contract = BulkPaymentContract::migrate();
+        assert_eq!(contract.view_migration().unwrap().pending, 3);
+
+        // One list per call, the unparseable one is kept behind the cursor
+        assert_eq!(contract.migrate_lists(1), 2);
+        assert_eq!(contract.migrate_lists(1), 1);
+        assert_eq!(contract.migrate_lists(1), 0);
+        let progress = contract.view_migration().unwrap();
+        assert_eq!(progress.pending, 0);
+        assert_eq!(progress.unconvertible, vec![list_id(3)]);
+        assert_eq!(contract.migrate_lists(10), 0);
+        assert!(contract.is_list_id_taken(&list_id(3)));
+
+        let approved = contract.view_list(list_id(1));
+        assert!(matches!(approved.status, ListStatus::Approved));
+        assert_eq!(approved.funded_amount, U128(300));
+        assert!(matches!(
+            approved.payments[0].status,
+            PaymentStatus::Paid { block_height: 7 }
+        ));
+        assert!(matches!(
+            approved.payments[1].status,
+            PaymentStatus::Pending
+        ));
+
+        let rejected = contract.view_list(list_id(2));
+        assert!(matches!(rejected.status, ListStatus::Rejected));
+        assert_eq!(rejected.token_id.to_string(), "nep141:usdc.near");
+        assert_eq!(rejected.funded_amount, U128(0));
+
+        assert_eq!(
+            contract.view_storage_credits(accounts(1)),
+            NearToken::from_yoctonear(4)
+        );
+        assert_eq!(contract.view_payout_queue(None, None), vec![list_id(1)]);
+    }
+
+    #[test]
+    fn test_migration_ends_once_every_list_is_converted() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+
+        let mut old = OldState {
+            payment_lists: IterableMap::new(b"p"),
+            storage_credits: IterableMap::new(b"s"),
+        };
+        for n in 1..=3 {
+            old.payment_lists.insert(
+                list_id(n),
+                OldPaymentList {
+                    token_id: "native".to_string(),
+                    submitter: accounts(1),
+                    status: OldListStatus::Pending,
+                    payments: vec![],
+                    created_at: n as u64,
+                },
+            );
+        }
+        old.payment_lists.flush();
+        env::state_write(&old);
+        drop(old);
+
+        let mut contract = BulkPaymentContract::migrate();
+        assert_eq!(contract.migrate_lists(10), 0);
+        assert!(contract.view_migration().is_none());
+        for n in 1..=3 {
+            assert_eq!(contract.view_list(list_id(n)).created_at, n as u64);
+        }
+    }
+}
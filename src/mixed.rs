@@ -0,0 +1,327 @@
+// Mixed-token payment lists
+//
+// A payment may name its own `token_id`, overriding the list's token, so one list can pay
+// e.g. NEAR, USDC and wNEAR at once. Such a list is funded per token: the native total
+// with `approve_list`, NEP-141 totals with `ft_transfer_call` and NEAR Intents totals with
+// `mt_transfer_call`, each possibly in tranches. The funded amounts are tracked per token on
+// the list, which becomes Approved once every token total is covered.
+use near_sdk::json_types::U128;
+use near_sdk::{log, near, require};
+
+use crate::funding::add_funding;
+use crate::{
+    BulkPaymentContract, ListId, ListStatus, PaymentInput, PaymentList, PaymentRecord, TokenId,
+};
+
+/// Amount of one token
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenAmount {
+    pub token_id: TokenId,
+    pub amount: U128,
+}
+
+impl PaymentList {
+    /// Token a payment is made in
+    pub fn payment_token<'a>(&'a self, payment: &'a PaymentRecord) -> &'a TokenId {
+        payment.token_id.as_ref().unwrap_or(&self.token_id)
+    }
+
+    /// Whether any payment is made in another token than the list's
+    pub fn is_mixed(&self) -> bool {
+        self.funded.is_some()
+    }
+
+    /// Amount required per token, in order of first use
+    pub fn token_totals(&self) -> Vec<TokenAmount> {
+        if self.merkle.is_some() {
+            return vec![TokenAmount {
+                token_id: self.token_id.clone(),
+                amount: U128(self.total_amount()),
+            }];
+        }
+        let mut totals: Vec<TokenAmount> = Vec::new();
+        for payment in &self.payments {
+            let token_id = self.payment_token(payment);
+            match totals.iter_mut().find(|total| total.token_id == *token_id) {
+                Some(total) => {
+                    total.amount = U128(
+                        total
+                            .amount
+                            .0
+                            .checked_add(payment.amount.0)
+                            .expect("Total payment amount overflow"),
+                    )
+                }
+                None => totals.push(TokenAmount {
+                    token_id: token_id.clone(),
+                    amount: payment.amount,
+                }),
+            }
+        }
+        totals
+    }
+}
+
+/// Whether some payments override the list token with a different one
+pub(crate) fn has_mixed_tokens(token_id: &TokenId, payments: &[PaymentInput]) -> bool {
+    payments
+        .iter()
+        .any(|p| p.token_id.as_ref().is_some_and(|t| t != token_id))
+}
+
+/// Check the per-payment tokens; NFTs are only paid by NFT lists
+pub(crate) fn validate_payment_tokens(payments: &[PaymentInput]) {
+    require!(
+        payments
+            .iter()
+            .all(|p| !matches!(p.token_id, Some(TokenId::Nft(_)))),
+        "Payments cannot override the list token with an NFT contract"
+    );
+}
+
+impl BulkPaymentContract {
+    /// Fund one token of a mixed list, approving the list once all token totals are funded
+    ///
+    /// # Returns
+    /// The part of `amount` exceeding what was outstanding for the token
+    pub(crate) fn fund_mixed_list(
+        &mut self,
+        list_id: ListId,
+        mut list: PaymentList,
+        token_id: TokenId,
+        amount: u128,
+    ) -> u128 {
+        require!(amount > 0, "Funding amount must be positive");
+        self.check_spending_limits(&list);
+        let totals = list.token_totals();
+        let required = totals
+            .iter()
+            .find(|total| total.token_id == token_id)
+            .unwrap_or_else(|| {
+                near_sdk::env::panic_str(&format!("List has no payments in {}", token_id))
+            });
+
+        let funded = list
+            .funded
+            .as_mut()
+            .expect("List is not a mixed-token list");
+        let index = match funded.iter().position(|f| f.token_id == token_id) {
+            Some(index) => index,
+            None => {
+                funded.push(TokenAmount {
+                    token_id: token_id.clone(),
+                    amount: U128(0),
+                });
+                funded.len() - 1
+            }
+        };
+        let excess = add_funding(&mut funded[index].amount, required.amount.0, amount);
+        let token_funded = funded[index].amount.0;
+
+        let approved = totals.iter().all(|total| {
+            funded
+                .iter()
+                .any(|f| f.token_id == total.token_id && f.amount == total.amount)
+        });
+        list.status = if approved {
+            self.record_spending(&list);
+            self.enqueue_payout(&list_id, &list);
+            ListStatus::Approved
+        } else {
+            ListStatus::PartiallyFunded
+        };
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Payment list {} funded with {} {} ({}/{}), {} returned",
+            list_id,
+            amount - excess,
+            token_id,
+            token_funded,
+            required.amount.0,
+            excess
+        );
+        if approved {
+            log!("Payment list {} approved", list_id);
+        }
+        excess
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{MultiTokenReceiver, PaymentStatus};
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, AccountId, Gas, NearToken};
+
+    const LIST_ID: &str = "a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3a3";
+
+    fn usdc() -> AccountId {
+        "usdc.near".parse().unwrap()
+    }
+
+    fn payment(recipient: AccountId, amount: u128, token_id: Option<TokenId>) -> PaymentInput {
+        PaymentInput {
+            token_id,
+            ..PaymentInput::new(recipient, amount)
+        }
+    }
+
+    /// Submit a list paying NEAR (list token), USDC and wNEAR via intents from accounts(0)
+    fn setup_list() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 4);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            vec![
+                payment(accounts(1), 100, None),
+                payment(accounts(2), 200, Some(TokenId::Nep141(usdc()))),
+                payment(accounts(3), 300, Some(TokenId::Nep141(usdc()))),
+                payment(accounts(1), 400, Some("nep141:wrap.near".parse().unwrap())),
+            ],
+            None,
+            None,
+        );
+        (context, contract)
+    }
+
+    #[test]
+    fn test_mixed_list_funded_per_token() {
+        let (mut context, mut contract) = setup_list();
+        let list = contract.view_list(LIST_ID.to_string());
+        assert_eq!(
+            list.token_totals(),
+            vec![
+                TokenAmount {
+                    token_id: TokenId::Native,
+                    amount: U128(100)
+                },
+                TokenAmount {
+                    token_id: TokenId::Nep141(usdc()),
+                    amount: U128(500)
+                },
+                TokenAmount {
+                    token_id: "nep141:wrap.near".parse().unwrap(),
+                    amount: U128(400)
+                },
+            ]
+        );
+
+        context.attached_deposit(NearToken::from_yoctonear(100));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+
+        context.predecessor_account_id(usdc());
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(500), LIST_ID.to_string());
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).status,
+            ListStatus::PartiallyFunded
+        ));
+
+        context.predecessor_account_id("intents.near".parse().unwrap());
+        testing_env!(context.build());
+        let _ = contract.mt_on_transfer(
+            accounts(0),
+            vec![accounts(0)],
+            vec!["nep141:wrap.near".to_string()],
+            vec![U128(400)],
+            LIST_ID.to_string(),
+        );
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(list.status, ListStatus::Approved));
+        assert_eq!(list.funded.unwrap().len(), 3);
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+        assert!(contract
+            .view_list(LIST_ID.to_string())
+            .payments
+            .iter()
+            .all(|p| matches!(p.status, PaymentStatus::Paid { .. })));
+        let receivers: Vec<String> = get_created_receipts()
+            .iter()
+            .map(|r| r.receiver_id.to_string())
+            .collect();
+        assert_eq!(
+            receivers,
+            vec![
+                accounts(1).to_string(),
+                "usdc.near".to_string(),
+                "usdc.near".to_string(),
+                "intents.near".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mixed_list_token_funded_in_tranches() {
+        let (mut context, mut contract) = setup_list();
+        context.predecessor_account_id(usdc());
+        testing_env!(context.build());
+        assert_eq!(
+            contract.ft_on_transfer(accounts(0), U128(200), LIST_ID.to_string()),
+            U128(0)
+        );
+        assert_eq!(
+            contract.ft_on_transfer(accounts(0), U128(400), LIST_ID.to_string()),
+            U128(100)
+        );
+        assert_eq!(
+            contract.view_list(LIST_ID.to_string()).funded,
+            Some(vec![TokenAmount {
+                token_id: TokenId::Nep141(usdc()),
+                amount: U128(500)
+            }])
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "List has no payments in dai.near")]
+    fn test_mixed_list_rejects_unused_token() {
+        let (mut context, mut contract) = setup_list();
+        context.predecessor_account_id("dai.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(500), LIST_ID.to_string());
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "mt_on_transfer can only be called by the intents contract for its own tokens"
+    )]
+    fn test_forged_mt_on_transfer_rejected() {
+        let (mut context, mut contract) = setup_list();
+        context.predecessor_account_id(accounts(4));
+        testing_env!(context.build());
+        let _ = contract.mt_on_transfer(
+            accounts(4),
+            vec![accounts(4)],
+            vec!["native".to_string()],
+            vec![U128(100)],
+            LIST_ID.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_reject_returns_funded_tokens() {
+        let (mut context, mut contract) = setup_list();
+        context.predecessor_account_id(usdc());
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(500), LIST_ID.to_string());
+
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        contract.reject_list(LIST_ID.to_string());
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, usdc());
+    }
+}
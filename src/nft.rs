@@ -0,0 +1,312 @@
+// NEP-171 NFT distribution lists
+//
+// A list with `token_id: "nft:<contract>"` sends one NFT per payment, named by the payment's
+// `nft_token_id` (its `amount` must be 1). The submitter funds the list by moving each
+// listed token into this contract with `nft_transfer_call` and the list ID as `msg`; once
+// every token has arrived the list is Approved and `payout_batch` sends them out with
+// `nft_transfer`. Rejecting a partially funded list returns the received tokens.
+use near_sdk::{env, log, near, require, AccountId, PromiseOrValue};
+use std::collections::HashSet;
+
+use crate::executor::NftExecutor;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentInput, TokenId,
+};
+
+/// NEP-171 NFT receiver trait
+pub trait NonFungibleTokenReceiver {
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: String,
+        msg: String,
+    ) -> PromiseOrValue<bool>;
+}
+
+#[near]
+impl NonFungibleTokenReceiver for BulkPaymentContract {
+    /// NEP-171 callback after `nft_transfer_call`, funding one payment of an NFT list
+    ///
+    /// The `msg` parameter should be the list_id. Panics (which returns the token) if the
+    /// token is not a pending payment of that list.
+    ///
+    /// # Returns
+    /// `false`, so the token is kept
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: String,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let _ = previous_owner_id;
+        let list_id: ListId = msg;
+
+        require!(
+            Self::validate_list_id(&list_id),
+            "msg must be a valid list_id (64-character hex string)"
+        );
+
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            list.token_id == TokenId::Nft(env::predecessor_account_id()),
+            format!(
+                "NFT contract mismatch: list expects '{}', received from '{}'",
+                list.token_id,
+                env::predecessor_account_id()
+            )
+        );
+        require!(
+            list.submitter == sender_id,
+            "Only the submitter can fund the list via nft_transfer_call"
+        );
+        require!(
+            list.is_awaiting_funding(),
+            "List must be in Pending or PartiallyFunded status"
+        );
+
+        self.check_spending_limits(&list);
+
+        let received = list.nft_received.as_mut().expect("List is not an NFT list");
+        let index = list
+            .payments
+            .iter()
+            .position(|p| p.nft_token_id.as_deref() == Some(token_id.as_str()))
+            .unwrap_or_else(|| env::panic_str(&format!("NFT {} is not in this list", token_id)));
+        require!(
+            !received.contains(&(index as u32)),
+            format!("NFT {} was already received", token_id)
+        );
+        received.push(index as u32);
+
+        let num_received = received.len();
+        let num_payments = list.payments.len();
+        list.status = if num_received == num_payments {
+            self.record_spending(&list);
+            self.enqueue_payout(&list_id, &list);
+            ListStatus::Approved
+        } else {
+            ListStatus::PartiallyFunded
+        };
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Payment list {} received NFT {} ({}/{})",
+            list_id,
+            token_id,
+            num_received,
+            num_payments
+        );
+        if num_received == num_payments {
+            log!("Payment list {} approved via nft_transfer_call", list_id);
+        }
+
+        PromiseOrValue::Value(false)
+    }
+}
+
+impl BulkPaymentContract {
+    /// Check that `nft_token_id` is set exactly for the payments of NFT lists, that
+    /// each NFT payment has amount 1 and that no NFT is listed twice
+    pub(crate) fn validate_nft_payments(token_id: &TokenId, payments: &[PaymentInput]) {
+        if !matches!(token_id, TokenId::Nft(_)) {
+            require!(
+                payments.iter().all(|p| p.nft_token_id.is_none()),
+                "nft_token_id is only supported for NFT lists"
+            );
+            return;
+        }
+
+        let mut seen = HashSet::new();
+        for payment in payments {
+            let nft_token_id = payment.nft_token_id.as_ref().unwrap_or_else(|| {
+                env::panic_str(&format!(
+                    "NFT payment to {} requires an nft_token_id",
+                    payment.recipient
+                ))
+            });
+            require!(
+                payment.amount.0 == 1,
+                format!("NFT payment of {} must have amount 1", nft_token_id)
+            );
+            require!(
+                seen.insert(nft_token_id),
+                format!("NFT {} is listed more than once", nft_token_id)
+            );
+        }
+    }
+
+    /// Send the NFTs received so far by a list back to its submitter
+    pub(crate) fn return_received_nfts(&self, list_id: &ListId) {
+        let list = self
+            .payment_lists
+            .get(list_id)
+            .expect("Payment list not found");
+        let (TokenId::Nft(nft_contract), Some(received)) = (&list.token_id, &list.nft_received)
+        else {
+            return;
+        };
+
+        let executor = NftExecutor {
+            nft_contract: nft_contract.clone(),
+            gas: None,
+        };
+        for index in received {
+            let payment = &list.payments[*index as usize];
+            executor
+                .transfer_token(
+                    &list.submitter,
+                    payment.nft_token_id.as_deref().expect("NFT payment"),
+                    None,
+                )
+                .detach();
+        }
+        if !received.is_empty() {
+            log!(
+                "Returned {} NFTs of list {} to {}",
+                received.len(),
+                list_id,
+                list.submitter
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::PaymentStatus;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const LIST_ID: &str = "f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2f2";
+
+    fn nft_payment(recipient: AccountId, nft_token_id: &str) -> PaymentInput {
+        PaymentInput {
+            nft_token_id: Some(nft_token_id.to_string()),
+            ..PaymentInput::new(recipient, 1)
+        }
+    }
+
+    fn badges() -> AccountId {
+        "badges.near".parse().unwrap()
+    }
+
+    /// Submit an NFT list of two badges from accounts(0)
+    fn setup_list() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Nft(badges()),
+            vec![
+                nft_payment(accounts(1), "badge-1"),
+                nft_payment(accounts(2), "badge-2"),
+            ],
+            None,
+            None,
+        );
+        (context, contract)
+    }
+
+    fn receive_nft(
+        context: &mut VMContextBuilder,
+        contract: &mut BulkPaymentContract,
+        token: &str,
+    ) {
+        context.predecessor_account_id(badges());
+        testing_env!(context.build());
+        let _ = contract.nft_on_transfer(
+            accounts(0),
+            accounts(0),
+            token.to_string(),
+            LIST_ID.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_nft_list_funded_and_paid_out() {
+        let (mut context, mut contract) = setup_list();
+
+        receive_nft(&mut context, &mut contract, "badge-2");
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).status,
+            ListStatus::PartiallyFunded
+        ));
+        receive_nft(&mut context, &mut contract, "badge-1");
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).status,
+            ListStatus::Approved
+        ));
+
+        context.predecessor_account_id(accounts(3));
+        context.prepaid_gas(near_sdk::Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(list
+            .payments
+            .iter()
+            .all(|p| matches!(p.status, PaymentStatus::Paid { .. })));
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 2);
+        assert!(receipts.iter().all(|r| r.receiver_id == badges()));
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT badge-1 was already received")]
+    fn test_nft_received_twice() {
+        let (mut context, mut contract) = setup_list();
+        receive_nft(&mut context, &mut contract, "badge-1");
+        receive_nft(&mut context, &mut contract, "badge-1");
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT contract mismatch")]
+    fn test_nft_from_other_contract() {
+        let (mut context, mut contract) = setup_list();
+        context.predecessor_account_id(accounts(4));
+        testing_env!(context.build());
+        let _ = contract.nft_on_transfer(
+            accounts(0),
+            accounts(0),
+            "badge-1".to_string(),
+            LIST_ID.to_string(),
+        );
+    }
+
+    #[test]
+    fn test_reject_returns_received_nfts() {
+        let (mut context, mut contract) = setup_list();
+        receive_nft(&mut context, &mut contract, "badge-1");
+
+        context.predecessor_account_id(accounts(0));
+        testing_env!(context.build());
+        contract.reject_list(LIST_ID.to_string());
+
+        let receipts = get_created_receipts();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].receiver_id, badges());
+    }
+
+    #[test]
+    #[should_panic(expected = "NFT badge-1 is listed more than once")]
+    fn test_duplicate_nft_rejected() {
+        BulkPaymentContract::validate_nft_payments(
+            &TokenId::Nft(badges()),
+            &[
+                nft_payment(accounts(1), "badge-1"),
+                nft_payment(accounts(2), "badge-1"),
+            ],
+        );
+    }
+}
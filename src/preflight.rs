@@ -0,0 +1,342 @@
+// Pre-flight validation of list recipients
+//
+// `preflight_list` checks before payout that every recipient of a NEP-141 list is
+// registered with the token contract (NEP-145 `storage_balance_of`), so bad entries are
+// found before tokens are sent. The checks of one call are joined into a single callback;
+// large lists take several calls, each continuing where the previous one stopped. The
+// outcome is stored as a report on the list and returned by `view_list`. Should a callback
+// fail, its checks never resolve; once no batch has been scheduled for
+// `PREFLIGHT_TIMEOUT_BLOCKS`, the next call starts a new round, and late callbacks of the
+// old round are ignored.
+use near_sdk::serde_json::{self, json};
+use near_sdk::{env, log, near, require, AccountId, Gas, NearToken, Promise, PromiseResult};
+
+use crate::{BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, StorageBalance};
+
+const GAS_FOR_STORAGE_BALANCE_OF: Gas = Gas::from_tgas(5);
+/// Base gas of the callback plus the gas per check result it processes
+const GAS_FOR_PREFLIGHT_CALLBACK: Gas = Gas::from_tgas(5);
+const GAS_PER_CHECK_RESULT: Gas = Gas::from_tgas(1);
+/// Reserve for storing the report and logging
+const GAS_RESERVE: Gas = Gas::from_tgas(15);
+/// Upper bound of checks joined into one callback
+const MAX_CHECKS_PER_CALL: u64 = 40;
+/// Blocks after which unresolved checks are assumed to have lost their callback
+const PREFLIGHT_TIMEOUT_BLOCKS: u64 = 1_000;
+
+/// Problem found with a payment during pre-flight checks
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreflightIssueKind {
+    /// Recipient has no storage balance with the token contract
+    NotRegistered,
+    /// `storage_balance_of` failed or returned an unexpected value
+    CheckFailed,
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PreflightIssue {
+    pub index: u32,
+    pub recipient: AccountId,
+    pub kind: PreflightIssueKind,
+}
+
+/// Result of the pre-flight checks of a list
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PreflightReport {
+    /// Block timestamp (ns) at which this round of checks started
+    pub started_at: u64,
+    /// Index of the next payment to check
+    pub next_index: u32,
+    /// Checks scheduled but not resolved yet
+    pub pending_checks: u32,
+    /// Block height at which the latest batch of checks was scheduled
+    pub scheduled_at: u64,
+    pub issues: Vec<PreflightIssue>,
+}
+
+impl PreflightReport {
+    fn is_complete(&self, num_payments: usize) -> bool {
+        self.next_index as usize == num_payments && self.pending_checks == 0
+    }
+
+    /// Whether checks are still unresolved long after the latest batch was scheduled
+    fn is_stalled(&self) -> bool {
+        self.pending_checks > 0
+            && env::block_height() > self.scheduled_at + PREFLIGHT_TIMEOUT_BLOCKS
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Check the token registration of the recipients of a NEP-141 list (anyone can call)
+    ///
+    /// Checks as many payments as the attached gas allows (up to 40 per call); call again
+    /// until this returns 0. Calling after a round has completed, or after its checks have
+    /// been unresolved for `PREFLIGHT_TIMEOUT_BLOCKS`, starts a new round.
+    ///
+    /// # Returns
+    /// Number of payments still to be checked after this call
+    pub fn preflight_list(&mut self, list_id: ListId) -> u64 {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            !list.is_mixed(),
+            "Preflight checks only apply to NEP-141 token lists"
+        );
+        let token_account = list.token_id.ft_contract().cloned().unwrap_or_else(|| {
+            env::panic_str("Preflight checks only apply to NEP-141 token lists")
+        });
+        require!(
+            !matches!(list.status, ListStatus::Rejected),
+            "List is rejected"
+        );
+
+        let num_payments = list.payments.len();
+        let report = match list.preflight.take() {
+            Some(report) if !report.is_complete(num_payments) && !report.is_stalled() => report,
+            previous => {
+                if previous.is_some_and(|report| report.is_stalled()) {
+                    log!(
+                        "Preflight of list {}: checks timed out, restarting",
+                        list_id
+                    );
+                }
+                PreflightReport {
+                    started_at: env::block_timestamp(),
+                    next_index: 0,
+                    pending_checks: 0,
+                    scheduled_at: env::block_height(),
+                    issues: Vec::new(),
+                }
+            }
+        };
+        let report = list.preflight.insert(report);
+
+        let unchecked = (num_payments - report.next_index as usize) as u64;
+        if unchecked == 0 {
+            // All checks of this round are already scheduled
+            self.payment_lists.insert(list_id, list);
+            return 0;
+        }
+
+        let gas_available = env::prepaid_gas()
+            .as_gas()
+            .saturating_sub(env::used_gas().as_gas())
+            .saturating_sub(GAS_RESERVE.as_gas() + GAS_FOR_PREFLIGHT_CALLBACK.as_gas());
+        let gas_per_check = GAS_FOR_STORAGE_BALANCE_OF.as_gas() + GAS_PER_CHECK_RESULT.as_gas();
+        let num_checks = (gas_available / gas_per_check)
+            .min(MAX_CHECKS_PER_CALL)
+            .min(unchecked);
+        require!(num_checks > 0, "Insufficient gas to run preflight checks");
+
+        let indices: Vec<u32> =
+            (report.next_index..report.next_index + num_checks as u32).collect();
+        let checks = indices
+            .iter()
+            .map(|index| {
+                Promise::new(token_account.clone()).function_call(
+                    "storage_balance_of".to_string(),
+                    json!({ "account_id": list.payments[*index as usize].recipient })
+                        .to_string()
+                        .into_bytes(),
+                    NearToken::from_yoctonear(0),
+                    GAS_FOR_STORAGE_BALANCE_OF,
+                )
+            })
+            .reduce(Promise::and)
+            .expect("At least one check is scheduled");
+
+        report.next_index += num_checks as u32;
+        report.pending_checks += num_checks as u32;
+        report.scheduled_at = env::block_height();
+        let round = report.started_at;
+        let remaining = unchecked - num_checks;
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Preflight of list {}: checking {} payments, {} remaining",
+            list_id,
+            num_checks,
+            remaining
+        );
+
+        let callback_gas =
+            GAS_FOR_PREFLIGHT_CALLBACK.as_gas() + num_checks * GAS_PER_CHECK_RESULT.as_gas();
+        checks
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::from_gas(callback_gas))
+                    .on_preflight_checked(list_id, round, indices),
+            )
+            .detach();
+
+        remaining
+    }
+
+    /// Callback with the `storage_balance_of` results of one preflight batch of the round
+    /// started at `round`, in the order of `indices`
+    #[private]
+    pub fn on_preflight_checked(&mut self, list_id: ListId, round: u64, indices: Vec<u32>) {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        let payments = &list.payments;
+        let report = list.preflight.as_mut().expect("No preflight report");
+        if report.started_at != round {
+            log!(
+                "Preflight of list {}: ignoring results of an earlier round",
+                list_id
+            );
+            return;
+        }
+
+        let issues_before = report.issues.len();
+        for (result_index, index) in indices.iter().enumerate() {
+            let kind = match env::promise_result(result_index as u64) {
+                PromiseResult::Successful(bytes) => {
+                    match serde_json::from_slice::<Option<StorageBalance>>(&bytes) {
+                        Ok(Some(_)) => continue,
+                        Ok(None) => PreflightIssueKind::NotRegistered,
+                        Err(_) => PreflightIssueKind::CheckFailed,
+                    }
+                }
+                PromiseResult::Failed => PreflightIssueKind::CheckFailed,
+            };
+            report.issues.push(PreflightIssue {
+                index: *index,
+                recipient: payments[*index as usize].recipient.clone(),
+                kind,
+            });
+        }
+        report.pending_checks -= indices.len() as u32;
+        let new_issues = report.issues.len() - issues_before;
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Preflight of list {}: {} payments checked, {} issues found",
+            list_id,
+            indices.len(),
+            new_issues
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::PaymentInput;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig};
+
+    const LIST_ID: &str = "e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1e1";
+
+    fn setup_list(token_id: &str) -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context
+            .predecessor_account_id(accounts(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        let mut contract = contract_with_storage(&mut context, 2);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 100),
+            PaymentInput::new(accounts(2), 200),
+        ];
+        contract.submit_list(
+            LIST_ID.to_string(),
+            token_id.parse().unwrap(),
+            payments,
+            None,
+            None,
+        );
+        (context, contract)
+    }
+
+    #[test]
+    #[should_panic(expected = "Preflight checks only apply to NEP-141 token lists")]
+    fn test_preflight_rejects_native_list() {
+        let (_context, mut contract) = setup_list("native");
+        contract.preflight_list(LIST_ID.to_string());
+    }
+
+    #[test]
+    fn test_preflight_reports_unregistered_recipients() {
+        let (mut context, mut contract) = setup_list("token.near");
+
+        assert_eq!(contract.preflight_list(LIST_ID.to_string()), 0);
+        let report = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+        assert_eq!(report.next_index, 2);
+        assert_eq!(report.pending_checks, 2);
+
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(b"null".to_vec()),
+                PromiseResult::Successful(br#"{"total":"1250","available":"0"}"#.to_vec()),
+            ],
+        );
+        contract.on_preflight_checked(LIST_ID.to_string(), report.started_at, vec![0, 1]);
+
+        let report = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+        assert_eq!(report.pending_checks, 0);
+        assert_eq!(report.issues.len(), 1);
+        assert_eq!(report.issues[0].index, 0);
+        assert_eq!(report.issues[0].recipient, accounts(1));
+        assert_eq!(report.issues[0].kind, PreflightIssueKind::NotRegistered);
+    }
+
+    #[test]
+    fn test_preflight_restarts_after_lost_callback() {
+        let (mut context, mut contract) = setup_list("token.near");
+        context.block_timestamp(1);
+        testing_env!(context.build());
+        assert_eq!(contract.preflight_list(LIST_ID.to_string()), 0);
+        let stale = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+
+        // The callback never arrives; before the timeout nothing is rescheduled
+        context.block_height(PREFLIGHT_TIMEOUT_BLOCKS);
+        context.block_timestamp(2);
+        testing_env!(context.build());
+        assert_eq!(contract.preflight_list(LIST_ID.to_string()), 0);
+        let report = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+        assert_eq!(report.started_at, stale.started_at);
+
+        context.block_height(PREFLIGHT_TIMEOUT_BLOCKS + 1);
+        testing_env!(context.build());
+        assert_eq!(contract.preflight_list(LIST_ID.to_string()), 0);
+        let report = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+        assert_eq!(report.started_at, 2);
+        assert_eq!(report.pending_checks, 2);
+
+        // A late callback of the old round leaves the new one alone
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![
+                PromiseResult::Successful(b"null".to_vec()),
+                PromiseResult::Successful(b"null".to_vec()),
+            ],
+        );
+        contract.on_preflight_checked(LIST_ID.to_string(), stale.started_at, vec![0, 1]);
+        let report = contract.view_list(LIST_ID.to_string()).preflight.unwrap();
+        assert_eq!(report.pending_checks, 2);
+        assert!(report.issues.is_empty());
+    }
+}
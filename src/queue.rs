@@ -0,0 +1,256 @@
+// Global payout queue
+//
+// Push-mode lists are appended to a FIFO queue when they become Approved, so keepers do not
+// need to track list IDs off-chain: `payout_next` pays the pending records of the lists at
+// the front of the queue, as many as the gas of the call allows, and drops each list once
+// it has nothing left to pay. Lists finished through `payout_batch` in between are dropped
+// when they reach the front. Lists restricted to operators other than the caller, and
+// lists whose remaining payments await recipient registration, are moved to the back.
+use near_sdk::store::LookupMap;
+use near_sdk::{env, near, IntoStorageKey};
+
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentList, PaymentStatus,
+    PayoutMode,
+};
+
+/// Default number of lists `payout_next` visits
+const DEFAULT_MAX_LISTS: u32 = 10;
+/// Most lists `payout_next` visits in one call
+const MAX_LISTS: u32 = 50;
+/// Default and maximum page size of `view_payout_queue`
+const MAX_PAGE_SIZE: u32 = 100;
+
+/// FIFO of list IDs, stored by position
+#[near(serializers = [borsh])]
+pub(crate) struct PayoutQueue {
+    entries: LookupMap<u64, ListId>,
+    head: u64,
+    tail: u64,
+}
+
+impl PayoutQueue {
+    pub(crate) fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            entries: LookupMap::new(prefix),
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    fn front(&self) -> Option<ListId> {
+        self.entries.get(&self.head).cloned()
+    }
+
+    fn push_back(&mut self, list_id: ListId) {
+        self.entries.insert(self.tail, list_id);
+        self.tail += 1;
+    }
+
+    fn pop_front(&mut self) -> Option<ListId> {
+        let list_id = self.entries.remove(&self.head)?;
+        self.head += 1;
+        Some(list_id)
+    }
+}
+
+impl PaymentList {
+    /// Whether the list has records `payout_next` can pay
+    fn awaits_payout(&self) -> bool {
+        matches!(self.status, ListStatus::Approved)
+            && self.payout_mode == PayoutMode::Push
+            && self
+                .payments
+                .iter()
+                .any(|p| matches!(p.status, PaymentStatus::Pending))
+    }
+}
+
+impl BulkPaymentContract {
+    /// Queue a newly Approved list for `payout_next`; claim-mode lists are not queued
+    pub(crate) fn enqueue_payout(&mut self, list_id: &ListId, list: &PaymentList) {
+        if list.payout_mode == PayoutMode::Push {
+            self.payout_queue.push_back(list_id.clone());
+        }
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Pay pending records of the queued lists in FIFO order (public function, anyone can
+    /// call)
+    ///
+    /// Visits up to `max_lists` lists (default 10, at most 50) and pays as many records
+    /// as the gas allows, like `payout_batch`; a list stays at the front until all its
+    /// records are paid. Keeper rewards of each list go to the caller.
+    ///
+    /// # Returns
+    /// Number of lists still queued, including finished lists not yet dropped
+    ///
+    /// # Panics
+    /// - If there's not enough gas to pay the first pending record
+    pub fn payout_next(&mut self, max_lists: Option<u32>) -> u64 {
+        let max_lists = max_lists.unwrap_or(DEFAULT_MAX_LISTS).min(MAX_LISTS);
+        let caller = env::predecessor_account_id();
+        let mut paid_any = false;
+
+        for _ in 0..max_lists {
+            let Some(list_id) = self.payout_queue.front() else {
+                break;
+            };
+            let Some(list) = self
+                .payment_lists
+                .get(&list_id)
+                .filter(|list| list.awaits_payout())
+                .cloned()
+            else {
+                self.payout_queue.pop_front();
+                continue;
+            };
+            if list
+                .operators
+                .as_ref()
+                .is_some_and(|operators| !operators.contains(&caller))
+            {
+                self.payout_queue.pop_front();
+                self.payout_queue.push_back(list_id);
+                continue;
+            }
+
+            let remaining = self.process_payouts(&list_id, list, !paid_any, None);
+            paid_any = true;
+            if remaining == 0 {
+                self.payout_queue.pop_front();
+            } else if self
+                .payment_lists
+                .get(&list_id)
+                .is_some_and(|list| !list.has_payable_payments())
+            {
+                // The rest awaits recipient registration, which may return payments to pending
+                self.payout_queue.pop_front();
+                self.payout_queue.push_back(list_id);
+            } else {
+                // Out of gas, the list stays at the front for the next call
+                break;
+            }
+        }
+
+        self.payout_queue.len()
+    }
+
+    /// Page through the payout queue from the front
+    pub fn view_payout_queue(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<ListId> {
+        let start = self.payout_queue.head + from_index.unwrap_or(0) as u64;
+        let end = self
+            .payout_queue
+            .tail
+            .min(start + limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE) as u64);
+        (start..end)
+            .filter_map(|position| self.payout_queue.entries.get(&position).cloned())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{approve_list, contract_with_storage};
+    use crate::{ListOptions, PaymentInput, TokenId};
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, Gas, NearToken};
+
+    fn list_id(n: u8) -> String {
+        format!("{:02x}", 0x90 + n).repeat(32)
+    }
+
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let contract = contract_with_storage(&mut context, 10);
+        (context, contract)
+    }
+
+    /// Submit and fund a native list of 2 payments of 100 from accounts(0)
+    fn approved_list(
+        context: &mut VMContextBuilder,
+        contract: &mut BulkPaymentContract,
+        n: u8,
+        options: Option<ListOptions>,
+    ) {
+        context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        let payments = vec![
+            PaymentInput::new(accounts(1), 100),
+            PaymentInput::new(accounts(2), 100),
+        ];
+        contract.submit_list(list_id(n), TokenId::Native, payments, None, options);
+        approve_list(context, contract, &list_id(n), 200);
+    }
+
+    fn keeper(context: &mut VMContextBuilder) {
+        context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(NearToken::from_yoctonear(0))
+            .prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+    }
+
+    fn pending(contract: &BulkPaymentContract, n: u8) -> usize {
+        contract
+            .view_list(list_id(n))
+            .payments
+            .iter()
+            .filter(|p| matches!(p.status, PaymentStatus::Pending))
+            .count()
+    }
+
+    #[test]
+    fn test_payout_next_drains_lists_in_order() {
+        let (mut context, mut contract) = setup();
+        approved_list(&mut context, &mut contract, 1, None);
+        approved_list(&mut context, &mut contract, 2, None);
+        approved_list(&mut context, &mut contract, 3, None);
+        assert_eq!(
+            contract.view_payout_queue(None, None),
+            vec![list_id(1), list_id(2), list_id(3)]
+        );
+
+        // A list finished directly is dropped when it reaches the front
+        keeper(&mut context);
+        contract.payout_batch(list_id(1), None);
+        assert_eq!(contract.payout_next(Some(2)), 1);
+        assert_eq!(pending(&contract, 2), 0);
+        assert_eq!(pending(&contract, 3), 2);
+        assert_eq!(contract.view_payout_queue(None, None), vec![list_id(3)]);
+
+        assert_eq!(contract.payout_next(None), 0);
+        assert_eq!(pending(&contract, 3), 0);
+    }
+
+    #[test]
+    fn test_payout_next_skips_lists_of_other_operators() {
+        let (mut context, mut contract) = setup();
+        approved_list(
+            &mut context,
+            &mut contract,
+            1,
+            Some(ListOptions {
+                operators: Some(vec![accounts(4)]),
+                ..Default::default()
+            }),
+        );
+        approved_list(&mut context, &mut contract, 2, None);
+
+        keeper(&mut context);
+        assert_eq!(contract.payout_next(None), 1);
+        assert_eq!(pending(&contract, 1), 2);
+        assert_eq!(pending(&contract, 2), 0);
+        assert_eq!(contract.view_payout_queue(None, None), vec![list_id(1)]);
+    }
+}
@@ -0,0 +1,796 @@
+// Automatic NEP-145 storage registration of FT recipients
+//
+// Lists submitted with `register_recipients: true` pay out in three steps per payment:
+// `storage_balance_of` and `storage_balance_bounds` on the token contract, then (only for
+// unregistered recipients) `storage_deposit` with the minimum balance, then `ft_transfer`.
+// The payment stays Pending until the transfer is sent, and returns to Pending if the
+// transfer fails or the recipient cannot be registered, so a later `payout_batch` retries
+// it. Payments in flight are tracked per index; should a callback fail, anyone can clear
+// entries older than `REGISTRATION_TIMEOUT_BLOCKS` with `clear_stale_registrations`.
+//
+// Registration deposits come from a NEAR budget, separate from the token deposit that
+// funds the payments: whatever NEAR the `approve_list` deposit leaves after the keeper
+// budget, so a DAO attaches it in the same proposal, ahead of the `ft_transfer_call`, or
+// later with `fund_registration_budget`. The list tracks how much of the budget was
+// spent; once all payments are processed the submitter can get the leftover back with
+// `refund_registration_budget`.
+//
+// The callbacks forward the list's attached gas to `ft_transfer`, so their static gas and
+// the gas `payout_batch` budgets per payment grow with the list's `attached_gas` override.
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{
+    env, is_promise_success, log, near, require, AccountId, Gas, NearToken, Promise, PromiseError,
+};
+
+use crate::executor::transfer_delivered;
+use crate::withholding;
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentList, PaymentStatus,
+    StorageBalance,
+};
+
+const GAS_FOR_STORAGE_VIEW: Gas = Gas::from_tgas(5);
+const GAS_FOR_STORAGE_DEPOSIT: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas::from_tgas(10);
+const GAS_FOR_RESOLVE_REFUND: Gas = Gas::from_tgas(10);
+/// Gas `on_recipient_registered` spends itself, besides the transfer it schedules
+const REGISTERED_CALLBACK_OVERHEAD: Gas = Gas::from_tgas(10);
+/// Gas `on_recipient_storage_checked` spends itself, besides the calls it schedules
+const STORAGE_CHECKED_CALLBACK_OVERHEAD: Gas = Gas::from_tgas(15);
+/// Gas `payout_batch` spends scheduling a registration check
+const REGISTRATION_SCHEDULING_OVERHEAD: Gas = Gas::from_tgas(5);
+
+/// Static gas of `on_recipient_registered` for transfers attaching `transfer_gas`
+fn registered_callback_gas(transfer_gas: Gas) -> Gas {
+    transfer_gas
+        .saturating_add(GAS_FOR_RESOLVE_TRANSFER)
+        .saturating_add(REGISTERED_CALLBACK_OVERHEAD)
+}
+
+/// Static gas of `on_recipient_storage_checked` for transfers attaching `transfer_gas`
+fn storage_checked_callback_gas(transfer_gas: Gas) -> Gas {
+    registered_callback_gas(transfer_gas)
+        .saturating_add(GAS_FOR_STORAGE_DEPOSIT)
+        .saturating_add(STORAGE_CHECKED_CALLBACK_OVERHEAD)
+}
+
+/// Gas `payout_batch` budgets per payment of a list with recipient registration whose
+/// transfers attach `transfer_gas` (110 TGas for the default 50 TGas)
+pub(crate) fn registering_payment_gas(transfer_gas: Gas) -> Gas {
+    storage_checked_callback_gas(transfer_gas)
+        .saturating_add(GAS_FOR_STORAGE_VIEW.saturating_mul(2))
+        .saturating_add(REGISTRATION_SCHEDULING_OVERHEAD)
+}
+
+/// Blocks after which a registration still in flight is assumed to have lost its callback
+const REGISTRATION_TIMEOUT_BLOCKS: u64 = 1_000;
+
+/// NEP-145 storage balance bounds as returned by `storage_balance_bounds`
+#[near(serializers = [json])]
+pub struct StorageBalanceBounds {
+    pub min: U128,
+    pub max: Option<U128>,
+}
+
+/// NEAR budget for registering a list's recipients with the token contract
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Default)]
+pub struct RecipientRegistration {
+    /// Total NEAR attached via `fund_registration_budget`
+    pub budget: U128,
+    /// NEAR spent on `storage_deposit` calls
+    pub spent: U128,
+    /// Payments scheduled by `payout_batch` whose registration or transfer has not resolved
+    pub in_flight: Vec<RegistrationInFlight>,
+}
+
+/// Payment of a list with registration between `payout_batch` and its transfer's result
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct RegistrationInFlight {
+    pub index: u32,
+    /// Block height at which `payout_batch` scheduled the registration check
+    pub scheduled_at: u64,
+}
+
+impl RecipientRegistration {
+    fn remaining(&self) -> u128 {
+        self.budget.0 - self.spent.0
+    }
+
+    /// Whether the payment at `index` is being registered or transferred
+    pub(crate) fn is_in_flight(&self, index: u32) -> bool {
+        self.in_flight.iter().any(|entry| entry.index == index)
+    }
+
+    /// Track the payment at `index` from the start of its registration check
+    pub(crate) fn start(&mut self, index: u32) {
+        self.in_flight.push(RegistrationInFlight {
+            index,
+            scheduled_at: env::block_height(),
+        });
+    }
+
+    fn finish(&mut self, index: u32) {
+        self.in_flight.retain(|entry| entry.index != index);
+    }
+}
+
+impl PaymentList {
+    /// Add `amount` to the registration budget if the list registers recipients, returning
+    /// the part it does not take
+    pub(crate) fn fund_registration(&mut self, amount: u128) -> u128 {
+        let Some(registration) = self.registration.as_mut() else {
+            return amount;
+        };
+        registration.budget = U128(
+            registration
+                .budget
+                .0
+                .checked_add(amount)
+                .expect("Registration budget overflow"),
+        );
+        0
+    }
+
+    /// Whether the payment at `index` awaits the result of its registration
+    pub(crate) fn is_registering(&self, index: usize) -> bool {
+        self.registration
+            .as_ref()
+            .is_some_and(|registration| registration.is_in_flight(index as u32))
+    }
+
+    /// Whether a payout can pay any pending payment now, i.e. one not being registered
+    pub(crate) fn has_payable_payments(&self) -> bool {
+        self.payments.iter().enumerate().any(|(index, p)| {
+            matches!(p.status, PaymentStatus::Pending) && !self.is_registering(index)
+        })
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Attach NEAR to the registration budget of a list (submitter only)
+    ///
+    /// The budget can also be attached to `approve_list`; this tops it up later, e.g. when
+    /// it runs out before every recipient is registered.
+    #[payable]
+    pub fn fund_registration_budget(&mut self, list_id: ListId) -> U128 {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can fund the registration budget"
+        );
+        require!(
+            !matches!(list.status, ListStatus::Rejected),
+            "List is rejected"
+        );
+        let attached = env::attached_deposit().as_yoctonear();
+        require!(attached > 0, "Attach NEAR to fund the registration budget");
+
+        require!(
+            list.registration.is_some(),
+            "List does not register recipients"
+        );
+        list.fund_registration(attached);
+        let budget = list.registration.as_ref().map_or(U128(0), |r| r.budget);
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Registration budget of list {} funded with {}, total {}",
+            list_id,
+            attached,
+            budget.0
+        );
+
+        budget
+    }
+
+    /// Refund the unspent registration budget to the submitter
+    ///
+    /// Allowed once the list is rejected, or once all payments are processed and no
+    /// registration is in flight.
+    pub fn refund_registration_budget(&mut self, list_id: ListId) -> Promise {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can refund the registration budget"
+        );
+        let finished = matches!(list.status, ListStatus::Rejected)
+            || !list
+                .payments
+                .iter()
+                .any(|p| matches!(p.status, PaymentStatus::Pending));
+        let registration = list
+            .registration
+            .as_mut()
+            .expect("List does not register recipients");
+        require!(
+            finished && registration.in_flight.is_empty(),
+            "Registration budget can be refunded once all payments are processed"
+        );
+
+        let leftover = registration.remaining();
+        require!(leftover > 0, "No registration budget left to refund");
+        registration.budget = registration.spent;
+
+        let submitter = list.submitter.clone();
+        self.payment_lists.insert(list_id.clone(), list);
+
+        log!(
+            "Refunding {} of registration budget of list {} to {}",
+            leftover,
+            list_id,
+            submitter
+        );
+
+        Promise::new(submitter)
+            .transfer(NearToken::from_yoctonear(leftover))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_REFUND)
+                    .on_registration_refund_resolved(list_id, U128(leftover)),
+            )
+    }
+
+    /// Callback after refunding the registration budget; a failed refund restores it
+    #[private]
+    pub fn on_registration_refund_resolved(&mut self, list_id: ListId, amount: U128) -> bool {
+        if is_promise_success() {
+            return true;
+        }
+
+        self.update_registration(&list_id, |registration| {
+            registration.budget = U128(registration.budget.0 + amount.0);
+        });
+        log!(
+            "Refund of registration budget of list {} failed, budget restored",
+            list_id
+        );
+        false
+    }
+
+    /// Forget registrations in flight for more than `REGISTRATION_TIMEOUT_BLOCKS`, whose
+    /// callback failed (public function, anyone can call)
+    ///
+    /// Their payments are paid by the next `payout_batch` if still pending; payments whose
+    /// transfer was sent stay paid.
+    ///
+    /// # Returns
+    /// Number of registrations cleared
+    pub fn clear_stale_registrations(&mut self, list_id: ListId) -> u32 {
+        let mut cleared = 0;
+        self.update_registration(&list_id, |registration| {
+            let before = registration.in_flight.len();
+            registration.in_flight.retain(|entry| {
+                entry.scheduled_at + REGISTRATION_TIMEOUT_BLOCKS >= env::block_height()
+            });
+            cleared = (before - registration.in_flight.len()) as u32;
+        });
+        log!(
+            "Cleared {} stale registrations of list {}",
+            cleared,
+            list_id
+        );
+        cleared
+    }
+
+    /// Callback with the recipient's storage balance and the token's storage bounds.
+    /// Registers the recipient if needed and the budget allows, then transfers.
+    #[private]
+    pub fn on_recipient_storage_checked(
+        &mut self,
+        list_id: ListId,
+        index: u32,
+        #[callback_result] storage_balance: Result<Option<StorageBalance>, PromiseError>,
+        #[callback_result] bounds: Result<StorageBalanceBounds, PromiseError>,
+    ) {
+        let list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found");
+        let payment = &list.payments[index as usize];
+        let recipient = payment.recipient.clone();
+        let token_id = list.token_id.clone();
+        let transfer_gas = list.executor().attached_gas();
+        let remaining = list
+            .registration
+            .as_ref()
+            .expect("List does not register recipients")
+            .remaining();
+
+        let deposit = match (storage_balance, bounds) {
+            (Ok(None), Ok(bounds)) if bounds.min.0 <= remaining => bounds.min.0,
+            (Ok(None), _) => {
+                log!(
+                    "Registration budget of list {} cannot cover storage for {}, payment {} stays pending",
+                    list_id,
+                    recipient,
+                    index
+                );
+                self.update_registration(&list_id, |registration| registration.finish(index));
+                return;
+            }
+            // Registered, or the token does not implement NEP-145
+            _ => return self.transfer_registered_payment(list_id, index),
+        };
+
+        self.update_registration(&list_id, |registration| {
+            registration.spent = U128(registration.spent.0 + deposit);
+        });
+        log!(
+            "Registering {} with {} for list {} ({})",
+            recipient,
+            token_id,
+            list_id,
+            deposit
+        );
+
+        let token_account = token_id
+            .ft_contract()
+            .expect("Registration requires a NEP-141 token")
+            .clone();
+        Promise::new(token_account)
+            .function_call(
+                "storage_deposit".to_string(),
+                json!({ "account_id": recipient, "registration_only": true })
+                    .to_string()
+                    .into_bytes(),
+                NearToken::from_yoctonear(deposit),
+                GAS_FOR_STORAGE_DEPOSIT,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(registered_callback_gas(transfer_gas))
+                    .on_recipient_registered(list_id, index, U128(deposit)),
+            )
+            .detach();
+    }
+
+    /// Callback after `storage_deposit`; a failed registration is not charged to the budget
+    /// and leaves the payment pending
+    #[private]
+    pub fn on_recipient_registered(&mut self, list_id: ListId, index: u32, deposit: U128) {
+        if !is_promise_success() {
+            self.update_registration(&list_id, |registration| {
+                registration.spent = U128(registration.spent.0 - deposit.0);
+                registration.finish(index);
+            });
+            log!(
+                "Registration of payment {} of list {} failed, payment stays pending",
+                index,
+                list_id
+            );
+            return;
+        }
+        self.transfer_registered_payment(list_id, index);
+    }
+
+    /// Callback after the transfer of a registered payment; a failed transfer returns the
+    /// payment to pending
+    #[private]
+    pub fn on_registered_payment_resolved(&mut self, list_id: ListId, index: u32) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        let payment = &list.payments[index as usize];
+        let amount = payment.net_amount.unwrap_or(payment.amount).0;
+        let withheld = payment.withheld_amount.map_or(0, |amount| amount.0);
+        let delivered = transfer_delivered(&list, amount);
+        if let Some(registration) = list.registration.as_mut() {
+            registration.finish(index);
+        }
+
+        if delivered {
+            if !list
+                .payments
+                .iter()
+                .any(|p| matches!(p.status, PaymentStatus::Pending))
+            {
+                self.try_remit_withholding(&list_id, &mut list);
+            }
+        } else {
+            list.payments[index as usize].status = PaymentStatus::Pending;
+            list.sub_withheld(withheld);
+            log!(
+                "Transfer of payment {} of list {} failed, payment stays pending",
+                index,
+                list_id
+            );
+        }
+        self.payment_lists.insert(list_id, list);
+        delivered
+    }
+}
+
+impl BulkPaymentContract {
+    /// First step of paying a recipient of a list with registration: query the
+    /// recipient's storage balance and the token's storage bounds
+    pub(crate) fn registering_payment_promise(
+        token_account: &AccountId,
+        transfer_gas: Gas,
+        list_id: &ListId,
+        index: u32,
+        recipient: &AccountId,
+    ) -> Promise {
+        Promise::new(token_account.clone())
+            .function_call(
+                "storage_balance_of".to_string(),
+                json!({ "account_id": recipient }).to_string().into_bytes(),
+                NearToken::from_yoctonear(0),
+                GAS_FOR_STORAGE_VIEW,
+            )
+            .and(Promise::new(token_account.clone()).function_call(
+                "storage_balance_bounds".to_string(),
+                b"{}".to_vec(),
+                NearToken::from_yoctonear(0),
+                GAS_FOR_STORAGE_VIEW,
+            ))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(storage_checked_callback_gas(transfer_gas))
+                    .on_recipient_storage_checked(list_id.clone(), index),
+            )
+    }
+
+    /// Mark a payment whose recipient is registered as paid and transfer it
+    fn transfer_registered_payment(&mut self, list_id: ListId, index: u32) {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        list.payments[index as usize].status = PaymentStatus::Paid {
+            block_height: env::block_height(),
+        };
+        let payment = &list.payments[index as usize];
+        let withheld = payment.withheld_amount.map_or(0, |amount| amount.0);
+        withholding::pay_net(&*list.executor(), payment)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
+                    .on_registered_payment_resolved(list_id.clone(), index),
+            )
+            .detach();
+        list.add_withheld(withheld);
+        self.payment_lists.insert(list_id, list);
+    }
+
+    fn update_registration(
+        &mut self,
+        list_id: &ListId,
+        update: impl FnOnce(&mut RecipientRegistration),
+    ) {
+        let mut list = self
+            .payment_lists
+            .get(list_id)
+            .expect("Payment list not found")
+            .clone();
+        update(
+            list.registration
+                .as_mut()
+                .expect("List does not register recipients"),
+        );
+        self.payment_lists.insert(list_id.clone(), list);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{ListOptions, PaymentInput, TokenGas, TokenId};
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig};
+
+    const LIST_ID: &str = "d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1d1";
+    const MIN_STORAGE: u128 = 1_250_000_000_000_000_000_000;
+
+    /// Submit an FT list with recipient registration, approve it via ft_on_transfer
+    /// and fund a registration budget for one recipient
+    fn setup_registering_list() -> (VMContextBuilder, BulkPaymentContract) {
+        setup_registering_list_with_gas(None)
+    }
+
+    fn setup_registering_list_with_gas(
+        attached_gas: Option<Gas>,
+    ) -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 100),
+            PaymentInput::new(accounts(2), 200),
+        ];
+        contract.submit_list(
+            LIST_ID.to_string(),
+            "token.near".parse().unwrap(),
+            payments,
+            None,
+            Some(ListOptions {
+                register_recipients: true,
+                attached_gas: attached_gas.map(|gas| {
+                    vec![TokenGas {
+                        token_id: "token.near".parse().unwrap(),
+                        gas,
+                    }]
+                }),
+                ..Default::default()
+            }),
+        );
+        context.predecessor_account_id("token.near".parse().unwrap());
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(300), LIST_ID.to_string());
+
+        context.predecessor_account_id(accounts(0));
+        context.attached_deposit(NearToken::from_yoctonear(MIN_STORAGE));
+        testing_env!(context.build());
+        contract.fund_registration_budget(LIST_ID.to_string());
+
+        context.attached_deposit(NearToken::from_yoctonear(0));
+        (context, contract)
+    }
+
+    /// Run a callback as the contract itself with the given promise results
+    fn callback_context(context: &mut VMContextBuilder, results: Vec<PromiseResult>) {
+        context.predecessor_account_id(accounts(4));
+        context.current_account_id(accounts(4));
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results,
+        );
+    }
+
+    fn registration(contract: &BulkPaymentContract) -> RecipientRegistration {
+        contract
+            .view_list(LIST_ID.to_string())
+            .registration
+            .unwrap()
+    }
+
+    #[test]
+    #[should_panic(expected = "register_recipients requires a NEP-141 token contract")]
+    fn test_register_recipients_rejects_native_token() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 1);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            vec![PaymentInput::new(accounts(1), 100)],
+            None,
+            Some(ListOptions {
+                register_recipients: true,
+                ..Default::default()
+            }),
+        );
+    }
+
+    fn bounds() -> Result<StorageBalanceBounds, PromiseError> {
+        Ok(StorageBalanceBounds {
+            min: U128(MIN_STORAGE),
+            max: None,
+        })
+    }
+
+    fn in_flight(contract: &BulkPaymentContract) -> Vec<u32> {
+        registration(contract)
+            .in_flight
+            .iter()
+            .map(|entry| entry.index)
+            .collect()
+    }
+
+    fn is_pending(contract: &BulkPaymentContract, index: usize) -> bool {
+        matches!(
+            contract.view_list(LIST_ID.to_string()).payments[index].status,
+            PaymentStatus::Pending
+        )
+    }
+
+    #[test]
+    fn test_registration_spends_budget_once() {
+        let (mut context, mut contract) = setup_registering_list();
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        // Both payments stay pending until their transfers are sent
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 2);
+        assert_eq!(in_flight(&contract), vec![0, 1]);
+
+        // First recipient is unregistered and gets registered from the budget
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(LIST_ID.to_string(), 0, Ok(None), bounds());
+        assert_eq!(registration(&contract).spent.0, MIN_STORAGE);
+        assert!(is_pending(&contract, 0));
+
+        callback_context(&mut context, vec![PromiseResult::Successful(vec![])]);
+        contract.on_recipient_registered(LIST_ID.to_string(), 0, U128(MIN_STORAGE));
+        assert!(!is_pending(&contract, 0));
+        assert_eq!(in_flight(&contract), vec![0, 1]);
+
+        callback_context(&mut context, vec![PromiseResult::Successful(vec![])]);
+        assert!(contract.on_registered_payment_resolved(LIST_ID.to_string(), 0));
+        assert_eq!(in_flight(&contract), vec![1]);
+
+        // The budget is used up, so the second payment waits for a top-up
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(LIST_ID.to_string(), 1, Ok(None), bounds());
+        assert_eq!(registration(&contract).spent.0, MIN_STORAGE);
+        assert!(in_flight(&contract).is_empty());
+        assert!(is_pending(&contract, 1));
+    }
+
+    #[test]
+    fn test_failed_registration_is_not_charged_and_leftover_refunded() {
+        let (mut context, mut contract) = setup_registering_list();
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(LIST_ID.to_string(), 0, Ok(None), bounds());
+        callback_context(&mut context, vec![PromiseResult::Failed]);
+        contract.on_recipient_registered(LIST_ID.to_string(), 0, U128(MIN_STORAGE));
+        assert_eq!(registration(&contract).spent.0, 0);
+        assert!(is_pending(&contract, 0));
+
+        // The second recipient is already registered and paid
+        let registered = || {
+            Ok(Some(StorageBalance {
+                total: U128(MIN_STORAGE),
+                available: U128(0),
+            }))
+        };
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(LIST_ID.to_string(), 1, registered(), bounds());
+        callback_context(&mut context, vec![PromiseResult::Successful(vec![])]);
+        contract.on_registered_payment_resolved(LIST_ID.to_string(), 1);
+
+        // The next batch only retries the first payment, whose recipient registered meanwhile
+        context
+            .predecessor_account_id(accounts(0))
+            .current_account_id(accounts(0));
+        testing_env!(context.build());
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 1);
+        assert_eq!(in_flight(&contract), vec![0]);
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(LIST_ID.to_string(), 0, registered(), bounds());
+        callback_context(&mut context, vec![PromiseResult::Successful(vec![])]);
+        contract.on_registered_payment_resolved(LIST_ID.to_string(), 0);
+
+        context
+            .predecessor_account_id(accounts(0))
+            .current_account_id(accounts(0));
+        testing_env!(context.build());
+        contract
+            .refund_registration_budget(LIST_ID.to_string())
+            .detach();
+        assert_eq!(registration(&contract).budget.0, 0);
+    }
+
+    #[test]
+    fn test_failed_transfer_returns_payment_to_pending() {
+        let (mut context, mut contract) = setup_registering_list();
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), Some(1));
+
+        callback_context(&mut context, vec![]);
+        contract.on_recipient_storage_checked(
+            LIST_ID.to_string(),
+            0,
+            Ok(Some(StorageBalance {
+                total: U128(MIN_STORAGE),
+                available: U128(0),
+            })),
+            bounds(),
+        );
+        callback_context(&mut context, vec![PromiseResult::Failed]);
+        assert!(!contract.on_registered_payment_resolved(LIST_ID.to_string(), 0));
+        assert!(is_pending(&contract, 0));
+        assert!(in_flight(&contract).is_empty());
+    }
+
+    #[test]
+    fn test_stale_registrations_cleared_after_timeout() {
+        let (mut context, mut contract) = setup_registering_list();
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        contract.payout_batch(LIST_ID.to_string(), None);
+        assert_eq!(contract.clear_stale_registrations(LIST_ID.to_string()), 0);
+
+        // The check callbacks never ran
+        context.block_height(REGISTRATION_TIMEOUT_BLOCKS + 1);
+        testing_env!(context.build());
+        assert_eq!(contract.clear_stale_registrations(LIST_ID.to_string()), 2);
+        assert!(is_pending(&contract, 0) && is_pending(&contract, 1));
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 2);
+        assert_eq!(in_flight(&contract), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_approve_list_deposit_funds_registration_budget() {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 1);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            "token.near".parse().unwrap(),
+            vec![PaymentInput::new(accounts(1), 100)],
+            None,
+            Some(ListOptions {
+                register_recipients: true,
+                ..Default::default()
+            }),
+        );
+
+        // Same proposal: NEAR for registration, then the tokens
+        context.attached_deposit(NearToken::from_yoctonear(MIN_STORAGE));
+        testing_env!(context.build());
+        contract.approve_list(LIST_ID.to_string());
+        assert_eq!(registration(&contract).budget.0, MIN_STORAGE);
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).status,
+            ListStatus::Pending
+        ));
+
+        context
+            .predecessor_account_id("token.near".parse().unwrap())
+            .attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+        contract.ft_on_transfer(accounts(0), U128(100), LIST_ID.to_string());
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).status,
+            ListStatus::Approved
+        ));
+    }
+
+    #[test]
+    fn test_callback_gas_follows_attached_gas() {
+        let (mut context, mut contract) =
+            setup_registering_list_with_gas(Some(Gas::from_tgas(150)));
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        // 210 TGas per payment, so one per call
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 2);
+        assert_eq!(in_flight(&contract), vec![0]);
+        let callback_gas = get_created_receipts()
+            .iter()
+            .flat_map(|receipt| &receipt.actions)
+            .find_map(|action| match action {
+                MockAction::FunctionCallWeight {
+                    method_name,
+                    prepaid_gas,
+                    ..
+                } if method_name == b"on_recipient_storage_checked" => Some(*prepaid_gas),
+                _ => None,
+            });
+        assert_eq!(callback_gas, Some(Gas::from_tgas(195)));
+        assert_eq!(
+            registering_payment_gas(Gas::from_tgas(50)),
+            Gas::from_tgas(110)
+        );
+    }
+}
@@ -0,0 +1,505 @@
+// Recipient screening
+//
+// The contract account keeps a denylist of accounts that must not be paid, e.g. sanctioned
+// or compromised accounts. A submitter can additionally enable an allowlist, after which
+// only the accounts on it can be paid from its lists. Both are checked when a list is
+// submitted, and again in `payout_batch` and `claim`, since the lists can change in between:
+// records of recipients screened out by then are set to Blocked instead of being paid, and
+// the submitter gets them back with `refund_blocked`. Every change to the lists is logged
+// as a NEP-297 event.
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near, require, AccountId, Gas};
+
+use crate::executor::executor;
+use crate::{
+    events, BulkPaymentContract, BulkPaymentContractExt, IntentsDelivery, ListId, ListStatus,
+    PaymentInput, PaymentRecord, PaymentStatus,
+};
+
+/// Most accounts added to or removed from a list per call
+const MAX_ACCOUNTS_PER_CALL: usize = 100;
+/// Default and maximum page size of `view_denylist`
+const MAX_PAGE_SIZE: u32 = 100;
+const GAS_FOR_RESOLVE_REFUND: Gas = Gas::from_tgas(10);
+/// Gas kept back by `refund_blocked` for its own bookkeeping
+const GAS_RESERVE: Gas = Gas::from_tgas(15);
+
+fn require_page(account_ids: &[AccountId]) {
+    require!(
+        !account_ids.is_empty() && account_ids.len() <= MAX_ACCOUNTS_PER_CALL,
+        format!(
+            "Between 1 and {} accounts can be changed per call",
+            MAX_ACCOUNTS_PER_CALL
+        )
+    );
+}
+
+fn require_allowlist_admin(submitter_id: &AccountId) {
+    let caller = env::predecessor_account_id();
+    require!(
+        caller == env::current_account_id() || caller == *submitter_id,
+        "Only the contract account or the submitter can change its allowlist"
+    );
+}
+
+/// Whether a submitter changes its own allowlist, paying for the entries it adds
+fn is_submitter_call() -> bool {
+    env::predecessor_account_id() != env::current_account_id()
+}
+
+impl BulkPaymentContract {
+    /// Why `submitter` may not pay `recipient`, if it may not
+    pub(crate) fn screening_issue(
+        &self,
+        submitter: &AccountId,
+        recipient: &AccountId,
+    ) -> Option<&'static str> {
+        if self.denylist.contains(recipient) {
+            Some("is on the denylist")
+        } else if self.allowlist_submitters.contains(submitter)
+            && !self
+                .allowlists
+                .contains(&(submitter.clone(), recipient.clone()))
+        {
+            Some("is not on the submitter's allowlist")
+        } else {
+            None
+        }
+    }
+
+    /// Panic listing every payment whose recipient `submitter` may not pay
+    pub(crate) fn require_screened_recipients(
+        &self,
+        submitter: &AccountId,
+        payments: &[PaymentInput],
+    ) {
+        let blocked: Vec<String> = payments
+            .iter()
+            .enumerate()
+            .filter_map(|(index, payment)| {
+                self.screening_issue(submitter, &payment.recipient)
+                    .map(|issue| format!("payment {}: {} {}", index, payment.recipient, issue))
+            })
+            .collect();
+        require!(
+            blocked.is_empty(),
+            format!("Blocked recipients: {}", blocked.join("; "))
+        );
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Add accounts to the denylist (contract account only, at most 100 per call)
+    #[private]
+    pub fn add_to_denylist(&mut self, account_ids: Vec<AccountId>) {
+        require_page(&account_ids);
+        let added: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| self.denylist.insert(account_id.clone()))
+            .collect();
+        if !added.is_empty() {
+            events::emit("denylist_add", json!({ "account_ids": added }));
+        }
+    }
+
+    /// Remove accounts from the denylist (contract account only, at most 100 per call)
+    #[private]
+    pub fn remove_from_denylist(&mut self, account_ids: Vec<AccountId>) {
+        require_page(&account_ids);
+        let removed: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| self.denylist.remove(account_id))
+            .collect();
+        if !removed.is_empty() {
+            events::emit("denylist_remove", json!({ "account_ids": removed }));
+        }
+    }
+
+    /// Page through the denylist
+    pub fn view_denylist(&self, from_index: Option<u32>, limit: Option<u32>) -> Vec<AccountId> {
+        self.denylist
+            .iter()
+            .skip(from_index.unwrap_or(0) as usize)
+            .take(limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE) as usize)
+            .cloned()
+            .collect()
+    }
+
+    /// Enable or disable the allowlist of a submitter (contract account or the submitter).
+    /// A submitter enabling its allowlist uses one storage credit.
+    pub fn set_allowlist_enabled(&mut self, submitter_id: AccountId, enabled: bool) {
+        require_allowlist_admin(&submitter_id);
+        let changed = if enabled {
+            self.allowlist_submitters.insert(submitter_id.clone())
+        } else {
+            self.allowlist_submitters.remove(&submitter_id)
+        };
+        if !changed {
+            return;
+        }
+        if enabled && is_submitter_call() {
+            self.use_storage_credits(&submitter_id, 1);
+        }
+        events::emit(
+            if enabled {
+                "allowlist_enable"
+            } else {
+                "allowlist_disable"
+            },
+            json!({ "submitter_id": submitter_id }),
+        );
+    }
+
+    /// Add accounts to the allowlist of a submitter (contract account or the submitter,
+    /// at most 100 per call). A submitter uses one storage credit per added account.
+    pub fn add_to_allowlist(&mut self, submitter_id: AccountId, account_ids: Vec<AccountId>) {
+        require_allowlist_admin(&submitter_id);
+        require_page(&account_ids);
+        let added: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| {
+                self.allowlists
+                    .insert((submitter_id.clone(), account_id.clone()))
+            })
+            .collect();
+        if added.is_empty() {
+            return;
+        }
+        if is_submitter_call() {
+            self.use_storage_credits(&submitter_id, added.len() as u128);
+        }
+        events::emit(
+            "allowlist_add",
+            json!({ "submitter_id": submitter_id, "account_ids": added }),
+        );
+    }
+
+    /// Remove accounts from the allowlist of a submitter (contract account or the
+    /// submitter, at most 100 per call)
+    pub fn remove_from_allowlist(&mut self, submitter_id: AccountId, account_ids: Vec<AccountId>) {
+        require_allowlist_admin(&submitter_id);
+        require_page(&account_ids);
+        let removed: Vec<AccountId> = account_ids
+            .into_iter()
+            .filter(|account_id| {
+                self.allowlists
+                    .remove(&(submitter_id.clone(), account_id.clone()))
+            })
+            .collect();
+        if !removed.is_empty() {
+            events::emit(
+                "allowlist_remove",
+                json!({ "submitter_id": submitter_id, "account_ids": removed }),
+            );
+        }
+    }
+
+    /// Whether lists of `submitter_id` can currently pay `account_id`
+    pub fn is_recipient_allowed(&self, submitter_id: AccountId, account_id: AccountId) -> bool {
+        self.screening_issue(&submitter_id, &account_id).is_none()
+    }
+
+    /// Return the blocked payments of a list to its submitter (submitter only)
+    ///
+    /// Blocked payments are refunded in one transfer per token (one per NFT for NFT
+    /// lists) and marked Reclaimed; failed refunds put them back to Blocked. Stops early
+    /// when the gas runs low.
+    ///
+    /// # Returns
+    /// Number of blocked payments left to refund
+    pub fn refund_blocked(&mut self, list_id: ListId) -> u64 {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        require!(
+            list.submitter == env::predecessor_account_id(),
+            "Only the submitter can refund blocked payments"
+        );
+        require!(
+            matches!(list.status, ListStatus::Approved),
+            "List must be Approved to refund blocked payments"
+        );
+
+        // Group the blocked payments into refunds: one per token, one per NFT
+        let mut refunds: Vec<Vec<usize>> = Vec::new();
+        for (index, payment) in list.payments.iter().enumerate() {
+            if !matches!(payment.status, PaymentStatus::Blocked { .. }) {
+                continue;
+            }
+            let token_id = list.payment_token(payment);
+            let group = (payment.nft_token_id.is_none())
+                .then(|| {
+                    refunds
+                        .iter_mut()
+                        .find(|group| list.payment_token(&list.payments[group[0]]) == token_id)
+                })
+                .flatten();
+            match group {
+                Some(group) => group.push(index),
+                None => refunds.push(vec![index]),
+            }
+        }
+        require!(!refunds.is_empty(), "No blocked payments to refund");
+
+        let block_height = env::block_height();
+        let mut refunded = 0;
+        for indices in refunds {
+            let first = &list.payments[indices[0]];
+            let token_id = list.payment_token(first).clone();
+            let executor = executor(&token_id, IntentsDelivery::Transfer);
+            let gas_remaining = env::prepaid_gas().saturating_sub(env::used_gas());
+            let gas_needed = executor
+                .gas_per_payment()
+                .saturating_add(GAS_FOR_RESOLVE_REFUND)
+                .saturating_add(GAS_RESERVE);
+            if gas_remaining < gas_needed {
+                require!(refunded > 0, "Insufficient gas to refund blocked payments");
+                break;
+            }
+
+            let amount = indices
+                .iter()
+                .map(|index| list.payments[*index].amount.0)
+                .sum();
+            let refund = PaymentRecord {
+                recipient: list.submitter.clone(),
+                amount: near_sdk::json_types::U128(amount),
+                memo: None,
+                destination: None,
+                ..first.clone()
+            };
+            executor
+                .pay_record(&refund)
+                .then(
+                    Self::ext(env::current_account_id())
+                        .with_static_gas(GAS_FOR_RESOLVE_REFUND)
+                        .on_blocked_refund_resolved(
+                            list_id.clone(),
+                            indices.iter().map(|index| *index as u32).collect(),
+                        ),
+                )
+                .detach();
+
+            for index in &indices {
+                list.payments[*index].status = PaymentStatus::Reclaimed { block_height };
+            }
+            refunded += indices.len();
+        }
+
+        let remaining = list
+            .payments
+            .iter()
+            .filter(|p| matches!(p.status, PaymentStatus::Blocked { .. }))
+            .count() as u64;
+        self.payment_lists.insert(list_id.clone(), list);
+        log!(
+            "Refunded {} blocked payments of list {}, {} remaining",
+            refunded,
+            list_id,
+            remaining
+        );
+        remaining
+    }
+
+    /// Callback after refunding blocked payments; a failed refund sets them back to Blocked
+    #[private]
+    pub fn on_blocked_refund_resolved(&mut self, list_id: ListId, indices: Vec<u32>) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        let first = &list.payments[indices[0] as usize];
+        let token_id = list.payment_token(first).clone();
+        let amount = indices
+            .iter()
+            .map(|index| list.payments[*index as usize].amount.0)
+            .sum();
+        if executor(&token_id, IntentsDelivery::Transfer)
+            .is_delivered(&env::promise_result(0), amount)
+        {
+            return true;
+        }
+
+        let block_height = env::block_height();
+        for index in &indices {
+            list.payments[*index as usize].status = PaymentStatus::Blocked { block_height };
+        }
+        self.payment_lists.insert(list_id.clone(), list);
+        log!(
+            "Refund of {} blocked payments of list {} failed",
+            indices.len(),
+            list_id
+        );
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{approve_list, contract_with_storage};
+    use near_sdk::test_utils::{accounts, get_created_receipts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, NearToken, PromiseResult, RuntimeFeesConfig};
+
+    const LIST_ID: &str = "f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7";
+
+    fn payments() -> Vec<PaymentInput> {
+        vec![
+            PaymentInput::new(accounts(2), 100),
+            PaymentInput::new(accounts(3), 200),
+            PaymentInput::new(accounts(4), 300),
+        ]
+    }
+
+    /// Contract at accounts(0), lists submitted by accounts(1)
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1));
+        let contract = contract_with_storage(&mut context, 3);
+        (context, contract)
+    }
+
+    fn as_contract(context: &mut VMContextBuilder) {
+        context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+    }
+
+    fn as_submitter(context: &mut VMContextBuilder) {
+        context
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(NearToken::from_yoctonear(0));
+        testing_env!(context.build());
+    }
+
+    #[test]
+    fn test_denylist_events_and_paging() {
+        let (mut context, mut contract) = setup();
+        as_contract(&mut context);
+        contract.add_to_denylist(vec![accounts(3), accounts(4), accounts(3)]);
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"bulk_payment","version":"1.0.0","event":"denylist_add","data":[{"account_ids":["danny","eugene"]}]}"#
+            ]
+        );
+        assert_eq!(contract.view_denylist(Some(1), Some(10)), vec![accounts(4)]);
+
+        // Changes that add or remove nothing are not logged
+        contract.add_to_denylist(vec![accounts(3)]);
+        contract.remove_from_denylist(vec![accounts(5)]);
+        assert_eq!(get_logs().len(), 1);
+
+        contract.remove_from_denylist(vec![accounts(4)]);
+        assert_eq!(contract.view_denylist(None, None), vec![accounts(3)]);
+        assert!(!contract.is_recipient_allowed(accounts(1), accounts(3)));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Blocked recipients: payment 1: danny is on the denylist; payment 2: eugene is not on the submitter's allowlist"
+    )]
+    fn test_submit_list_screens_recipients() {
+        let (mut context, mut contract) = setup();
+        as_contract(&mut context);
+        contract.add_to_denylist(vec![accounts(3)]);
+
+        as_contract(&mut context);
+        contract.set_allowlist_enabled(accounts(1), true);
+        contract.add_to_allowlist(accounts(1), vec![accounts(2), accounts(3)]);
+
+        as_submitter(&mut context);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            crate::TokenId::Native,
+            payments(),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_submitter_pays_storage_for_allowlist() {
+        let (mut context, mut contract) = setup();
+        as_submitter(&mut context);
+        contract.set_allowlist_enabled(accounts(1), true);
+        // Enabling it again writes nothing
+        contract.set_allowlist_enabled(accounts(1), true);
+        assert_eq!(contract.view_storage_credits(accounts(1)).as_yoctonear(), 2);
+
+        contract.add_to_allowlist(accounts(1), vec![accounts(2), accounts(3), accounts(2)]);
+        assert_eq!(contract.view_storage_credits(accounts(1)).as_yoctonear(), 0);
+        assert!(contract.is_recipient_allowed(accounts(1), accounts(3)));
+
+        // The contract account changes it for free
+        as_contract(&mut context);
+        contract.add_to_allowlist(accounts(1), vec![accounts(4)]);
+        assert_eq!(contract.view_storage_credits(accounts(1)).as_yoctonear(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient storage credits. Required: 4, Available: 3")]
+    fn test_allowlist_entries_need_storage_credits() {
+        let (mut context, mut contract) = setup();
+        as_submitter(&mut context);
+        contract.add_to_allowlist(
+            accounts(1),
+            vec![accounts(2), accounts(3), accounts(4), accounts(5)],
+        );
+    }
+
+    #[test]
+    fn test_payout_blocks_newly_denied_recipient_and_refunds() {
+        let (mut context, mut contract) = setup();
+        as_submitter(&mut context);
+        contract.submit_list(
+            LIST_ID.to_string(),
+            crate::TokenId::Native,
+            payments(),
+            None,
+            None,
+        );
+        approve_list(&mut context, &mut contract, LIST_ID, 600);
+
+        as_contract(&mut context);
+        contract.add_to_denylist(vec![accounts(3)]);
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 0);
+        let list = contract.view_list(LIST_ID.to_string());
+        assert!(matches!(
+            list.payments[1].status,
+            PaymentStatus::Blocked { .. }
+        ));
+        assert_eq!(get_created_receipts().len(), 2);
+
+        as_submitter(&mut context);
+        assert_eq!(contract.refund_blocked(LIST_ID.to_string()), 0);
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).payments[1].status,
+            PaymentStatus::Reclaimed { .. }
+        ));
+
+        // A failed refund can be retried
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_blocked_refund_resolved(LIST_ID.to_string(), vec![1]));
+        assert!(matches!(
+            contract.view_list(LIST_ID.to_string()).payments[1].status,
+            PaymentStatus::Blocked { .. }
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+// Weighted payment splits
+//
+// Grant rounds are often proposed as "split this total across these recipients by weight".
+// `submit_weighted_list` takes the total and one weight per recipient in basis points
+// (summing to 10,000) and computes the amounts itself:
+// - each share gets floor(total * weight_bps / 10,000)
+// - the rounding remainder (less than one unit per share) goes to the first or the last
+//   share, as chosen by `remainder` (the last one by default)
+// The records are then stored as with `submit_list`, in the order of the shares. The list
+// keeps the total, the weights and the rounding rule in `split`, and the API hashes the
+// split instead of the computed amounts, so the list ID commits to what was proposed.
+use near_sdk::json_types::U128;
+use near_sdk::{env, log, near, require, AccountId};
+
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, DuplicatePolicy, ListId, ListOptions,
+    PaymentInput, TokenId,
+};
+
+/// Weights of a split add up to this many basis points
+pub const TOTAL_BASIS_POINTS: u32 = 10_000;
+
+/// One recipient of a weighted split
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct WeightedShare {
+    pub recipient: AccountId,
+    /// Share of the total in basis points (1/100 of a percent)
+    pub weight_bps: u32,
+    pub memo: Option<String>,
+    pub reference: Option<String>,
+}
+
+/// Share that receives the rounding remainder of a split
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum SplitRemainder {
+    First,
+    #[default]
+    Last,
+}
+
+/// Total and weights of a split, as passed to `submit_weighted_list`
+#[near(serializers = [json])]
+pub struct WeightedSplit {
+    pub total: U128,
+    pub shares: Vec<WeightedShare>,
+    #[serde(default)]
+    pub remainder: SplitRemainder,
+}
+
+/// Terms a weighted list was computed from, the weights in the order of its records
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct SplitTerms {
+    pub total: U128,
+    pub weights_bps: Vec<u32>,
+    pub remainder: SplitRemainder,
+}
+
+/// floor(amount * bps / 10,000), without overflowing for `bps` up to 10,000
+pub(crate) fn bps_of(amount: u128, bps: u32) -> u128 {
+    let unit = TOTAL_BASIS_POINTS as u128;
+    (amount / unit) * bps as u128 + (amount % unit) * bps as u128 / unit
+}
+
+/// Amount of each weight's share of `total`, the remainder added to the first or last one
+pub(crate) fn split_amounts(
+    total: u128,
+    weights_bps: &[u32],
+    remainder: SplitRemainder,
+) -> Vec<u128> {
+    let mut amounts: Vec<u128> = weights_bps
+        .iter()
+        .map(|&weight| bps_of(total, weight))
+        .collect();
+    let leftover = total - amounts.iter().sum::<u128>();
+    let target = match remainder {
+        SplitRemainder::First => 0,
+        SplitRemainder::Last => amounts.len() - 1,
+    };
+    amounts[target] += leftover;
+    amounts
+}
+
+impl WeightedSplit {
+    /// Panic unless the split has a positive total and weights adding up to 10,000
+    fn validate(&self) {
+        require!(!self.shares.is_empty(), "Payment list cannot be empty");
+        require!(self.total.0 > 0, "Split total must be greater than 0");
+        if let Some(index) = self.shares.iter().position(|share| share.weight_bps == 0) {
+            env::panic_str(&format!("Share {} has a zero weight", index));
+        }
+        let sum = self
+            .shares
+            .iter()
+            .map(|share| share.weight_bps as u64)
+            .sum::<u64>();
+        require!(
+            sum == TOTAL_BASIS_POINTS as u64,
+            format!(
+                "Split weights must add up to {} basis points, got {}",
+                TOTAL_BASIS_POINTS, sum
+            )
+        );
+    }
+
+    /// Payments of the split and the terms they were computed from
+    fn into_payments(self) -> (Vec<PaymentInput>, SplitTerms) {
+        let weights_bps: Vec<u32> = self.shares.iter().map(|share| share.weight_bps).collect();
+        let amounts = split_amounts(self.total.0, &weights_bps, self.remainder);
+        let payments = self
+            .shares
+            .into_iter()
+            .zip(amounts)
+            .map(|(share, amount)| PaymentInput {
+                recipient: share.recipient,
+                amount: U128(amount),
+                memo: share.memo,
+                reference: share.reference,
+                nft_token_id: None,
+                destination: None,
+                token_id: None,
+            })
+            .collect();
+        let terms = SplitTerms {
+            total: self.total,
+            weights_bps,
+            remainder: self.remainder,
+        };
+        (payments, terms)
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Submit a payment list splitting a total across recipients by weight
+    ///
+    /// Amounts are floor(total * weight_bps / 10,000), the remainder going to the first or
+    /// last share. Otherwise the list is submitted and charged like with `submit_list`,
+    /// except that duplicates cannot be merged.
+    ///
+    /// # Arguments
+    /// * `list_id` - The SHA-256 hash of the list, covering the split rather than the amounts
+    /// * `split` - Total, shares with weights in basis points (summing to 10,000) and the
+    ///             share receiving the rounding remainder
+    /// * `token_id`, `submitter_id`, `options` - As in `submit_list`
+    pub fn submit_weighted_list(
+        &mut self,
+        list_id: ListId,
+        token_id: TokenId,
+        split: WeightedSplit,
+        submitter_id: Option<AccountId>,
+        options: Option<ListOptions>,
+    ) -> ListId {
+        require!(
+            !matches!(token_id, TokenId::Nft(_)),
+            "Weighted lists do not support NFTs"
+        );
+        require!(
+            options
+                .as_ref()
+                .is_none_or(|options| options.duplicates != DuplicatePolicy::Merge),
+            "Weighted lists cannot merge duplicate payments"
+        );
+        split.validate();
+
+        let total = split.total;
+        let (payments, terms) = split.into_payments();
+        let list_id = self.submit_list(list_id, token_id, payments, submitter_id, options);
+
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        log!(
+            "Split {} across {} payments, remainder to the {:?} share",
+            total.0,
+            list.payments.len(),
+            terms.remainder
+        );
+        list.split = Some(terms);
+        self.payment_lists.insert(list_id.clone(), list);
+
+        list_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+
+    const LIST_ID: &str = "b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7b7";
+
+    fn setup() -> BulkPaymentContract {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        contract_with_storage(&mut context, 5)
+    }
+
+    fn split(total: u128, weights_bps: &[u32], remainder: SplitRemainder) -> WeightedSplit {
+        WeightedSplit {
+            total: U128(total),
+            shares: weights_bps
+                .iter()
+                .enumerate()
+                .map(|(n, &weight_bps)| WeightedShare {
+                    recipient: accounts(n + 1),
+                    weight_bps,
+                    memo: None,
+                    reference: None,
+                })
+                .collect(),
+            remainder,
+        }
+    }
+
+    #[test]
+    fn test_split_amounts_round_down_with_remainder() {
+        let weights = [3333, 3333, 3334];
+        assert_eq!(
+            split_amounts(100, &weights, SplitRemainder::Last),
+            vec![33, 33, 34]
+        );
+        assert_eq!(
+            split_amounts(100, &weights, SplitRemainder::First),
+            vec![34, 33, 33]
+        );
+        // No overflow for totals close to u128::MAX
+        let amounts = split_amounts(u128::MAX, &[5000, 5000], SplitRemainder::Last);
+        assert_eq!(amounts[0], u128::MAX / 2);
+        assert_eq!(amounts[0] + amounts[1], u128::MAX);
+    }
+
+    #[test]
+    fn test_submit_weighted_list_stores_amounts_and_terms() {
+        let mut contract = setup();
+        contract.submit_weighted_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            split(10_000_001, &[6000, 2500, 1500], SplitRemainder::First),
+            None,
+            None,
+        );
+
+        let list = contract.view_list(LIST_ID.to_string());
+        let amounts: Vec<u128> = list.payments.iter().map(|p| p.amount.0).collect();
+        assert_eq!(amounts, vec![6_000_001, 2_500_000, 1_500_000]);
+        assert_eq!(list.total_amount(), 10_000_001);
+        let terms = list.split.unwrap();
+        assert_eq!(terms.weights_bps, vec![6000, 2500, 1500]);
+        assert_eq!(terms.remainder, SplitRemainder::First);
+    }
+
+    #[test]
+    #[should_panic(expected = "Split weights must add up to 10000 basis points, got 9999")]
+    fn test_weights_must_add_up() {
+        let mut contract = setup();
+        contract.submit_weighted_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            split(1000, &[5000, 4999], SplitRemainder::Last),
+            None,
+            None,
+        );
+    }
+}
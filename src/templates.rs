@@ -0,0 +1,617 @@
+// Recurring payroll templates
+//
+// A template stores a fixed set of payments plus a cadence. Once per period a new
+// PaymentList instance is materialised from it, which is then funded and paid out
+// through the regular approve_list / ft_transfer_call / payout_batch flow.
+//
+// Instance list IDs are content hashes like those of submitted lists: the SHA-256 of the
+// canonical JSON of the instance's submitter, token, payments (sorted by recipient) and
+// options, plus the template ID and the period. Approvers can recompute it from
+// `view_template`, and the DAO can compute the ID of the next instance up front to
+// reference it in the funding proposal. `period` counts the instances created; periods in
+// which nobody materialised the template are skipped rather than created afterwards, so a
+// gap never produces a burst of lists. The template keeps the options its lists are
+// submitted with; recipients, payments and options are checked when the template is
+// created and again for every instance, against the validation config of the time.
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near, require, AccountId};
+
+use crate::mixed::validate_payment_tokens;
+use crate::{
+    to_hex, BulkPaymentContract, BulkPaymentContractExt, ListId, ListOptions, ListStatus,
+    PaymentInput, TokenId,
+};
+
+/// Template ID is a hex-encoded SHA-256 hash (64 characters), same format as [`ListId`]
+pub type TemplateId = String;
+
+const NANOS_PER_DAY: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// How often a payroll template produces a new payment list
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub enum Cadence {
+    Weekly,
+    Biweekly,
+    /// Fixed 30-day period
+    Monthly,
+}
+
+impl Cadence {
+    /// Length of one period in nanoseconds (block timestamp units)
+    pub fn period_ns(&self) -> u64 {
+        match self {
+            Self::Weekly => 7 * NANOS_PER_DAY,
+            Self::Biweekly => 14 * NANOS_PER_DAY,
+            Self::Monthly => 30 * NANOS_PER_DAY,
+        }
+    }
+}
+
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct PayrollTemplate {
+    pub token_id: TokenId,
+    pub submitter: AccountId,
+    pub payments: Vec<PaymentInput>,
+    /// Options every instance is submitted with
+    pub options: ListOptions,
+    pub cadence: Cadence,
+    /// Block timestamp (ns) at which the next instance can be materialised
+    pub next_run_at: u64,
+    /// Optional block timestamp (ns) after which no more instances are created
+    pub ends_at: Option<u64>,
+    /// Cancelled templates no longer produce instances
+    pub active: bool,
+    /// IDs of all payment lists materialised from this template, oldest first
+    pub instances: Vec<ListId>,
+    pub created_at: u64,
+}
+
+/// Schedule of a payroll template, as passed to `create_template`
+#[near(serializers = [json])]
+pub struct TemplateSchedule {
+    pub cadence: Cadence,
+    /// Block timestamp (ns) of the first period. Defaults to now.
+    pub start_at: Option<u64>,
+    /// Optional block timestamp (ns) after which no more periods start
+    pub ends_at: Option<u64>,
+}
+
+/// A payment list materialised from a payroll template
+#[near(serializers = [json])]
+pub struct TemplateInstance {
+    pub list_id: ListId,
+    pub period: u64,
+    pub status: ListStatus,
+}
+
+/// Derive the list ID of the instance of `template` for the given period from its content
+pub fn instance_list_id(template_id: &str, period: u64, template: &PayrollTemplate) -> ListId {
+    let mut payments: Vec<&PaymentInput> = template.payments.iter().collect();
+    payments.sort_by(|a, b| a.recipient.cmp(&b.recipient));
+    let mut canonical = json!({
+        "submitter": template.submitter,
+        "token_id": template.token_id,
+        "payments": payments,
+        "options": template.options,
+        "template_id": template_id,
+        "period": period,
+    });
+    canonical.sort_all_objects();
+    to_hex(&env::sha256(canonical.to_string().as_bytes()))
+}
+
+impl BulkPaymentContract {
+    /// Panic unless a list of `payments` with `options` can be submitted in `token_id`
+    fn require_valid_template_list(
+        &self,
+        token_id: &TokenId,
+        payments: &[PaymentInput],
+        options: &ListOptions,
+    ) {
+        require!(!payments.is_empty(), "Payment list cannot be empty");
+        self.require_valid_payments(token_id, payments, options.denomination);
+        require!(
+            !matches!(token_id, TokenId::Nft(_)),
+            "Payroll templates do not support NFT lists"
+        );
+        validate_payment_tokens(payments);
+        Self::validate_list_options(token_id, payments, options);
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Create a recurring payroll template
+    ///
+    /// The template itself stores its payments, so it consumes storage credits like a
+    /// regular list. Every materialised instance consumes credits again.
+    ///
+    /// # Arguments
+    /// * `template_id` - Client-generated ID (hex-encoded SHA-256 hash, 64 chars)
+    /// * `token_id` - The token to use for payments, same format as in `submit_list`
+    /// * `payments` - Payments repeated in every period
+    /// * `schedule` - Cadence plus optional start and end timestamps (ns)
+    /// * `submitter_id` - Optional submitter, same semantics as in `submit_list`
+    /// * `options` - Options of every instance, same as in `submit_list`
+    pub fn create_template(
+        &mut self,
+        template_id: TemplateId,
+        token_id: TokenId,
+        payments: Vec<PaymentInput>,
+        schedule: TemplateSchedule,
+        submitter_id: Option<AccountId>,
+        options: Option<ListOptions>,
+    ) -> TemplateId {
+        let options = options.unwrap_or_default();
+        self.require_valid_template_list(&token_id, &payments, &options);
+        require!(
+            Self::validate_list_id(&template_id),
+            "Invalid template_id: must be a 64-character hex string (SHA-256 hash)"
+        );
+        require!(
+            self.payroll_templates.get(&template_id).is_none(),
+            "Payroll template with this ID already exists"
+        );
+
+        let now = env::block_timestamp();
+        let start_at = schedule.start_at.unwrap_or(now);
+        if let Some(ends_at) = schedule.ends_at {
+            require!(
+                ends_at >= start_at,
+                "Template end date must not precede its start"
+            );
+        }
+
+        let caller = env::predecessor_account_id();
+        let submitter = if let Some(sid) = submitter_id {
+            require!(
+                caller == env::current_account_id(),
+                "Only the contract account can submit on behalf of another account"
+            );
+            sid
+        } else {
+            caller
+        };
+        self.require_screened_recipients(&submitter, &payments);
+
+        self.use_storage_credits(&submitter, options.storage_credits(&payments));
+
+        let num_payments = payments.len();
+        self.payroll_templates.insert(
+            template_id.clone(),
+            PayrollTemplate {
+                token_id,
+                submitter: submitter.clone(),
+                payments,
+                options,
+                cadence: schedule.cadence,
+                next_run_at: start_at,
+                ends_at: schedule.ends_at,
+                active: true,
+                instances: Vec::new(),
+                created_at: now,
+            },
+        );
+
+        log!(
+            "Payroll template {} created by {} with {} payments",
+            template_id,
+            submitter,
+            num_payments
+        );
+
+        template_id
+    }
+
+    /// Materialise the next payment list of a template (public function, anyone can call)
+    ///
+    /// Can be called once the current period has started. The new list is stored in
+    /// Pending status with the template submitter as its submitter, and is funded via
+    /// the usual `approve_list` / `ft_transfer_call` / `mt_transfer_call` flow. Periods
+    /// that passed without an instance are skipped: the next instance is due at the start
+    /// of the period after the current one.
+    ///
+    /// # Returns
+    /// The list_id of the new payment list
+    ///
+    /// # Panics
+    /// - If the template is not found or was cancelled
+    /// - If the next period has not started yet or the template has ended
+    /// - If the payments or options no longer pass validation
+    /// - If a recipient is screened out for the submitter
+    /// - If the submitter has insufficient storage credits
+    pub fn materialize_template(&mut self, template_id: TemplateId) -> ListId {
+        let mut template = self
+            .payroll_templates
+            .get(&template_id)
+            .expect("Payroll template not found")
+            .clone();
+
+        require!(template.active, "Payroll template is cancelled");
+
+        let now = env::block_timestamp();
+        require!(
+            now >= template.next_run_at,
+            format!(
+                "Next period starts at {}, current time is {}",
+                template.next_run_at, now
+            )
+        );
+        // Start of the current period; missed periods before it are skipped
+        let period_ns = template.cadence.period_ns();
+        let skipped = (now - template.next_run_at) / period_ns;
+        let period_start = template.next_run_at + skipped * period_ns;
+        require!(
+            template.ends_at.is_none_or(|end| period_start <= end),
+            "Payroll template has ended"
+        );
+
+        let period = template.instances.len() as u64;
+        let list_id = instance_list_id(&template_id, period, &template);
+        require!(
+            !self.is_list_id_taken(&list_id),
+            "Payment list with this ID already exists"
+        );
+
+        // The validation config may have changed since the template was created
+        self.require_valid_template_list(&template.token_id, &template.payments, &template.options);
+        self.require_screened_recipients(&template.submitter, &template.payments);
+        self.use_storage_credits(
+            &template.submitter,
+            template.options.storage_credits(&template.payments),
+        );
+        self.insert_pending_list(
+            list_id.clone(),
+            template.token_id.clone(),
+            template.submitter.clone(),
+            template.payments.clone(),
+            Some(template_id.clone()),
+            template.options.clone(),
+        );
+
+        template.instances.push(list_id.clone());
+        template.next_run_at = period_start
+            .checked_add(period_ns)
+            .expect("Template schedule overflow");
+        self.payroll_templates.insert(template_id.clone(), template);
+
+        log!(
+            "Payroll template {} materialised period {} as list {}{}",
+            template_id,
+            period,
+            list_id,
+            if skipped > 0 {
+                format!(", {} missed periods skipped", skipped)
+            } else {
+                String::new()
+            }
+        );
+
+        list_id
+    }
+
+    /// Cancel a payroll template. Already materialised lists are not affected.
+    pub fn cancel_template(&mut self, template_id: TemplateId) {
+        let mut template = self
+            .payroll_templates
+            .get(&template_id)
+            .expect("Payroll template not found")
+            .clone();
+
+        require!(
+            template.submitter == env::predecessor_account_id(),
+            "Only the submitter can cancel the template"
+        );
+        require!(template.active, "Payroll template is already cancelled");
+
+        template.active = false;
+        self.payroll_templates.insert(template_id.clone(), template);
+
+        log!("Payroll template {} cancelled", template_id);
+    }
+
+    /// View a payroll template
+    pub fn view_template(&self, template_id: TemplateId) -> PayrollTemplate {
+        self.payroll_templates
+            .get(&template_id)
+            .expect("Payroll template not found")
+            .clone()
+    }
+
+    /// Get the history of payment lists materialised from a template, oldest first
+    pub fn get_template_instances(&self, template_id: TemplateId) -> Vec<TemplateInstance> {
+        let template = self
+            .payroll_templates
+            .get(&template_id)
+            .expect("Payroll template not found");
+
+        template
+            .instances
+            .iter()
+            .enumerate()
+            .map(|(period, list_id)| TemplateInstance {
+                list_id: list_id.clone(),
+                period: period as u64,
+                status: self
+                    .payment_lists
+                    .get(list_id)
+                    .expect("Payment list not found")
+                    .status
+                    .clone(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use crate::{ListMetadata, TokenAmount, ValidationConfig};
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    const TEMPLATE_ID: &str = "7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e7e";
+
+    fn setup(credits: u64) -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let contract = contract_with_storage(&mut context, credits);
+        (context, contract)
+    }
+
+    fn payroll() -> Vec<PaymentInput> {
+        vec![
+            PaymentInput::new(accounts(1), 1_000),
+            PaymentInput::new(accounts(2), 2_000),
+        ]
+    }
+
+    #[test]
+    fn test_materialize_template_periods() {
+        let (mut context, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Weekly,
+                start_at: None,
+                ends_at: None,
+            },
+            None,
+            None,
+        );
+        // Template storage consumes credits for its 2 payments
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 8);
+
+        // First period starts immediately
+        let first = contract.materialize_template(TEMPLATE_ID.to_string());
+        let template = contract.view_template(TEMPLATE_ID.to_string());
+        assert_eq!(first, instance_list_id(TEMPLATE_ID, 0, &template));
+
+        let list = contract.view_list(first.clone());
+        assert_eq!(list.payments.len(), 2);
+        assert_eq!(list.submitter, accounts(0));
+        assert_eq!(list.template_id.as_deref(), Some(TEMPLATE_ID));
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 6);
+
+        // One week later the second instance can be materialised by anyone
+        context.predecessor_account_id(accounts(3));
+        context.block_timestamp(Cadence::Weekly.period_ns());
+        testing_env!(context.build());
+        let second = contract.materialize_template(TEMPLATE_ID.to_string());
+
+        let instances = contract.get_template_instances(TEMPLATE_ID.to_string());
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].list_id, first);
+        assert_eq!(instances[1].list_id, second);
+        assert_eq!(instances[1].period, 1);
+        assert!(matches!(instances[1].status, ListStatus::Pending));
+    }
+
+    #[test]
+    #[should_panic(expected = "Next period starts at")]
+    fn test_materialize_template_before_period() {
+        let (_, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Monthly,
+                start_at: None,
+                ends_at: None,
+            },
+            None,
+            None,
+        );
+        contract.materialize_template(TEMPLATE_ID.to_string());
+        // Second period has not started yet
+        contract.materialize_template(TEMPLATE_ID.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Payroll template has ended")]
+    fn test_materialize_template_after_end() {
+        let (mut context, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Weekly,
+                start_at: Some(0),
+                ends_at: Some(Cadence::Weekly.period_ns() - 1),
+            },
+            None,
+            None,
+        );
+        contract.materialize_template(TEMPLATE_ID.to_string());
+
+        context.block_timestamp(Cadence::Weekly.period_ns());
+        testing_env!(context.build());
+        contract.materialize_template(TEMPLATE_ID.to_string());
+    }
+
+    #[test]
+    fn test_materialize_template_skips_missed_periods() {
+        let (mut context, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Weekly,
+                start_at: Some(0),
+                ends_at: None,
+            },
+            None,
+            None,
+        );
+
+        // Three and a half weeks later only the current period is materialised
+        let week = Cadence::Weekly.period_ns();
+        context.block_timestamp(3 * week + week / 2);
+        testing_env!(context.build());
+        contract.materialize_template(TEMPLATE_ID.to_string());
+        assert_eq!(
+            contract.view_template(TEMPLATE_ID.to_string()).next_run_at,
+            4 * week
+        );
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 6);
+    }
+
+    #[test]
+    #[should_panic(expected = "Blocked recipients: payment 1")]
+    fn test_materialize_template_screens_recipients() {
+        let (_, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Weekly,
+                start_at: None,
+                ends_at: None,
+            },
+            None,
+            None,
+        );
+        contract.add_to_denylist(vec![accounts(2)]);
+        contract.materialize_template(TEMPLATE_ID.to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "Payroll template is cancelled")]
+    fn test_cancelled_template_stops_instances() {
+        let (_, mut contract) = setup(10);
+
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            TemplateSchedule {
+                cadence: Cadence::Weekly,
+                start_at: None,
+                ends_at: None,
+            },
+            None,
+            None,
+        );
+        contract.cancel_template(TEMPLATE_ID.to_string());
+        contract.materialize_template(TEMPLATE_ID.to_string());
+    }
+
+    fn weekly() -> TemplateSchedule {
+        TemplateSchedule {
+            cadence: Cadence::Weekly,
+            start_at: None,
+            ends_at: None,
+        }
+    }
+
+    #[test]
+    fn test_instance_list_id_commits_to_content() {
+        let (_, mut contract) = setup(10);
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            weekly(),
+            None,
+            None,
+        );
+        let template = contract.view_template(TEMPLATE_ID.to_string());
+        let list_id = instance_list_id(TEMPLATE_ID, 0, &template);
+        assert_ne!(list_id, instance_list_id(TEMPLATE_ID, 1, &template));
+
+        // Payment order does not matter, amounts do
+        let mut reordered = template.clone();
+        reordered.payments.reverse();
+        assert_eq!(list_id, instance_list_id(TEMPLATE_ID, 0, &reordered));
+        let mut changed = template.clone();
+        changed.payments[0].amount = U128(1_001);
+        assert_ne!(list_id, instance_list_id(TEMPLATE_ID, 0, &changed));
+    }
+
+    #[test]
+    fn test_template_options_apply_to_instances() {
+        let (_, mut contract) = setup(10);
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            weekly(),
+            None,
+            Some(ListOptions {
+                metadata: Some(ListMetadata {
+                    title: Some("Payroll".to_string()),
+                    ..Default::default()
+                }),
+                keeper_fee: Some(U128(5)),
+                ..Default::default()
+            }),
+        );
+        // Two payments plus the metadata
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 7);
+
+        let list_id = contract.materialize_template(TEMPLATE_ID.to_string());
+        let list = contract.view_list(list_id);
+        assert_eq!(list.metadata.unwrap().title.as_deref(), Some("Payroll"));
+        assert_eq!(list.keeper_rewards.unwrap().fee_per_payment, U128(5));
+        assert_eq!(contract.view_storage_credits(accounts(0)).as_yoctonear(), 4);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Invalid payments: payment 0: amount 1000 is below the minimum of 1500 for native"
+    )]
+    fn test_materialize_template_applies_current_validation_config() {
+        let (_, mut contract) = setup(10);
+        contract.create_template(
+            TEMPLATE_ID.to_string(),
+            TokenId::Native,
+            payroll(),
+            weekly(),
+            None,
+            None,
+        );
+        contract.set_validation_config(ValidationConfig {
+            min_amounts: vec![TokenAmount {
+                token_id: TokenId::Native,
+                amount: U128(1_500),
+            }],
+            ..Default::default()
+        });
+        contract.materialize_template(TEMPLATE_ID.to_string());
+    }
+}
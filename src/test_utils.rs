@@ -0,0 +1,52 @@
+// Fixtures shared by the unit tests of the modules
+use near_sdk::json_types::U128;
+use near_sdk::test_utils::VMContextBuilder;
+use near_sdk::{testing_env, AccountId, NearToken};
+
+use crate::{BulkPaymentContract, PaymentInput};
+
+impl PaymentInput {
+    /// Payment of `amount` to `recipient` in the list's token, without memo or reference
+    pub(crate) fn new(recipient: AccountId, amount: u128) -> Self {
+        Self {
+            recipient,
+            amount: U128(amount),
+            memo: None,
+            reference: None,
+            nft_token_id: None,
+            destination: None,
+            token_id: None,
+        }
+    }
+}
+
+/// New contract with `credits` storage credits bought by the predecessor of `context`,
+/// whose environment is left without attached deposit
+pub(crate) fn contract_with_storage(
+    context: &mut VMContextBuilder,
+    credits: u64,
+) -> BulkPaymentContract {
+    context.attached_deposit(BulkPaymentContract::default().calculate_storage_cost(credits));
+    testing_env!(context.build());
+    let mut contract = BulkPaymentContract::default();
+    contract.buy_storage(credits, None);
+
+    context.attached_deposit(NearToken::from_yoctonear(0));
+    testing_env!(context.build());
+    contract
+}
+
+/// Fund a native list with `amount` yoctoNEAR through `approve_list`
+pub(crate) fn approve_list(
+    context: &mut VMContextBuilder,
+    contract: &mut BulkPaymentContract,
+    list_id: &str,
+    amount: u128,
+) {
+    context.attached_deposit(NearToken::from_yoctonear(amount));
+    testing_env!(context.build());
+    contract.approve_list(list_id.to_string());
+
+    context.attached_deposit(NearToken::from_yoctonear(0));
+    testing_env!(context.build());
+}
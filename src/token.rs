@@ -0,0 +1,143 @@
+// Typed token identifiers
+//
+// Lists store the token they pay out as a `TokenId` instead of a free-form string, so the
+// transfer path is decided once when the list is submitted. In JSON the token is still a
+// plain string ("native", "<token contract>", "nep141:<token contract>" or
+// "nft:<nft contract>"); a string that matches none of these is rejected when the
+// arguments are deserialised.
+use near_sdk::{near, AccountId};
+use std::fmt;
+use std::str::FromStr;
+
+/// Account of the NEAR Intents contract holding "nep141:" tokens
+pub const INTENTS_CONTRACT: &str = "intents.near";
+
+/// Token a payment list is paid out in
+#[near(serializers = [borsh])]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TokenId {
+    /// Native NEAR ("native", "near" or "NEAR")
+    Native,
+    /// NEP-141 token paid with `ft_transfer` on its contract
+    Nep141(AccountId),
+    /// NEP-141 token held in NEAR Intents ("nep141:<token>"), paid with `ft_withdraw`
+    Intents {
+        contract: AccountId,
+        token: AccountId,
+    },
+    /// NEP-171 NFT contract ("nft:<contract>"), whose tokens are listed per payment
+    Nft(AccountId),
+}
+
+impl TokenId {
+    pub fn is_native(&self) -> bool {
+        matches!(self, TokenId::Native)
+    }
+
+    /// Token contract of a plain NEP-141 token, which recipients register with
+    pub fn ft_contract(&self) -> Option<&AccountId> {
+        match self {
+            TokenId::Nep141(contract) => Some(contract),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for TokenId {
+    type Err = String;
+
+    fn from_str(token_id: &str) -> Result<Self, Self::Err> {
+        if matches!(token_id, "native" | "near" | "NEAR") {
+            return Ok(TokenId::Native);
+        }
+        let parse_account = |account_id: &str| {
+            account_id
+                .parse::<AccountId>()
+                .map_err(|_| format!("Invalid token_id '{}'", token_id))
+        };
+        if let Some(contract) = token_id.strip_prefix("nft:") {
+            return parse_account(contract).map(TokenId::Nft);
+        }
+        match token_id.strip_prefix("nep141:") {
+            Some(token) => Ok(TokenId::Intents {
+                contract: INTENTS_CONTRACT.parse().unwrap(),
+                token: parse_account(token)?,
+            }),
+            None => parse_account(token_id).map(TokenId::Nep141),
+        }
+    }
+}
+
+impl fmt::Display for TokenId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenId::Native => f.write_str("native"),
+            TokenId::Nep141(contract) => f.write_str(contract.as_str()),
+            TokenId::Intents { token, .. } => write!(f, "nep141:{}", token),
+            TokenId::Nft(contract) => write!(f, "nft:{}", contract),
+        }
+    }
+}
+
+impl near_sdk::serde::Serialize for TokenId {
+    fn serialize<S: near_sdk::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> near_sdk::serde::Deserialize<'de> for TokenId {
+    fn deserialize<D: near_sdk::serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        let token_id = String::deserialize(deserializer)?;
+        token_id.parse().map_err(near_sdk::serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::serde_json;
+
+    #[test]
+    fn test_parse_token_ids() {
+        assert_eq!("near".parse::<TokenId>().unwrap(), TokenId::Native);
+        assert_eq!(
+            "wrap.near".parse::<TokenId>().unwrap(),
+            TokenId::Nep141("wrap.near".parse().unwrap())
+        );
+
+        let btc: TokenId = "nep141:btc.omft.near".parse().unwrap();
+        assert_eq!(
+            btc,
+            TokenId::Intents {
+                contract: INTENTS_CONTRACT.parse().unwrap(),
+                token: "btc.omft.near".parse().unwrap(),
+            }
+        );
+
+        assert!("Not A Token".parse::<TokenId>().is_err());
+        assert!("nep141:".parse::<TokenId>().is_err());
+    }
+
+    #[test]
+    fn test_json_is_plain_string() {
+        for token_id in [
+            "native",
+            "wrap.near",
+            "nep141:btc.omft.near",
+            "nft:badges.near",
+        ] {
+            let json = format!("\"{}\"", token_id);
+            let parsed: TokenId = serde_json::from_str(&json).unwrap();
+            assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        }
+        assert_eq!(
+            serde_json::to_string(&"NEAR".parse::<TokenId>().unwrap()).unwrap(),
+            "\"native\""
+        );
+
+        let err = serde_json::from_str::<TokenId>("\"bad token\"").unwrap_err();
+        assert!(err.to_string().contains("Invalid token_id 'bad token'"));
+    }
+}
@@ -0,0 +1,374 @@
+// Validation of submitted payments
+//
+// `submit_list`, `create_template` and `materialize_template` run every payment through the
+// same checks and panic once with all problems found, each with the position of the
+// offending payment:
+// - amounts must be non-zero and at least the configured minimum (dust threshold) of their
+//   token; amounts of USD-denominated lists are checked against the minimum once converted
+//   with `lock_price`
+// - the contract's own account cannot be a recipient
+// - memo and reference are limited to `MAX_PAYMENT_FIELD_LENGTH` bytes
+// - a list holds at most `max_payments` records
+// The limits are set by the contract account with `set_validation_config`. The
+// `validate_payments` view runs the same checks without storing anything, reading the
+// payments one by one so that one it cannot read, e.g. with an unknown `token_id`, is
+// reported at its position as well.
+use near_sdk::{env, near, require, serde_json};
+
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, Denomination, PaymentInput, PaymentRecord,
+    TokenAmount, TokenId, MAX_PAYMENT_FIELD_LENGTH,
+};
+
+/// Default maximum number of payments per list; reading larger lists in `payout_batch`
+/// takes most of the gas of a call
+const DEFAULT_MAX_PAYMENTS: u32 = 500;
+
+/// Limits applied to submitted payments
+#[near(serializers = [json, borsh])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationConfig {
+    /// Maximum number of payments in a list
+    pub max_payments: u32,
+    /// Smallest amount a payment of each listed token may have; other tokens only
+    /// reject zero amounts
+    pub min_amounts: Vec<TokenAmount>,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_payments: DEFAULT_MAX_PAYMENTS,
+            min_amounts: Vec::new(),
+        }
+    }
+}
+
+/// Problem with a payment list, `index` being the position of the payment if it
+/// concerns a single one
+#[near(serializers = [json])]
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValidationIssue {
+    pub index: Option<u32>,
+    pub message: String,
+}
+
+impl ValidationIssue {
+    fn payment(index: usize, message: String) -> Self {
+        Self {
+            index: Some(index as u32),
+            message,
+        }
+    }
+}
+
+/// Panic with every issue found, if there are any
+fn require_no_issues(issues: &[ValidationIssue]) {
+    require!(
+        issues.is_empty(),
+        format!(
+            "Invalid payments: {}",
+            issues
+                .iter()
+                .map(|issue| match issue.index {
+                    Some(index) => format!("payment {}: {}", index, issue.message),
+                    None => issue.message.clone(),
+                })
+                .collect::<Vec<_>>()
+                .join("; ")
+        )
+    );
+}
+
+impl BulkPaymentContract {
+    /// Problem with `amount` of `token_id`: zero or below the token's minimum
+    fn amount_issue(&self, token_id: &TokenId, amount: u128) -> Option<String> {
+        let min_amount = self
+            .validation_config
+            .min_amounts
+            .iter()
+            .find(|min| min.token_id == *token_id)
+            .map_or(0, |min| min.amount.0);
+        if amount == 0 {
+            Some("amount is zero".to_string())
+        } else if amount < min_amount {
+            Some(format!(
+                "amount {} is below the minimum of {} for {}",
+                amount, min_amount, token_id
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Problem with a list of `len` payments: more than `max_payments`
+    fn count_issue(&self, len: usize) -> Option<ValidationIssue> {
+        let max_payments = self.validation_config.max_payments;
+        (len > max_payments as usize).then(|| ValidationIssue {
+            index: None,
+            message: format!(
+                "List has {} payments, at most {} are allowed",
+                len, max_payments
+            ),
+        })
+    }
+
+    /// Add the problems with `payment`, at position `index` of a list of `token_id`
+    fn push_payment_issues(
+        &self,
+        issues: &mut Vec<ValidationIssue>,
+        index: usize,
+        token_id: &TokenId,
+        payment: &PaymentInput,
+        denomination: Denomination,
+    ) {
+        let token_id = payment.token_id.as_ref().unwrap_or(token_id);
+        let amount_issue = match denomination {
+            Denomination::Token => self.amount_issue(token_id, payment.amount.0),
+            Denomination::UsdCents => (payment.amount.0 == 0).then(|| "amount is zero".to_string()),
+        };
+        if let Some(message) = amount_issue {
+            issues.push(ValidationIssue::payment(index, message));
+        }
+
+        if payment.recipient == env::current_account_id() {
+            issues.push(ValidationIssue::payment(
+                index,
+                "recipient is the bulk payment contract itself".to_string(),
+            ));
+        }
+
+        for (field, value) in [("memo", &payment.memo), ("reference", &payment.reference)] {
+            if value
+                .as_ref()
+                .is_some_and(|value| value.len() > MAX_PAYMENT_FIELD_LENGTH)
+            {
+                issues.push(ValidationIssue::payment(
+                    index,
+                    format!("{} exceeds {} bytes", field, MAX_PAYMENT_FIELD_LENGTH),
+                ));
+            }
+        }
+    }
+
+    /// All problems with `payments` of a list of `token_id`; US cent amounts are only
+    /// checked for zero, as the token minimums apply once they are converted
+    pub(crate) fn payment_issues(
+        &self,
+        token_id: &TokenId,
+        payments: &[PaymentInput],
+        denomination: Denomination,
+    ) -> Vec<ValidationIssue> {
+        let mut issues: Vec<ValidationIssue> =
+            self.count_issue(payments.len()).into_iter().collect();
+        for (index, payment) in payments.iter().enumerate() {
+            self.push_payment_issues(&mut issues, index, token_id, payment, denomination);
+        }
+        issues
+    }
+
+    /// Panic with every problem with `payments`, if there are any
+    pub(crate) fn require_valid_payments(
+        &self,
+        token_id: &TokenId,
+        payments: &[PaymentInput],
+        denomination: Denomination,
+    ) {
+        require_no_issues(&self.payment_issues(token_id, payments, denomination));
+    }
+
+    /// Panic if the amount of a stored record is zero or below its token's minimum, e.g.
+    /// after converting US cents to `token_id`
+    pub(crate) fn require_valid_amounts(&self, token_id: &TokenId, records: &[PaymentRecord]) {
+        let issues: Vec<ValidationIssue> = records
+            .iter()
+            .enumerate()
+            .filter_map(|(index, record)| {
+                let token_id = record.token_id.as_ref().unwrap_or(token_id);
+                self.amount_issue(token_id, record.amount.0)
+                    .map(|message| ValidationIssue::payment(index, message))
+            })
+            .collect();
+        require_no_issues(&issues);
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Check payments against the rules of `submit_list` without storing anything
+    ///
+    /// `denomination` is that of the list options, token amounts by default. Payments are
+    /// read one by one, so one that cannot be read (e.g. with an unknown `token_id`) is
+    /// reported at its position instead of failing the call.
+    ///
+    /// # Returns
+    /// Every problem found, empty if the payments would be accepted
+    pub fn validate_payments(
+        &self,
+        token_id: String,
+        payments: Vec<serde_json::Value>,
+        denomination: Option<Denomination>,
+    ) -> Vec<ValidationIssue> {
+        let token_id = match token_id.parse::<TokenId>() {
+            Ok(token_id) => token_id,
+            Err(message) => {
+                return vec![ValidationIssue {
+                    index: None,
+                    message,
+                }]
+            }
+        };
+        let denomination = denomination.unwrap_or_default();
+        let mut issues: Vec<ValidationIssue> =
+            self.count_issue(payments.len()).into_iter().collect();
+        for (index, payment) in payments.into_iter().enumerate() {
+            match serde_json::from_value::<PaymentInput>(payment) {
+                Ok(payment) => {
+                    self.push_payment_issues(&mut issues, index, &token_id, &payment, denomination)
+                }
+                Err(err) => issues.push(ValidationIssue::payment(
+                    index,
+                    format!("cannot be read: {}", err),
+                )),
+            }
+        }
+        issues
+    }
+
+    /// Set the limits applied to submitted payments (contract account only)
+    #[private]
+    pub fn set_validation_config(&mut self, config: ValidationConfig) {
+        require!(config.max_payments > 0, "max_payments must be positive");
+        self.validation_config = config;
+    }
+
+    /// Limits applied to submitted payments
+    pub fn view_validation_config(&self) -> ValidationConfig {
+        self.validation_config.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::contract_with_storage;
+    use near_sdk::json_types::U128;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+
+    const LIST_ID: &str = "e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6e6";
+
+    fn as_json(payments: Vec<PaymentInput>) -> Vec<serde_json::Value> {
+        payments
+            .iter()
+            .map(|payment| serde_json::to_value(payment).unwrap())
+            .collect()
+    }
+
+    fn usdc() -> TokenId {
+        TokenId::Nep141("usdc.near".parse().unwrap())
+    }
+
+    /// Contract deployed at accounts(0) with a 10 USDC dust threshold and at most 3 payments,
+    /// and 2 storage credits of accounts(0)
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        contract.set_validation_config(ValidationConfig {
+            max_payments: 3,
+            min_amounts: vec![TokenAmount {
+                token_id: usdc(),
+                amount: U128(10),
+            }],
+        });
+        (context, contract)
+    }
+
+    #[test]
+    fn test_validate_payments_itemises_issues() {
+        let (_, contract) = setup();
+        let issues = contract.validate_payments(
+            "usdc.near".to_string(),
+            as_json(vec![
+                PaymentInput::new(accounts(1), 100),
+                PaymentInput::new(accounts(2), 0),
+                PaymentInput::new(accounts(0), 100),
+                PaymentInput::new(accounts(3), 5),
+            ]),
+            None,
+        );
+        assert_eq!(
+            issues
+                .iter()
+                .map(|issue| (issue.index, issue.message.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, "List has 4 payments, at most 3 are allowed"),
+                (Some(1), "amount is zero"),
+                (Some(2), "recipient is the bulk payment contract itself"),
+                (Some(3), "amount 5 is below the minimum of 10 for usdc.near"),
+            ]
+        );
+
+        // The dust threshold only applies to its token
+        assert!(contract
+            .validate_payments(
+                "native".to_string(),
+                as_json(vec![PaymentInput::new(accounts(3), 5)]),
+                None
+            )
+            .is_empty());
+        // US cents are checked against the minimum once converted
+        assert!(contract
+            .validate_payments(
+                "usdc.near".to_string(),
+                as_json(vec![PaymentInput::new(accounts(3), 5)]),
+                Some(Denomination::UsdCents)
+            )
+            .is_empty());
+        assert_eq!(
+            contract.validate_payments("not a token".to_string(), vec![], None)[0].index,
+            None
+        );
+    }
+
+    #[test]
+    fn test_validate_payments_reports_unreadable_payments() {
+        let (_, contract) = setup();
+        let mut payments = as_json(vec![
+            PaymentInput::new(accounts(1), 0),
+            PaymentInput::new(accounts(2), 100),
+            PaymentInput::new(accounts(3), 100),
+        ]);
+        payments[1]["token_id"] = "not a token".into();
+
+        let issues = contract.validate_payments("native".to_string(), payments, None);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(
+            issues[0],
+            ValidationIssue::payment(0, "amount is zero".to_string())
+        );
+        assert_eq!(issues[1].index, Some(1));
+        assert!(issues[1].message.starts_with("cannot be read: "));
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "Invalid payments: payment 0: amount is zero; payment 1: recipient is the bulk payment contract itself"
+    )]
+    fn test_submit_list_rejects_all_issues_at_once() {
+        let (_, mut contract) = setup();
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            vec![
+                PaymentInput::new(accounts(1), 0),
+                PaymentInput::new(accounts(0), 100),
+            ],
+            None,
+            None,
+        );
+    }
+}
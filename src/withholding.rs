@@ -0,0 +1,324 @@
+// Tax withholding
+//
+// Payroll lists can withhold a share of every payment for a tax escrow account. A list
+// submitted with `withholding: { rate_bps, account }` splits each record when it is
+// stored: `withheld_amount` is floor(amount * rate_bps / 10,000) and `net_amount` the
+// rest, `amount` remaining the gross. `payout_batch` sends recipients their net amount
+// and adds the withheld part to the list's running total. Once no payment is pending, the
+// total is paid to the withholding account in a single transfer; if that transfer fails
+// or the last batch has no gas left for it, anyone can retry with `remit_withholding`.
+// Blocked payments withhold nothing, as their gross amount is refunded to the submitter.
+use near_sdk::json_types::U128;
+use near_sdk::serde_json::json;
+use near_sdk::{env, log, near, require, AccountId, Gas, Promise};
+
+use crate::events;
+use crate::executor::{transfer_delivered, PayoutExecutor};
+use crate::gas::GAS_RESERVE;
+use crate::split::{bps_of, TOTAL_BASIS_POINTS};
+use crate::{
+    BulkPaymentContract, BulkPaymentContractExt, ListId, ListStatus, PaymentList, PaymentRecord,
+    PaymentStatus,
+};
+
+const GAS_FOR_RESOLVE_REMITTANCE: Gas = Gas::from_tgas(10);
+
+/// Memo of the transfer to the withholding account, where the token supports one
+const REMITTANCE_MEMO: &str = "Tax withholding";
+
+/// Rate and recipient of the tax withheld from a list, as passed in `ListOptions`
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct WithholdingRule {
+    /// Share of each payment withheld, in basis points (1/100 of a percent)
+    pub rate_bps: u32,
+    /// Tax escrow account the withheld total is paid to
+    pub account: AccountId,
+}
+
+/// Tax withheld from the payments of a list
+#[near(serializers = [json, borsh])]
+#[derive(Clone)]
+pub struct Withholding {
+    pub rate_bps: u32,
+    pub account: AccountId,
+    /// Withheld from the payments paid so far
+    pub withheld: U128,
+    /// Block height at which the withheld total was sent to `account`
+    pub remitted_at: Option<u64>,
+}
+
+impl WithholdingRule {
+    /// Panic unless the rate leaves recipients a positive net amount
+    pub(crate) fn validate(&self) {
+        require!(
+            self.rate_bps > 0 && self.rate_bps < TOTAL_BASIS_POINTS,
+            format!(
+                "Withholding rate must be between 1 and {} basis points",
+                TOTAL_BASIS_POINTS - 1
+            )
+        );
+        require!(
+            self.account != env::current_account_id(),
+            "Withholding account cannot be the bulk payment contract itself"
+        );
+    }
+
+    /// Withholding state of a new list
+    pub(crate) fn into_withholding(self) -> Withholding {
+        Withholding {
+            rate_bps: self.rate_bps,
+            account: self.account,
+            withheld: U128(0),
+            remitted_at: None,
+        }
+    }
+}
+
+impl Withholding {
+    /// Split the gross amount of each record into its net and withheld parts
+    pub(crate) fn apply(&self, records: &mut [PaymentRecord]) {
+        for record in records {
+            let withheld = bps_of(record.amount.0, self.rate_bps);
+            record.withheld_amount = Some(U128(withheld));
+            record.net_amount = Some(U128(record.amount.0 - withheld));
+        }
+    }
+}
+
+/// Build the promise paying a record's recipient, its net amount if tax is withheld
+pub(crate) fn pay_net(executor: &dyn PayoutExecutor, payment: &PaymentRecord) -> Promise {
+    match payment.net_amount {
+        Some(net_amount) => executor.pay_record(&PaymentRecord {
+            amount: net_amount,
+            ..payment.clone()
+        }),
+        None => executor.pay_record(payment),
+    }
+}
+
+impl PaymentList {
+    /// Add tax withheld from payments just paid to the list's total
+    pub(crate) fn add_withheld(&mut self, withheld: u128) {
+        if let Some(withholding) = self.withholding.as_mut() {
+            withholding.withheld = U128(withholding.withheld.0 + withheld);
+        }
+    }
+
+    /// Take back tax added for a payment whose transfer failed
+    pub(crate) fn sub_withheld(&mut self, withheld: u128) {
+        if let Some(withholding) = self.withholding.as_mut() {
+            withholding.withheld = U128(withholding.withheld.0 - withheld);
+        }
+    }
+
+    /// Whether the withheld total is due: nothing pending or being registered, not yet
+    /// remitted and not zero
+    fn withholding_due(&self) -> bool {
+        self.withholding.as_ref().is_some_and(|withholding| {
+            withholding.remitted_at.is_none() && withholding.withheld.0 > 0
+        }) && matches!(self.status, ListStatus::Approved)
+            && self
+                .registration
+                .as_ref()
+                .is_none_or(|registration| registration.in_flight.is_empty())
+            && !self
+                .payments
+                .iter()
+                .any(|p| matches!(p.status, PaymentStatus::Pending))
+    }
+}
+
+impl BulkPaymentContract {
+    /// Send the withheld total of a completed list to its withholding account, if due and
+    /// the remaining gas covers the transfer. Returns whether it was sent.
+    pub(crate) fn try_remit_withholding(&self, list_id: &ListId, list: &mut PaymentList) -> bool {
+        if !list.withholding_due() {
+            return false;
+        }
+        let executor = list.executor();
+        let gas_needed = executor
+            .gas_per_payment()
+            .saturating_add(GAS_FOR_RESOLVE_REMITTANCE)
+            .saturating_add(GAS_RESERVE);
+        if env::prepaid_gas().saturating_sub(env::used_gas()) < gas_needed {
+            log!(
+                "Not enough gas to remit the withholding of list {}, call remit_withholding",
+                list_id
+            );
+            return false;
+        }
+
+        let withholding = list.withholding.as_mut().expect("List has no withholding");
+        withholding.remitted_at = Some(env::block_height());
+        let (account, amount) = (withholding.account.clone(), withholding.withheld);
+        executor
+            .transfer(&account, amount.0, Some(REMITTANCE_MEMO))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_REMITTANCE)
+                    .on_withholding_remitted(list_id.clone()),
+            )
+            .detach();
+        events::emit(
+            "withholding_remit",
+            json!({ "list_id": list_id, "account_id": account, "amount": amount }),
+        );
+        true
+    }
+}
+
+#[near]
+impl BulkPaymentContract {
+    /// Send the tax withheld from a completed list to its withholding account (public
+    /// function, anyone can call)
+    ///
+    /// `payout_batch` does this after the last payment; this retries when that transfer
+    /// failed or the batch ran out of gas.
+    pub fn remit_withholding(&mut self, list_id: ListId) {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        require!(
+            list.withholding_due(),
+            "No withheld tax due: the list has pending payments or nothing left to remit"
+        );
+        require!(
+            self.try_remit_withholding(&list_id, &mut list),
+            "Insufficient gas to remit the withholding"
+        );
+        self.payment_lists.insert(list_id, list);
+    }
+
+    /// Callback after the transfer to the withholding account; a failed transfer can be
+    /// retried with `remit_withholding`
+    #[private]
+    pub fn on_withholding_remitted(&mut self, list_id: ListId) -> bool {
+        let mut list = self
+            .payment_lists
+            .get(&list_id)
+            .expect("Payment list not found")
+            .clone();
+        let withholding = list.withholding.as_ref().expect("List has no withholding");
+        if transfer_delivered(&list, withholding.withheld.0) {
+            return true;
+        }
+
+        if let Some(withholding) = list.withholding.as_mut() {
+            withholding.remitted_at = None;
+        }
+        self.payment_lists.insert(list_id.clone(), list);
+        log!("Remitting the withholding of list {} failed", list_id);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{approve_list, contract_with_storage};
+    use crate::{ListOptions, PaymentInput, TokenId};
+    use near_sdk::mock::MockAction;
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, PromiseResult, RuntimeFeesConfig};
+
+    const LIST_ID: &str = "c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4c4";
+
+    /// Approved native list paying 1000 and 333 with 25% withheld for accounts(5)
+    fn setup() -> (VMContextBuilder, BulkPaymentContract) {
+        let mut context = VMContextBuilder::new();
+        context.predecessor_account_id(accounts(0));
+        let mut contract = contract_with_storage(&mut context, 2);
+        let payments = vec![
+            PaymentInput::new(accounts(1), 1000),
+            PaymentInput::new(accounts(2), 333),
+        ];
+        contract.submit_list(
+            LIST_ID.to_string(),
+            TokenId::Native,
+            payments,
+            None,
+            Some(ListOptions {
+                withholding: Some(WithholdingRule {
+                    rate_bps: 2500,
+                    account: accounts(5),
+                }),
+                ..Default::default()
+            }),
+        );
+
+        approve_list(&mut context, &mut contract, LIST_ID, 1333);
+
+        context.prepaid_gas(Gas::from_tgas(300));
+        testing_env!(context.build());
+        (context, contract)
+    }
+
+    fn transfers() -> Vec<(AccountId, u128)> {
+        get_created_receipts()
+            .into_iter()
+            .flat_map(|receipt| {
+                let receiver = receipt.receiver_id.clone();
+                receipt
+                    .actions
+                    .into_iter()
+                    .filter_map(move |action| match action {
+                        MockAction::Transfer { deposit, .. } => {
+                            Some((receiver.clone(), deposit.as_yoctonear()))
+                        }
+                        _ => None,
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_payout_sends_net_amounts_and_remits_withheld_total() {
+        let (_, mut contract) = setup();
+        let list = contract.view_list(LIST_ID.to_string());
+        assert_eq!(list.payments[1].withheld_amount, Some(U128(83)));
+        assert_eq!(list.payments[1].net_amount, Some(U128(250)));
+
+        assert_eq!(contract.payout_batch(LIST_ID.to_string(), None), 0);
+        assert_eq!(
+            transfers(),
+            vec![(accounts(1), 750), (accounts(2), 250), (accounts(5), 333)]
+        );
+
+        let withholding = contract.view_list(LIST_ID.to_string()).withholding.unwrap();
+        assert_eq!(withholding.withheld, U128(333));
+        assert!(withholding.remitted_at.is_some());
+
+        let transactions = contract.get_payment_transactions(LIST_ID.to_string());
+        assert_eq!(transactions[0].amount, U128(1000));
+        assert_eq!(transactions[0].net_amount, Some(U128(750)));
+        assert_eq!(transactions[0].withheld_amount, Some(U128(250)));
+    }
+
+    #[test]
+    fn test_failed_remittance_can_be_retried() {
+        let (context, mut contract) = setup();
+        contract.payout_batch(LIST_ID.to_string(), None);
+
+        testing_env!(
+            context.build(),
+            near_sdk::test_vm_config(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert!(!contract.on_withholding_remitted(LIST_ID.to_string()));
+        assert!(contract
+            .view_list(LIST_ID.to_string())
+            .withholding
+            .unwrap()
+            .remitted_at
+            .is_none());
+
+        testing_env!(context.build());
+        contract.remit_withholding(LIST_ID.to_string());
+        assert_eq!(transfers(), vec![(accounts(5), 333)]);
+    }
+}